@@ -1,3 +1,7 @@
 pub mod utils;
 pub mod emulator_listings;
-pub mod game_listings;
\ No newline at end of file
+pub mod game_listings;
+pub mod roms;
+pub mod media;
+pub mod metadata;
+pub mod session;
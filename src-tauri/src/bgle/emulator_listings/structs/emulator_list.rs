@@ -12,7 +12,7 @@ pub fn new() -> EmulatorList {
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmulatorList {
     pub emulators: Vec<EmulatorMeta>,
 }
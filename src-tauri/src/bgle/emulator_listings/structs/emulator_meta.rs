@@ -3,9 +3,11 @@
  */
 
 use super::super::utils::{directory_name, directory_path_buf, get_rom_extensions};
+use crate::bgle::game_listings::gamelist_xml;
+use crate::bgle::roms::ScanOptions;
+use crate::bgle::roms::disc_sets::rom_sets;
 use std::fs::{read_to_string};
 use std::path::PathBuf;
-use regex::Regex;
 use serde::{Serialize, Deserialize};
 use serde::ser::SerializeStruct;
 use super::super::utils::TEST_DIRECTORY;
@@ -42,60 +44,38 @@ pub fn new(directory: &str) -> EmulatorMeta {
 }
 
 
-// Counts the game files within the directory.
+// Counts the games within the directory and its subdirectories, a multi-file game counting once.
 fn count_games_in_the_directory(dir_path: &PathBuf, extensions: &Vec<String>) -> u32 {
     
-    let mut game_count = 0;
-    
-    for entry in dir_path.read_dir().unwrap() {
-        
-        let entry = entry.unwrap();
-        let file_path = entry.path();
-
-        if let Some(extension) = file_path.extension() {   
-            
-            let file_extension = extension.to_str().unwrap();
-            
-            if extensions.contains(&file_extension.to_string()) {
-                game_count += 1;
-            }
-        }
+    match rom_sets(dir_path, extensions, &ScanOptions::default()) {
+        Ok(sets) => sets.len() as u32,
+        Err(_) => 0,
     }
-    
-    game_count
 }
 
 // Counts the games listed with the gamefile and the fields that are filled in.
 fn gamefile_game_count_and_field(dir_path: &PathBuf) -> Option<(u32, u32)> {
-    let mut game_count = 0;
-    let mut field_count = 0;
     
     let mut file_path_buf = dir_path.clone();
     file_path_buf.push("gamelist.xml");
 
-    if let Ok(file_string) = read_to_string(&file_path_buf){
+    let file_string = read_to_string(&file_path_buf).ok()?;
+    let entries: Vec<_> = gamelist_xml::parse(&file_string).ok()?
+        .entries
+        .into_iter()
+        .filter(|entry| entry.kind == gamelist_xml::EntryKind::Game)
+        .collect();
 
-        let regex_game  = Regex::new(r"<game>").unwrap();
-        let regex_field = Regex::new(r"<\s?\w+\s?>(.*)</\s?\w+\s?>").unwrap();
+    let game_count = entries.len() as u32;
+    let field_count = entries.iter()
+        .flat_map(|entry| entry.fields.iter())
+        .filter(|field| !field.value.is_empty())
+        .count() as u32;
 
-        for _cap in regex_game.captures_iter(&file_string){
-            game_count += 1;
-        }
-
-        if game_count > 0 {
-            for _cap in regex_field.captures_iter(&file_string){
-                field_count += 1;
-            }
-        }
-
-        Some((game_count, field_count))
-    
-    } else {
-        None
-    }
+    Some((game_count, field_count))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct EmulatorMeta {
     pub name: String,
     directory : std::path::PathBuf,
@@ -1,51 +1,111 @@
 pub mod structs;
 pub mod display_game_list;
 pub mod game_form;
-
-use std::io::{Error, ErrorKind};
+pub mod gamelist_xml;
+pub mod duplicates;
+pub mod orphans;
+pub mod backups;
+pub mod conflicts;
+
+use std::fmt;
+use std::io;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::{PathBuf};
-use regex::Regex;
-use crate::bgle::utils::file_name_to_string;
+use std::path::{Path, PathBuf};
 
+use self::backups::SaveOptions;
+use self::conflicts::Conflict;
+use self::gamelist_xml::EntryKind;
 use self::structs::game_list::GameList;
 
 use super::utils::{directory_name, directory_path_buf, TEST_DIRECTORY};
+use super::roms::{self, ScanOptions};
+use super::roms::disc_sets;
+use super::roms::archives;
+use super::roms::tags;
+use super::roms::checksums::HashCache;
 use structs::game_list;
-use structs::game::Game;
 
 
+/// Errors which can occur while reading or saving a gamelist.xml file.
+#[derive(Debug)]
+pub enum Error {
+    /// The gamelist.xml file or the emulator directory could not be read.
+    Io(io::Error),
+    /// The gamelist.xml file is not well formed, position is the byte offset of the problem.
+    Malformed { position: usize, message: String },
+    /// The document root is not a <gameList> element.
+    UnexpectedRoot(String),
+    /// The document does not contain a root element.
+    MissingRoot,
+    /// The gamelist.xml file was changed by another program since the game list was loaded.
+    Conflict(Conflict),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Malformed { position, message } => write!(f, "Malformed gamelist.xml at byte {}: {}", position, message),
+            Error::UnexpectedRoot(name) => write!(f, "Expected a <gameList> root element, found <{}>", name),
+            Error::MissingRoot => write!(f, "The gamelist.xml file does not contain a <gameList> element"),
+            Error::Conflict(conflict) => write!(f, "{}", conflict),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<Conflict> for Error {
+    fn from(conflict: Conflict) -> Self {
+        Error::Conflict(conflict)
+    }
+}
+
 
 /// Creates a new GameList struct from a valid directory.
 /// 
-pub fn new(valid_dir: &str) -> GameList {
+/// Returns an error if the directory cannot be read or its gamelist.xml file is malformed.
+pub fn new(valid_dir: &str) -> Result<GameList, Error> {
     
-    match directory_path_buf(&valid_dir.to_string()) {
-        Ok(path_buf) => {
-            let mut game_list = game_list::new();
-            game_list.emulator = directory_name(&path_buf);  
-            let mut directory = path_buf.clone();
-            game_list.directory = directory;
-              
-            collate_games_to_list(&path_buf, &mut game_list);
-            return game_list;
-        }
-        Err(e) => GameList { 
-            directory: PathBuf::new(), 
-            emulator: String::from("None"), 
-            games: Vec::<Game>::new(), 
-        },
-    }
- 
+    let path_buf = directory_path_buf(&valid_dir.to_string())?;
+    let mut game_list = game_list::new();
+    game_list.emulator = directory_name(&path_buf);  
+    game_list.directory = path_buf.clone();
+      
+    collate_games_to_list(&path_buf, &mut game_list)?;
+    Ok(game_list)
 }
 
 // Use both the game list and the directory to collate a list of games and return a GameList struct
-fn collate_games_to_list(emulator_dir: &PathBuf, game_list: &mut game_list::GameList) {
+fn collate_games_to_list(emulator_dir: &Path, game_list: &mut game_list::GameList) -> Result<(), Error> {
    
-    let directory = emulator_dir.clone();
-    games_from_gamelist(&directory, game_list);
+    let directory = emulator_dir.to_path_buf();
+    games_from_gamelist(&directory, game_list)?;
     games_found_in_dir(&directory, game_list);
+    inspect_archives(&directory, game_list);
+    // media files are not checked here, reading every file's header is left to the media report
+    orphans::mark_orphans(&directory, game_list);
+    Ok(())
+}
+
+// Lists the ROMs inside each archived game, so the inner file can be shown and hashed.
+fn inspect_archives(emulator_dir: &Path, game_list: &mut game_list::GameList) {
+    let extensions = super::utils::get_rom_extensions(&emulator_dir.to_path_buf()).unwrap_or_default();
+
+    for game in game_list.games.iter_mut() {
+        let rom_path = match game.field_value("path") {
+            Some(path) if !path.is_empty() => roms::rom_file_path(emulator_dir, path),
+            _ => continue,
+        };
+        if archives::is_archive(&rom_path) && rom_path.is_file() {
+            game.set_archive(archives::inspect(&rom_path, &extensions));
+        }
+    }
 }
 
 // Collects the games found in a directory and checks if they are already in the gamelist
@@ -53,22 +113,28 @@ fn collate_games_to_list(emulator_dir: &PathBuf, game_list: &mut game_list::Game
 // for any games not found in the gamelist, add them to the gamelist
 fn games_found_in_dir(game_list_dir: &PathBuf, game_list: &mut game_list::GameList) {
 
-    let mut directory = game_list_dir.clone();
+    for game_path in game_paths_list(game_list_dir).unwrap_or(vec![]) {
 
-    for game_path in game_paths_list(&mut directory).unwrap_or(vec![]) {
-        let game_path = PathBuf::from(game_path);
-        let game_path = file_name_to_string(&game_path);
+        // The path of the game relative to the emulator directory, as it is written in gamelist.xml
+        let path = match roms::gamelist_path(game_list_dir, Path::new(&game_path)) {
+            Some(path) => path,
+            None => continue,
+        };
 
         // Search the game list for a game path entry if no entry is found, it is assumed that the game 
         // is not in the game list and so should be added.
-        let mut path = String::from("./");
-        path.push_str(&game_path);
-        
         if let None = game_list.search(game_list::SearchGames::Path, &path){
         
             game_list.add_game_entry();
             let game = game_list.games.last_mut().unwrap(); 
-            game.change_field("path", game_path);
+            // new entries are named from the tags in their file name, eg `Game (USA) (Rev 1).sfc`
+            let name_tags = tags::parse_file_name(&path);
+            game.add_field("path", path);
+            for (field, value) in name_tags.field_values() {
+                if !value.is_empty() {
+                    game.add_field(field, value);
+                }
+            }
         }
     }
 
@@ -87,149 +153,165 @@ fn game_paths_list(valid_emulator_dir: &PathBuf) -> Option<Vec<String>> {
     None
 }
 
-// Returns a list of file valid game paths found within a directory
-fn dir_game_paths(dir_path: &PathBuf) -> Result<Vec<String>, Error> {
-    
-    let mut game_paths_list = Vec::<String>::new();
+// Returns a list of valid game paths found within a directory and its subdirectories. The files
+// of multi-file games are grouped, so only the .m3u, .cue or .gdi file of such a game is listed.
+fn dir_game_paths(dir_path: &PathBuf) -> Result<Vec<String>, io::Error> {
 
     let extensions = extensions_list(&dir_path);
     // leave this function if no valid extensions were found
     if let None = extensions {
-        return Err(Error::new(ErrorKind::NotFound, "No valid extensions found"));
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No valid extensions found"));
     }
     let extensions = extensions.unwrap();
-    
-    for dir_entry in dir_path.read_dir()? {
-        
-        let path: PathBuf = dir_entry?.path(); 
-        let extension = file_extension_from_path(&path);
-        
-        if is_valid_emulator_extension(extension, &extensions) {
-            game_paths_list.push(path.to_str().unwrap().to_string());
-        }
-    }
 
-    return Ok(game_paths_list);
+    let sets = disc_sets::rom_sets(dir_path, &extensions, &ScanOptions::default())?;
+    return Ok(sets.iter().map(|set| set.path.to_str().unwrap().to_string()).collect());
 
     // Helper functions
     fn extensions_list(path_buf: &PathBuf) -> Option<Vec<String>> {
         super::utils::get_rom_extensions(&path_buf)
     }
-
-    fn is_valid_emulator_extension(extension: Option<String>, valid_extensions: &Vec<String>) -> bool {
-        if let Some(extension) = extension {
-            if valid_extensions.contains(&extension) {
-                return true;
-            }
-        }
-        return false;
-    }
-
-    fn file_extension_from_path(file_path: &PathBuf) -> Option<String> {
-        if let Some(extension) = file_path.extension() {
-            Some(extension.to_str().unwrap().to_string())   
-        }   else {
-            None
-        }
-    }
 }
 
 
 
 
-// Extracts a games from the gamelist.xml file and adds them to the game list
-fn games_from_gamelist(gamelist_dir: &PathBuf, game_list: &mut game_list::GameList) {
+// Extracts the games and folders from the gamelist.xml file and adds them to the game list.
+// A missing gamelist.xml file is not an error, the directory simply has no listed games.
+fn games_from_gamelist(gamelist_dir: &Path, game_list: &mut game_list::GameList) -> Result<(), Error> {
 
-    // clone the gamelist_dir path buffer and append the gamelist.xml file name
-    let mut dir_buf = gamelist_dir.clone();
+    // copy the gamelist_dir path into a buffer and append the gamelist.xml file name
+    let mut dir_buf = gamelist_dir.to_path_buf();
     dir_buf.push("gamelist.xml");
 
-    // read the gamelist.xml file into a string
-    if let Ok(stringified_file) = std::fs::read_to_string(&dir_buf){
-        
-        // scrub the string, removing all new lines and tabs
-        let stringified_file = stringified_file.replace("\n", "").replace("\t", "");
-
-        // create a regex to match the contexts of the a game element 
-        if let Err(e) = strip_out_games(&stringified_file, game_list) {
-            println!("Error: {:?}", e);
+    // the file is kept as it was loaded, to tell when it is changed by another program
+    let loaded = match conflicts::read(&dir_buf)? {
+        Some(loaded) => loaded,
+        None => return Ok(()),
+    };
+
+    // each entry found in the file becomes a game or folder, with its elements applied as fields
+    for (index, entry) in gamelist_xml::parse(&loaded.contents)?.entries.into_iter().enumerate() {
+        match entry.kind {
+            EntryKind::Game => {
+                game_list.add_game_entry();
+                let game = game_list.games.last_mut().unwrap();
+                game.set_gamelist_entry(Some(index));
+                for field in entry.fields {
+                    game.add_field(&field.name, field.value);
+                }
+            }
+            EntryKind::Folder => {
+                game_list.add_folder_entry();
+                let folder = game_list.folders.last_mut().unwrap();
+                folder.set_gamelist_entry(Some(index));
+                for field in entry.fields {
+                    folder.add_field(&field.name, field.value);
+                }
+            }
         }
     }
+    game_list.loaded = Some(loaded);
+    Ok(())
 }
 
-
-fn strip_out_games(gamelist: &String, game_list: &mut game_list::GameList) -> Result<(), ErrorKind> {
- 
-    // Capture the contents of the gameList element
-    let regex = Regex::new(r"<gameList>((?:.|\n)*)</gameList>").unwrap();
-    let captured = regex.captures(gamelist.as_str());
-
-    // if the contents where captured assign it to the contents variable
-    if let Some(captured) = captured {
-        
-        let contents = captured[1].to_string();
-
-        // find the start and end indexes of the game elements found in the contents string
-        let game_start_indexes = contents.match_indices("<game>").map(|(i, _)| i).collect::<Vec<usize>>();
-        let game_end_indexes = contents.match_indices("</game>").map(|(i, _)| i).collect::<Vec<usize>>();
-        
-        // if the number of start and end indexes are not equal, the gamelist.xml file is invalid
-        if game_end_indexes.len() != game_start_indexes.len() {
-            return Err(ErrorKind::InvalidData);
+/// Computes the checksums of each game's ROM and fills in the crc32 and md5 fields.
+///
+/// Checksums are cached within the emulator directory, so only ROMs which are new or have changed
+/// since the last scan are read. The ROM inside an archive holding a single ROM is hashed rather
+/// than the archive. Games whose ROM cannot be read, such as a directory ROM, are skipped.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its directory must be the emulator directory.
+/// ### Returns
+/// * `Result<usize, io::Error>` - The number of games hashed, or an error if the cache cannot be saved.
+pub fn hash_games(game_list: &mut GameList) -> Result<usize, io::Error> {
+    let emulator_dir = game_list.directory.clone();
+    inspect_archives(&emulator_dir, game_list);
+
+    let mut cache = HashCache::load(&emulator_dir);
+    let mut hashed = 0;
+
+    for game in game_list.games.iter_mut() {
+        let path = match game.field_value("path") {
+            Some(path) if !path.is_empty() => path.to_string(),
+            _ => continue,
+        };
+        let rom_path = roms::rom_file_path(&emulator_dir, &path);
+        if !rom_path.is_file() {
+            continue;
         }
-
-        // iterate through the start and end indexes and extract the contents of each game element
-        for i in 0..game_end_indexes.len() {
-            let game_slice = contents[game_start_indexes[i]..game_end_indexes[i]].to_string();
-            game_list.add_game_entry();
-            // then parse out the field elements and apply them to the game struct
-            parse_game_fields(game_list.games.last_mut().unwrap(), &game_slice);
+        let inner_file = game.archive()
+            .and_then(|archive| archive.inner_rom())
+            .map(|rom| rom.name.clone());
+        if let Ok(hashes) = cache.hashes(&rom_path, &path, inner_file.as_deref()) {
+            game.set_hashes(hashes);
+            hashed += 1;
         }
-
-    }
-    Ok(())
-}
-
-/*
-    From the contents of a game element within the gamelist.xml file, 
-    extract the field elements and apply them to a new Game struct
- */
-fn parse_game_fields(game: &mut Game, entry_chunk: &String) {
-    let regex = Regex::new(r"<([\w:-]+)>((?:[^<>]|\n|\w)*)</[\w:-]+>").unwrap();
-    
-    for tag_and_value in regex.captures_iter(entry_chunk.as_str()) {
-        let tag = &tag_and_value[1];
-        let value = tag_and_value[2].to_string();
-        game.add_field(tag, value);  
     }
+    cache.save()?;
+    Ok(hashed)
 }
 
-pub fn save(game_list: &GameList) -> Result<(), Error> {
-    let xml_string = parse_gamelist_to_XML_string(game_list);
+/// Saves a game list to the gamelist.xml file at the game list's directory path.
+/// 
+/// An existing file is updated in place, so only the games, folders and fields which have changed
+/// are rewritten. Otherwise a new file is created. The previous file is kept as a backup, and the
+/// new contents are written to a temporary file which then replaces it, so a crash or a full disk
+/// part way through a save leaves the previous file intact.
+///
+/// The save is refused with a conflict error if the file is no longer the one the game list was
+/// loaded from, see [`conflicts::merge`]. Once saved the game list is loaded from the new file.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its directory must be the path of the gamelist.xml file.
+/// * `options` - The number of backups to keep.
+pub fn save(game_list: &mut GameList, options: &SaveOptions) -> Result<(), Error> {
+    let on_disk = conflicts::read(&game_list.directory)?;
+    conflicts::check(game_list.loaded.as_ref(), on_disk.as_ref())?;
+
+    let (xml_string, entries) = match on_disk {
+        Some(existing) => gamelist_xml::update(&existing.contents, &game_list.games, &game_list.folders)?,
+        None => (gamelist_xml::create(&game_list.games, &game_list.folders), (0..game_list.games.len() + game_list.folders.len()).collect()),
+    };
+    backups::back_up(&game_list.directory, options.backups)?;
     write_to_file(&game_list.directory, &xml_string)?;
-    Ok(())
-}
 
-fn write_to_file(file_path: &PathBuf, contents: &String) -> Result<(), Error> {
-    println!("Attempting to write to file: {:?}", file_path);
-    let mut file = File::create(file_path)?;
-    file.write_all(contents.as_bytes())?;
+    // the entries are listed for the games, then for the folders
+    let (game_entries, folder_entries) = entries.split_at(game_list.games.len());
+    for (game, entry) in game_list.games.iter_mut().zip(game_entries) {
+        game.set_gamelist_entry(Some(*entry));
+    }
+    for (folder, entry) in game_list.folders.iter_mut().zip(folder_entries) {
+        folder.set_gamelist_entry(Some(*entry));
+    }
+    game_list.loaded = conflicts::read(&game_list.directory)?;
     Ok(())
 }
 
-fn parse_gamelist_to_XML_string(game_list: &GameList) -> String {
-    let mut xml_string = String::new();
-    xml_string.push_str("<?xml version=\"1.0\"?>\n");
-    xml_string.push_str("<gameList>\n");
-    for game in &game_list.games {
-        xml_string.push_str("\t<game>\n");
-        for field in &game.fields {
-            xml_string.push_str(format!("\t\t<{}>{}</{}>\n", field.name, field.value, field.name).as_str());
-        }
-        xml_string.push_str("\t</game>\n");
+// Writes the contents to a temporary file beside the file, flushes it to disk, then renames it
+// over the file. A rename within a directory replaces the file in one step, readers see either
+// the old or the new contents.
+fn write_to_file(file_path: &Path, contents: &str) -> Result<(), io::Error> {
+    let mut temp_name = file_path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".bgle-tmp");
+    let temp_path = file_path.with_file_name(temp_name);
+
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| std::fs::rename(&temp_path, file_path)) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
     }
-    xml_string.push_str("</gameList>\n");
-    xml_string
+
+    // the rename itself is only durable once the directory is flushed too
+    #[cfg(unix)]
+    if let Some(directory) = file_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        File::open(directory)?.sync_all()?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -239,6 +321,7 @@ mod test {
     // Modify the tests to reflect the test directory on your system. Else the tests will fail.
     //
     use super::*;
+    use crate::bgle::utils::TempDir;
     
 
     #[test]
@@ -300,8 +383,9 @@ mod test {
         let mut path = PathBuf::from(TEST_DIRECTORY);
         path.push("snes");
         let mut gamelist = game_list::new();
-        games_from_gamelist(&path, &mut gamelist);   
+        games_from_gamelist(&path, &mut gamelist).unwrap();   
         assert_eq!(gamelist.games.len(), 3);
+        assert_eq!(gamelist.folders.len(), 0);
     }
 
     #[test]
@@ -309,11 +393,56 @@ mod test {
         let mut path = PathBuf::from(TEST_DIRECTORY);
         path.push("snes");
         let mut game_list = game_list::new();
-        collate_games_to_list(&path, &mut game_list);
+        collate_games_to_list(&path, &mut game_list).unwrap();
 
         assert_eq!(game_list.games.len(), 4);
     }
- 
 
+    #[test]
+    fn test_hash_games_in_archives() {
+        let directory = TempDir::new("game-listings-hash");
+        std::fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc .zip\"\n").unwrap();
+        std::fs::write(directory.join("Loose.smc"), "The quick brown fox jumps over the lazy dog").unwrap();
+
+        let mut writer = zip::ZipWriter::new(File::create(directory.join("Zipped.zip")).unwrap());
+        writer.start_file("Zipped.smc", Default::default()).unwrap();
+        writer.write_all(b"The quick brown fox jumps over the lazy dog").unwrap();
+        writer.finish().unwrap();
+
+        let mut game_list = new(directory.to_str().unwrap()).unwrap();
+        assert_eq!(game_list.games.len(), 2);
+        let zipped = game_list.games.iter().position(|game| game.field_value("path") == Some("./Zipped.zip")).unwrap();
+        assert_eq!(game_list.games[zipped].archive().unwrap().inner_rom().unwrap().name, "Zipped.smc");
+
+        assert_eq!(hash_games(&mut game_list).unwrap(), 2);
+        for game in &game_list.games {
+            assert_eq!(game.field_value("md5"), Some("9e107d9d372bb6826bd81d3542a419d6"));
+            assert_eq!(game.field_value("crc32"), Some("414FA339"));
+        }
+    }
+
+    #[test]
+    fn test_save_refuses_external_changes() {
+        let directory = TempDir::new("game-listings-save");
+        std::fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc\"\n").unwrap();
+        std::fs::write(directory.join("A.smc"), "rom").unwrap();
+
+        let mut game_list = new(directory.to_str().unwrap()).unwrap();
+        assert!(game_list.loaded.is_none());
+        game_list.directory = directory.join("gamelist.xml");
+        game_list.games[0].set_field("name", "First".to_string());
+        save(&mut game_list, &SaveOptions::default()).unwrap();
+        assert_eq!(game_list.games[0].gamelist_entry(), Some(0));
+
+        // the list was loaded again from the saved file, so it can be saved a second time
+        game_list.games[0].set_field("name", "Second".to_string());
+        save(&mut game_list, &SaveOptions::default()).unwrap();
+        assert_eq!(backups::list(&game_list.directory).unwrap().len(), 1);
+
+        std::fs::write(&game_list.directory, "<gameList></gameList>").unwrap();
+        game_list.games[0].set_field("name", "Third".to_string());
+        assert!(matches!(save(&mut game_list, &SaveOptions::default()), Err(Error::Conflict(_))));
+        assert_eq!(std::fs::read_to_string(&game_list.directory).unwrap(), "<gameList></gameList>");
+    }
 }
 
@@ -0,0 +1,153 @@
+//! This module keeps backups of a gamelist.xml file from before it was saved.
+//!
+//! Each time a gamelist.xml file is saved its previous contents are copied beside it, named after
+//! the time of the save as `gamelist.xml.bgle-bak-<seconds since 1970>`. Only the most recent
+//! backups are kept, the oldest are removed once there are more than the number to keep.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+// Appended to the gamelist.xml file name, before the backup's timestamp.
+const BACKUP_SUFFIX: &str = ".bgle-bak-";
+
+/// How a gamelist.xml file is saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveOptions {
+    /// The number of backups of the file to keep, 0 saves without making a backup.
+    pub backups: usize,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions { backups: 5 }
+    }
+}
+
+/// A backup of a gamelist.xml file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// When the backup was made, in seconds since 1970, this also identifies the backup.
+    pub timestamp: u64,
+    /// The size of the backup in bytes.
+    pub size: u64,
+}
+
+/// Lists the backups of a gamelist.xml file, the most recent first.
+///
+/// ### Arguments
+/// * `gamelist_path` - The full path of the gamelist.xml file.
+/// ### Returns
+/// * `Result<Vec<Backup>, io::Error>` - The backups, an error if the file's directory cannot be read.
+pub fn list(gamelist_path: &Path) -> Result<Vec<Backup>, io::Error> {
+    let prefix = format!("{}{}", file_name(gamelist_path), BACKUP_SUFFIX);
+    let mut backups = Vec::<Backup>::new();
+    for entry in fs::read_dir(directory(gamelist_path))? {
+        let entry = entry?;
+        let timestamp = entry.file_name().to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|timestamp| timestamp.parse::<u64>().ok());
+        if let Some(timestamp) = timestamp {
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                backups.push(Backup { path: entry.path(), timestamp, size: metadata.len() });
+            }
+        }
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.timestamp));
+    Ok(backups)
+}
+
+/// Copies a gamelist.xml file to a new backup, then removes the oldest backups beyond the number
+/// to keep.
+///
+/// ### Arguments
+/// * `gamelist_path` - The full path of the gamelist.xml file.
+/// * `keep` - The number of backups to keep, 0 makes no backup.
+/// ### Returns
+/// * `Result<Option<Backup>, io::Error>` - The new backup, None if there is no file to back up or no backups are kept.
+pub fn back_up(gamelist_path: &Path, keep: usize) -> Result<Option<Backup>, io::Error> {
+    if keep == 0 || !gamelist_path.is_file() {
+        return Ok(None);
+    }
+
+    // two saves within the same second are told apart by moving the later one on a second
+    let existing = list(gamelist_path)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+    let timestamp = existing.first().map_or(now, |latest| now.max(latest.timestamp + 1));
+
+    let path = backup_path(gamelist_path, timestamp);
+    let size = fs::copy(gamelist_path, &path)?;
+    for old in existing.iter().skip(keep - 1) {
+        fs::remove_file(&old.path)?;
+    }
+    Ok(Some(Backup { path, timestamp, size }))
+}
+
+/// Replaces a gamelist.xml file with one of its backups. The file's current contents are backed up
+/// first, so a restore can itself be undone.
+///
+/// ### Arguments
+/// * `gamelist_path` - The full path of the gamelist.xml file.
+/// * `timestamp` - The timestamp of the backup to restore.
+/// * `options` - The number of backups to keep.
+/// ### Returns
+/// * `Result<(), io::Error>` - An error if the backup does not exist or the file cannot be written.
+pub fn restore(gamelist_path: &Path, timestamp: u64, options: &SaveOptions) -> Result<(), io::Error> {
+    let contents = fs::read_to_string(backup_path(gamelist_path, timestamp))?;
+    back_up(gamelist_path, options.backups)?;
+    super::write_to_file(gamelist_path, &contents)
+}
+
+// The path of the backup of a gamelist.xml file made at a time.
+fn backup_path(gamelist_path: &Path, timestamp: u64) -> PathBuf {
+    directory(gamelist_path).join(format!("{}{}{}", file_name(gamelist_path), BACKUP_SUFFIX, timestamp))
+}
+
+fn file_name(gamelist_path: &Path) -> String {
+    gamelist_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+fn directory(gamelist_path: &Path) -> &Path {
+    match gamelist_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+
+    #[test]
+    fn test_back_up_and_restore() {
+        let directory = TempDir::new("backups");
+        let gamelist_path = directory.join("gamelist.xml");
+
+        assert_eq!(back_up(&gamelist_path, 2).unwrap(), None);
+        for version in 1..=3 {
+            fs::write(&gamelist_path, format!("version {}", version)).unwrap();
+            back_up(&gamelist_path, 2).unwrap().unwrap();
+        }
+        assert_eq!(back_up(&gamelist_path, 0).unwrap(), None);
+
+        // only the two latest backups are kept, listed newest first
+        let backups = list(&gamelist_path).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(fs::read_to_string(&backups[0].path).unwrap(), "version 3");
+        assert_eq!(fs::read_to_string(&backups[1].path).unwrap(), "version 2");
+        assert!(backups[0].path.to_str().unwrap().ends_with(&format!("gamelist.xml.bgle-bak-{}", backups[0].timestamp)));
+
+        fs::write(&gamelist_path, "version 4").unwrap();
+        restore(&gamelist_path, backups[1].timestamp, &SaveOptions { backups: 3 }).unwrap();
+        assert_eq!(fs::read_to_string(&gamelist_path).unwrap(), "version 2");
+        assert!(!directory.join("gamelist.xml.bgle-tmp").exists());
+        assert_eq!(fs::read_to_string(&list(&gamelist_path).unwrap()[0].path).unwrap(), "version 4");
+        assert!(restore(&gamelist_path, 1, &SaveOptions::default()).is_err());
+    }
+}
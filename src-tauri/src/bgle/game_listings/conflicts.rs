@@ -0,0 +1,515 @@
+//! This module detects when a gamelist.xml file was changed by another program, such as Batocera
+//! or a scraper, while BGLE had it loaded, and merges those changes with BGLE's own.
+//!
+//! A game list keeps the file as it was when it was loaded. Before saving, the file on disk is
+//! compared with it and a save which would overwrite changes made elsewhere is refused. The edits
+//! made in BGLE can instead be merged field by field with the file on disk, using the loaded file
+//! as the common original of both.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use serde::{Serialize, Deserialize};
+use sha1::{Digest, Sha1};
+
+use super::Error;
+use super::gamelist_xml::{self, Document, Entry, EntryKind};
+use super::structs::folder::Folder;
+use super::structs::game::{Field, Game};
+use super::structs::game_list::GameList;
+
+/// Identifies the contents of a gamelist.xml file at a point in time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// The sha1 of the file's contents, as a lowercase hexadecimal string.
+    pub sha1: String,
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+/// The contents of a gamelist.xml file and its fingerprint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadedFile {
+    pub fingerprint: Fingerprint,
+    pub contents: String,
+}
+
+/// A save refused because the gamelist.xml file is not the one the game list was loaded from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conflict {
+    /// The file when the game list was loaded, None if there was no file.
+    pub loaded: Option<Fingerprint>,
+    /// The file on disk now, None if it has been removed.
+    pub on_disk: Option<Fingerprint>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.on_disk {
+            Some(_) => write!(f, "The gamelist.xml file was changed by another program since it was loaded"),
+            None => write!(f, "The gamelist.xml file was removed by another program since it was loaded"),
+        }
+    }
+}
+
+/// A field changed both in BGLE and in the file on disk, to different values. The merged game list
+/// keeps BGLE's value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldConflict {
+    pub kind: EntryKind,
+    /// The id of the game or folder within the merged game list.
+    pub id: usize,
+    pub field: String,
+    /// The value when the list was loaded, None if the field was not set.
+    pub original: Option<String>,
+    /// The value in BGLE, None if the field was removed.
+    pub ours: Option<String>,
+    /// The value in the file on disk, None if the field or the whole entry was removed.
+    pub theirs: Option<String>,
+}
+
+/// A game list merged with the changes made to its gamelist.xml file by another program.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Merge {
+    /// The merged game list, it is loaded from the file on disk so it can be saved over it.
+    pub game_list: GameList,
+    pub conflicts: Vec<FieldConflict>,
+}
+
+/// Reads a gamelist.xml file and fingerprints it.
+///
+/// ### Arguments
+/// * `path` - The full path of the gamelist.xml file.
+/// ### Returns
+/// * `Result<Option<LoadedFile>, io::Error>` - The file, None if it does not exist.
+pub fn read(path: &Path) -> Result<Option<LoadedFile>, io::Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let sha1: String = Sha1::digest(contents.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    Ok(Some(LoadedFile {
+        fingerprint: Fingerprint {
+            sha1,
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        },
+        contents,
+    }))
+}
+
+/// Checks that the gamelist.xml file on disk is the one a game list was loaded from. A file whose
+/// modification time changed but whose contents did not, for example one that was only copied, is
+/// not a conflict.
+///
+/// ### Arguments
+/// * `loaded` - The file the game list was loaded from.
+/// * `on_disk` - The file on disk now.
+pub fn check(loaded: Option<&LoadedFile>, on_disk: Option<&LoadedFile>) -> Result<(), Conflict> {
+    match (loaded, on_disk) {
+        (None, None) => Ok(()),
+        (Some(loaded), Some(on_disk)) if unchanged(&loaded.fingerprint, &on_disk.fingerprint) => Ok(()),
+        _ => Err(Conflict {
+            loaded: loaded.map(|file| file.fingerprint.clone()),
+            on_disk: on_disk.map(|file| file.fingerprint.clone()),
+        }),
+    }
+}
+
+fn unchanged(loaded: &Fingerprint, on_disk: &Fingerprint) -> bool {
+    let same_time = loaded.modified_secs == on_disk.modified_secs && loaded.modified_nanos == on_disk.modified_nanos;
+    (same_time && loaded.size == on_disk.size) || loaded.sha1 == on_disk.sha1
+}
+
+/// Merges the edits made to a game list with the changes made to its gamelist.xml file since it
+/// was loaded.
+///
+/// Games and folders are matched by the entry they were loaded from, and entries of the loaded and
+/// current files by their path. A field changed on only one side takes that side's value, a field
+/// changed on both sides to different values keeps the game list's value and is reported as a
+/// conflict. Entries added to the file are added to the list, entries removed from the file are
+/// removed from the list unless they were edited in BGLE.
+///
+/// ### Arguments
+/// * `game_list` - The game list, as edited in BGLE.
+/// * `on_disk` - The file on disk now, None if it has been removed.
+/// ### Returns
+/// * `Result<Merge, Error>` - The merged list and its conflicts, an error if either file is malformed.
+pub fn merge(game_list: GameList, on_disk: Option<LoadedFile>) -> Result<Merge, Error> {
+    let original = document(game_list.loaded.as_ref())?;
+    let current = document(on_disk.as_ref())?;
+    let original_values: Vec<Values> = original.entries.iter().map(entry_values).collect();
+    let current_values: Vec<Values> = current.entries.iter().map(entry_values).collect();
+    let moved = match_entries(&original, &original_values, &current, &current_values);
+
+    let mut merger = Merger {
+        original: &original,
+        original_values: &original_values,
+        current: &current,
+        current_values: &current_values,
+        moved: &moved,
+        claimed: vec![false; current.entries.len()],
+        kept: vec![false; original.entries.len()],
+        conflicts: Vec::new(),
+    };
+
+    let mut game_list = game_list;
+    let games = std::mem::take(&mut game_list.games);
+    game_list.games = merger.merge_items(games);
+    let folders = std::mem::take(&mut game_list.folders);
+    game_list.folders = merger.merge_items(folders);
+
+    // entries added to the file by the other program, those removed in BGLE stay removed
+    for (index, entry) in current.entries.iter().enumerate() {
+        if merger.claimed[index] || moved.contains(&Some(index)) {
+            continue;
+        }
+        match entry.kind {
+            EntryKind::Game => {
+                let id = game_list.new_game_id();
+                game_list.games.push(Game::from_values(id, index, &current_values[index]));
+            }
+            EntryKind::Folder => {
+                let id = game_list.new_folder_id();
+                game_list.folders.push(Folder::from_values(id, index, &current_values[index]));
+            }
+        }
+    }
+
+    game_list.loaded = on_disk;
+    Ok(Merge { game_list, conflicts: merger.conflicts })
+}
+
+// The fields of an entry as names and values, the first of any fields sharing a name.
+type Values = Vec<(String, String)>;
+
+fn document(file: Option<&LoadedFile>) -> Result<Document, Error> {
+    match file {
+        Some(file) => gamelist_xml::parse(&file.contents),
+        None => Ok(Document::default()),
+    }
+}
+
+// Reads an entry's fields as they are read when loading, so invalid values are cleared the same way.
+fn entry_values(entry: &Entry) -> Values {
+    let fields = match entry.kind {
+        EntryKind::Game => {
+            let mut game = Game::new(0);
+            entry.fields.iter().for_each(|field| game.add_field(&field.name, field.value.clone()));
+            game.fields
+        }
+        EntryKind::Folder => {
+            let mut folder = Folder::new(0);
+            entry.fields.iter().for_each(|field| folder.add_field(&field.name, field.value.clone()));
+            folder.fields
+        }
+    };
+    values(&fields)
+}
+
+fn values(fields: &[Field]) -> Values {
+    let mut values = Values::new();
+    for field in fields {
+        if !values.iter().any(|(name, _)| name == &field.name) {
+            values.push((field.name.clone(), field.value.clone()));
+        }
+    }
+    values
+}
+
+fn value<'a>(values: &'a Values, name: &str) -> Option<&'a String> {
+    values.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+}
+
+// Returns, for every entry of the loaded file, the entry of the current file with the same kind
+// and path.
+fn match_entries(original: &Document, original_values: &[Values], current: &Document, current_values: &[Values]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; original.entries.len()];
+    for (index, entry) in original.entries.iter().enumerate() {
+        let path = match value(&original_values[index], "path") {
+            Some(path) if !path.is_empty() => path,
+            _ => continue,
+        };
+        matched[index] = (0..current.entries.len()).find(|&other| {
+            current.entries[other].kind == entry.kind
+                && value(&current_values[other], "path") == Some(path)
+                && !matched.contains(&Some(other))
+        });
+    }
+    matched
+}
+
+// The parts of a game or folder the merge reads and changes.
+trait Item {
+    const KIND: EntryKind;
+    fn id(&self) -> usize;
+    fn fields(&self) -> &[Field];
+    fn gamelist_entry(&self) -> Option<usize>;
+    fn set_gamelist_entry(&mut self, entry: Option<usize>);
+    fn set_value(&mut self, name: &str, value: Option<String>);
+
+    fn from_values(id: usize, entry: usize, values: &Values) -> Self;
+}
+
+impl Item for Game {
+    const KIND: EntryKind = EntryKind::Game;
+
+    fn id(&self) -> usize {
+        self.game_id()
+    }
+
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    fn gamelist_entry(&self) -> Option<usize> {
+        Game::gamelist_entry(self)
+    }
+
+    fn set_gamelist_entry(&mut self, entry: Option<usize>) {
+        Game::set_gamelist_entry(self, entry)
+    }
+
+    fn set_value(&mut self, name: &str, value: Option<String>) {
+        match value {
+            Some(value) => self.set_field(name, value),
+            None => self.fields.retain(|field| field.name != name),
+        }
+    }
+
+    fn from_values(id: usize, entry: usize, values: &Values) -> Self {
+        let mut game = Game::new(id);
+        game.set_gamelist_entry(Some(entry));
+        values.iter().for_each(|(name, value)| game.add_field(name, value.clone()));
+        game
+    }
+}
+
+impl Item for Folder {
+    const KIND: EntryKind = EntryKind::Folder;
+
+    fn id(&self) -> usize {
+        self.folder_id()
+    }
+
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    fn gamelist_entry(&self) -> Option<usize> {
+        Folder::gamelist_entry(self)
+    }
+
+    fn set_gamelist_entry(&mut self, entry: Option<usize>) {
+        Folder::set_gamelist_entry(self, entry)
+    }
+
+    fn set_value(&mut self, name: &str, value: Option<String>) {
+        match value {
+            Some(value) => self.set_field(name, value),
+            None => self.fields.retain(|field| field.name != name),
+        }
+    }
+
+    fn from_values(id: usize, entry: usize, values: &Values) -> Self {
+        let mut folder = Folder::new(id);
+        folder.set_gamelist_entry(Some(entry));
+        values.iter().for_each(|(name, value)| folder.add_field(name, value.clone()));
+        folder
+    }
+}
+
+// The state of a merge shared between games and folders.
+struct Merger<'a> {
+    original: &'a Document,
+    original_values: &'a [Values],
+    current: &'a Document,
+    current_values: &'a [Values],
+    // the entry of the current file matching each entry of the loaded file
+    moved: &'a [Option<usize>],
+    // the entries of the current file given to a game or folder
+    claimed: Vec<bool>,
+    // the entries of the loaded file still held by a game or folder
+    kept: Vec<bool>,
+    conflicts: Vec<FieldConflict>,
+}
+
+impl Merger<'_> {
+
+    fn merge_items<T: Item>(&mut self, items: Vec<T>) -> Vec<T> {
+        let (original_values, current_values) = (self.original_values, self.current_values);
+        let mut merged = Vec::<T>::new();
+        for mut item in items {
+            let original = item.gamelist_entry()
+                .filter(|&entry| entry < self.original.entries.len() && self.original.entries[entry].kind == T::KIND && !self.kept[entry]);
+            let ours = values(item.fields());
+
+            let current = match original {
+                Some(entry) => {
+                    self.kept[entry] = true;
+                    self.moved[entry]
+                }
+                // an item added in BGLE may have been added to the file too
+                None => value(&ours, "path").and_then(|path| (0..self.current.entries.len()).find(|&entry| {
+                    self.current.entries[entry].kind == T::KIND
+                        && !self.claimed[entry]
+                        && !self.moved.contains(&Some(entry))
+                        && value(&current_values[entry], "path") == Some(path)
+                })),
+            };
+            let empty = Values::new();
+            let original_values = original.map_or(&empty, |entry| &original_values[entry]);
+
+            match current {
+                Some(entry) => {
+                    self.claimed[entry] = true;
+                    self.merge_fields(&mut item, original_values, &ours, &current_values[entry]);
+                    item.set_gamelist_entry(Some(entry));
+                }
+                // removed from the file, kept only if it was edited in BGLE
+                None if original.is_some() => {
+                    if &ours == original_values {
+                        continue;
+                    }
+                    self.conflicts.push(FieldConflict {
+                        kind: T::KIND,
+                        id: item.id(),
+                        field: String::from("path"),
+                        original: value(original_values, "path").cloned(),
+                        ours: value(&ours, "path").cloned(),
+                        theirs: None,
+                    });
+                    item.set_gamelist_entry(None);
+                }
+                None => item.set_gamelist_entry(None),
+            }
+            merged.push(item);
+        }
+        merged
+    }
+
+    fn merge_fields<T: Item>(&mut self, item: &mut T, original: &Values, ours: &Values, theirs: &Values) {
+        let mut names = Vec::<&String>::new();
+        for (name, _) in ours.iter().chain(theirs).chain(original) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        for name in names {
+            let (original_value, our_value, their_value) = (value(original, name), value(ours, name), value(theirs, name));
+            if our_value == original_value {
+                if their_value != our_value {
+                    item.set_value(name, their_value.cloned());
+                }
+            } else if their_value != original_value && their_value != our_value {
+                self.conflicts.push(FieldConflict {
+                    kind: T::KIND,
+                    id: item.id(),
+                    field: name.clone(),
+                    original: original_value.cloned(),
+                    ours: our_value.cloned(),
+                    theirs: their_value.cloned(),
+                });
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loaded(contents: &str, modified_secs: u64) -> LoadedFile {
+        LoadedFile {
+            fingerprint: Fingerprint {
+                sha1: format!("{:x}", Sha1::digest(contents.as_bytes())),
+                size: contents.len() as u64,
+                modified_secs,
+                modified_nanos: 0,
+            },
+            contents: contents.to_string(),
+        }
+    }
+
+    fn load(file: LoadedFile) -> GameList {
+        let mut game_list = super::super::structs::game_list::new();
+        for (index, entry) in gamelist_xml::parse(&file.contents).unwrap().entries.iter().enumerate() {
+            let id = game_list.new_game_id();
+            game_list.games.push(Game::from_values(id, index, &entry_values(entry)));
+        }
+        game_list.loaded = Some(file);
+        game_list
+    }
+
+    const ORIGINAL: &str = "<gameList>\n\
+\t<game><path>./a.smc</path><name>A</name><rating>0.5</rating></game>\n\
+\t<game><path>./b.smc</path><name>B</name></game>\n\
+\t<game><path>./c.smc</path><name>C</name></game>\n\
+</gameList>\n";
+
+    #[test]
+    fn test_check() {
+        let original = loaded(ORIGINAL, 1);
+        assert!(check(Some(&original), Some(&loaded(ORIGINAL, 1))).is_ok());
+        // only touched, the contents are the same
+        assert!(check(Some(&original), Some(&loaded(ORIGINAL, 2))).is_ok());
+        assert!(check(None, None).is_ok());
+
+        let changed = loaded(&ORIGINAL.replace("<name>A</name>", "<name>AA</name>"), 2);
+        let conflict = check(Some(&original), Some(&changed)).unwrap_err();
+        assert_eq!(conflict.on_disk, Some(changed.fingerprint.clone()));
+        assert!(check(Some(&original), None).is_err());
+        assert!(check(None, Some(&changed)).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut game_list = load(loaded(ORIGINAL, 1));
+        game_list.games[0].set_field("name", "Ours".to_string());
+        game_list.games[0].set_field("desc", "Ours".to_string());
+        game_list.games[1].set_field("name", "Ours".to_string());
+        game_list.games.remove(2);
+
+        // the other program renamed A and B, rated A, removed C and added D before A
+        let on_disk = "<gameList>\n\
+\t<game><path>./d.smc</path><name>D</name></game>\n\
+\t<game><path>./a.smc</path><name>A</name><rating>0.9</rating></game>\n\
+\t<game><path>./b.smc</path><name>Theirs</name></game>\n\
+</gameList>\n";
+        let merge = merge(game_list, Some(loaded(on_disk, 2))).unwrap();
+        let games: Vec<(usize, Option<usize>)> = merge.game_list.games.iter().map(|game| (game.game_id(), game.gamelist_entry())).collect();
+        // D is not given the id C had
+        assert_eq!(games, vec![(0, Some(1)), (1, Some(2)), (3, Some(0))]);
+        let fields: Vec<[Option<&str>; 3]> = merge.game_list.games.iter()
+            .map(|game| ["name", "rating", "desc"].map(|field| game.field_value(field)))
+            .collect();
+        assert_eq!(fields, vec![
+            [Some("Ours"), Some("0.9"), Some("Ours")],
+            [Some("Ours"), None, None],
+            [Some("D"), None, None],
+        ]);
+        assert_eq!(merge.conflicts, vec![FieldConflict {
+            kind: EntryKind::Game,
+            id: 1,
+            field: String::from("name"),
+            original: Some(String::from("B")),
+            ours: Some(String::from("Ours")),
+            theirs: Some(String::from("Theirs")),
+        }]);
+
+        // the merged list is loaded from the file on disk, so it may be saved over it
+        let saved = gamelist_xml::update(on_disk, &merge.game_list.games, &merge.game_list.folders).unwrap().0;
+        assert!(saved.contains("<desc>Ours</desc>") && !saved.contains("./c.smc"));
+        assert!(check(merge.game_list.loaded.as_ref(), Some(&loaded(on_disk, 2))).is_ok());
+    }
+}
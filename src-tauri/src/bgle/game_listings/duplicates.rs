@@ -0,0 +1,274 @@
+//! This module finds games which are listed more than once.
+//!
+//! Within a game list a game may be listed twice under the same path, or the same ROM may be stored
+//! twice under different names. Across systems the same ROM may be stored in several emulator
+//! directories, for example a Game Boy game kept in both `gb` and `gbc`.
+
+use std::collections::{BTreeMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+use super::structs::game::Game;
+use super::structs::game_list::GameList;
+use crate::bgle::emulator_listings::valid::valid_directories_list;
+use crate::bgle::roms;
+
+/// What the games of a duplicate group have in common.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DuplicateKind {
+    /// The games are listed under the same path.
+    Path,
+    /// The games' ROMs have the same checksum.
+    Hash,
+}
+
+/// Games within a game list which are listed more than once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    /// The shared path or checksum.
+    pub key: String,
+    pub game_ids: Vec<usize>,
+}
+
+/// A copy of a ROM stored in an emulator directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RomCopy {
+    pub emulator: String,
+    pub directory: String,
+    pub path: String,
+}
+
+/// A ROM stored in more than one emulator directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrossSystemDuplicate {
+    pub md5: String,
+    pub copies: Vec<RomCopy>,
+}
+
+/// Finds the games of a game list which share a path or a ROM checksum.
+///
+/// Games sharing a path are only reported once, as a path group, rather than again as a hash group.
+/// The checksum of a game is taken from its computed hashes, or else its md5 or crc32 field.
+pub fn find_duplicates(game_list: &GameList) -> Vec<DuplicateGroup> {
+    let mut paths = BTreeMap::<String, Vec<usize>>::new();
+    let mut hashes = BTreeMap::<String, Vec<usize>>::new();
+    let mut seen_paths = HashSet::<String>::new();
+
+    for game in &game_list.games {
+        let path = path_key(game);
+        if let Some(path) = &path {
+            paths.entry(path.clone()).or_default().push(game.game_id());
+        }
+        // only the first game listed under a path takes part in the hash comparison
+        let first_with_path = path.map(|path| seen_paths.insert(path)).unwrap_or(true);
+        if let (true, Some(hash)) = (first_with_path, hash_key(game)) {
+            hashes.entry(hash).or_default().push(game.game_id());
+        }
+    }
+
+    let groups = |kind: DuplicateKind, keys: BTreeMap<String, Vec<usize>>| {
+        keys.into_iter()
+            .filter(|(_, game_ids)| game_ids.len() > 1)
+            .map(move |(key, game_ids)| DuplicateGroup { kind: kind.clone(), key, game_ids })
+    };
+    groups(DuplicateKind::Path, paths).chain(groups(DuplicateKind::Hash, hashes)).collect()
+}
+
+/// Merges duplicate games into the game with the most filled in fields. Fields which that game
+/// lacks, or which are empty, are taken from the other games in the order given, which are then
+/// removed from the list.
+///
+/// ### Arguments
+/// * `game_list` - The game list holding the games.
+/// * `game_ids` - The ids of the games to merge.
+/// ### Returns
+/// * `Option<usize>` - The id of the game kept, None if fewer than two of the games were found.
+pub fn merge_duplicates(game_list: &mut GameList, game_ids: &[usize]) -> Option<usize> {
+    let mut indices = Vec::<usize>::new();
+    for index in game_ids.iter().filter_map(|id| game_list.games.iter().position(|game| game.game_id() == *id)) {
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+    }
+    if indices.len() < 2 {
+        return None;
+    }
+
+    let kept = *indices.iter()
+        .rev()
+        .max_by_key(|index| filled_fields(&game_list.games[**index]))
+        .unwrap();
+
+    let mut fields = Vec::<(String, String)>::new();
+    let mut gamelist_entry = game_list.games[kept].gamelist_entry();
+    for index in indices.iter().filter(|index| **index != kept) {
+        let other = &game_list.games[*index];
+        gamelist_entry = gamelist_entry.or(other.gamelist_entry());
+        for field in other.fields.iter().filter(|field| !field.value.is_empty()) {
+            let missing = game_list.games[kept].field_value(&field.name).map(|value| value.is_empty()).unwrap_or(true);
+            if missing && !fields.iter().any(|(name, _)| *name == field.name) {
+                fields.push((field.name.clone(), field.value.clone()));
+            }
+        }
+    }
+
+    let game = &mut game_list.games[kept];
+    for (name, value) in fields {
+        game.set_field(&name, value);
+    }
+    // a game which has not been saved takes over the entry of a saved duplicate
+    game.set_gamelist_entry(gamelist_entry);
+    let kept_id = game.game_id();
+
+    let removed: Vec<usize> = indices.iter()
+        .filter(|index| **index != kept)
+        .map(|index| game_list.games[*index].game_id())
+        .collect();
+    for id in removed {
+        game_list.remove(id);
+    }
+    Some(kept_id)
+}
+
+/// Finds the ROMs stored in more than one emulator directory below a roms directory, by comparing
+/// their md5 checksums. Emulator directories which cannot be read are skipped.
+pub fn find_cross_system_duplicates(roms_dir: &str) -> Vec<CrossSystemDuplicate> {
+    let directories = valid_directories_list(roms_dir).unwrap_or_default();
+
+    let mut copies = BTreeMap::<String, Vec<RomCopy>>::new();
+    for directory in directories {
+        let mut game_list = match super::new(&directory) {
+            Ok(game_list) => game_list,
+            Err(_) => continue,
+        };
+        if super::hash_games(&mut game_list).is_err() {
+            continue;
+        }
+        for game in &game_list.games {
+            if let (Some(hashes), Some(path)) = (game.hashes(), game.field_value("path")) {
+                copies.entry(hashes.md5.clone()).or_default().push(RomCopy {
+                    emulator: game_list.emulator.clone(),
+                    directory: directory.clone(),
+                    path: path.to_string(),
+                });
+            }
+        }
+    }
+
+    copies.into_iter()
+        .filter(|(_, copies)| {
+            let emulators: HashSet<&String> = copies.iter().map(|copy| &copy.directory).collect();
+            emulators.len() > 1
+        })
+        .map(|(md5, copies)| CrossSystemDuplicate { md5, copies })
+        .collect()
+}
+
+// Paths are compared as the file they lead to, so "./a.smc" and "a.smc" are the same path.
+fn path_key(game: &Game) -> Option<String> {
+    let path = game.field_value("path").filter(|path| !path.is_empty())?;
+    roms::rom_file_path(std::path::Path::new(""), path).to_str().map(|path| path.to_string())
+}
+
+fn hash_key(game: &Game) -> Option<String> {
+    if let Some(hashes) = game.hashes() {
+        return Some(format!("md5:{}", hashes.md5));
+    }
+    let field = |name: &str| game.field_value(name).filter(|value| !value.is_empty()).map(|value| value.to_lowercase());
+    field("md5").map(|md5| format!("md5:{}", md5))
+        .or_else(|| field("crc32").map(|crc32| format!("crc32:{}", crc32)))
+}
+
+fn filled_fields(game: &Game) -> usize {
+    game.fields.iter().filter(|field| !field.value.is_empty()).count()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use crate::bgle::game_listings::structs::game_list;
+
+    fn game_list_of(games: &[&[(&str, &str)]]) -> GameList {
+        let mut game_list = game_list::new();
+        for fields in games {
+            game_list.add_game_entry();
+            let game = game_list.games.last_mut().unwrap();
+            for (name, value) in fields.iter() {
+                game.add_field(name, value.to_string());
+            }
+        }
+        game_list
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let game_list = game_list_of(&[
+            &[("path", "./a.smc"), ("md5", "AAAA")],
+            &[("path", "./a.smc"), ("md5", "aaaa")],
+            &[("path", "./b.smc"), ("md5", "aaaa")],
+            &[("path", "./c.smc"), ("crc32", "1234")],
+            &[("path", "./d.smc"), ("crc32", "1234")],
+            &[("path", "./e.smc")],
+        ]);
+        assert_eq!(find_duplicates(&game_list), vec![
+            DuplicateGroup { kind: DuplicateKind::Path, key: "a.smc".to_string(), game_ids: vec![0, 1] },
+            DuplicateGroup { kind: DuplicateKind::Hash, key: "crc32:1234".to_string(), game_ids: vec![3, 4] },
+            DuplicateGroup { kind: DuplicateKind::Hash, key: "md5:aaaa".to_string(), game_ids: vec![0, 2] },
+        ]);
+    }
+
+    #[test]
+    fn test_merge_duplicates() {
+        let mut game_list = game_list_of(&[
+            &[("path", "./a.smc"), ("name", "A"), ("genre", "")],
+            &[("path", "./a.smc"), ("name", "Other A"), ("desc", "About A"), ("developer", "Dev")],
+            &[("path", "./b.smc")],
+        ]);
+        game_list.games[0].set_gamelist_entry(Some(0));
+
+        assert_eq!(merge_duplicates(&mut game_list, &[0, 1]), Some(1));
+        assert_eq!(game_list.games.len(), 2);
+        let game = &game_list.games[0];
+        assert_eq!(game.game_id(), 1);
+        assert_eq!(game.field_value("name"), Some("Other A"));
+        assert_eq!(game.field_value("desc"), Some("About A"));
+        assert_eq!(game.gamelist_entry(), Some(0));
+
+        assert_eq!(merge_duplicates(&mut game_list, &[1]), None);
+    }
+
+    #[test]
+    fn test_merge_fills_empty_fields() {
+        let mut game_list = game_list_of(&[
+            &[("path", "./a.smc"), ("name", "A"), ("genre", ""), ("desc", "About A")],
+            &[("path", "./b.smc"), ("genre", "Platform")],
+        ]);
+        assert_eq!(merge_duplicates(&mut game_list, &[0, 1]), Some(0));
+        assert_eq!(game_list.games[0].field_value("genre"), Some("Platform"));
+        assert_eq!(game_list.games[0].field_value("path"), Some("./a.smc"));
+    }
+
+    #[test]
+    fn test_find_cross_system_duplicates() {
+        let roms_dir = TempDir::new("duplicates-roms");
+        for (emulator, files) in [("gb", vec!["Tetris.gb", "Zelda.gb"]), ("gbc", vec!["Tetris (World).gb"])] {
+            let directory = roms_dir.join(emulator);
+            std::fs::create_dir_all(&directory).unwrap();
+            std::fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".gb\"\n").unwrap();
+            std::fs::write(directory.join("gamelist.xml"), "<gameList>\n</gameList>\n").unwrap();
+            for file in files {
+                std::fs::write(directory.join(file), if file.starts_with("Tetris") { "tetris" } else { "zelda" }).unwrap();
+            }
+        }
+
+        let duplicates = find_cross_system_duplicates(roms_dir.to_str().unwrap());
+        assert_eq!(duplicates.len(), 1);
+        let mut paths: Vec<(&str, &str)> = duplicates[0].copies.iter()
+            .map(|copy| (copy.emulator.as_str(), copy.path.as_str()))
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec![("gb", "./Tetris.gb"), ("gbc", "./Tetris (World).gb")]);
+    }
+}
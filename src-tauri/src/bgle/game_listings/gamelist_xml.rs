@@ -0,0 +1,849 @@
+//! This module reads and writes the contents of a gamelist.xml file.
+//!
+//! Batocera writes the game list as a `<gameList>` root element holding one `<game>` element per
+//! game, along with `<folder>` elements describing subdirectories. Each child element of a `<game>`
+//! or `<folder>` is treated as a field, its text (including any CDATA sections) becoming the field
+//! value. Attributes, comments and any other elements are accepted but not collected.
+//!
+//! Field values are held as unescaped text, entity references such as `&amp;` are resolved when
+//! reading and the characters `&`, `<` and `>` are escaped again when writing.
+//!
+//! When writing to an existing file only the elements which changed are rewritten, everything else
+//! in the file is kept as it was, byte for byte.
+
+use std::ops::Range;
+
+use quick_xml::escape::partial_escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Serialize, Deserialize};
+
+use super::Error;
+use super::structs::game::{Field, Game};
+use super::structs::folder::Folder;
+
+/// The parts of a gamelist.xml file needed to read and update its game entries.
+#[derive(Debug, Default)]
+pub struct Document {
+    pub entries: Vec<Entry>,
+    // the span of the <gameList> start tag, or the whole element when it is self-closing
+    root_open: Range<usize>,
+    // the start of the </gameList> end tag, None when the root element is self-closing
+    root_close: Option<usize>,
+}
+
+/// The kinds of element read as entries from a gamelist.xml file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EntryKind {
+    #[default]
+    Game,
+    Folder,
+}
+
+impl EntryKind {
+    fn from_tag(name: &str) -> Option<EntryKind> {
+        match name {
+            "game" => Some(EntryKind::Game),
+            "folder" => Some(EntryKind::Folder),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            EntryKind::Game => "game",
+            EntryKind::Folder => "folder",
+        }
+    }
+}
+
+/// A `<game>` or `<folder>` element read from a gamelist.xml file.
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub fields: Vec<EntryField>,
+    // the span of the whole element, from its start tag to its end tag
+    span: Range<usize>,
+    // the span of the start tag
+    open_tag: Range<usize>,
+    // the start of the end tag, None when the element is self-closing
+    close_tag: Option<usize>,
+}
+
+/// A child element of an entry, its value holds unescaped text.
+#[derive(Debug)]
+pub struct EntryField {
+    pub name: String,
+    pub value: String,
+    span: Range<usize>,
+}
+
+// Element depths within the document, the root element being at depth 1.
+const ROOT_DEPTH: usize = 1;
+const ENTRY_DEPTH: usize = 2;
+const FIELD_DEPTH: usize = 3;
+
+/// Parses the contents of a gamelist.xml file and returns the game and folder entries found within it.
+///
+/// ### Arguments
+/// * `xml` - The contents of a gamelist.xml file.
+/// ### Returns
+/// * `Result<Document, Error>` - The entries in document order, or an error if the document is malformed.
+pub fn parse(xml: &str) -> Result<Document, Error> {
+    let mut reader = Reader::from_str(xml);
+
+    let mut document = Document::default();
+    let mut entry: Option<Entry> = None;
+    let mut field: Option<EntryField> = None;
+    let mut root_found = false;
+    let mut depth = 0;
+
+    loop {
+        let position = reader.buffer_position();
+        let event = reader.read_event().map_err(|e| malformed(position, e))?;
+        let span = position..reader.buffer_position();
+
+        match event {
+            Event::Start(element) => {
+                depth += 1;
+                let name = element_name(element.name().as_ref());
+                match depth {
+                    ROOT_DEPTH => {
+                        check_root(&name, root_found, position)?;
+                        root_found = true;
+                        document.root_open = span;
+                    }
+                    ENTRY_DEPTH => {
+                        entry = EntryKind::from_tag(&name)
+                            .map(|kind| Entry { kind, open_tag: span.clone(), span, ..Entry::default() });
+                    }
+                    FIELD_DEPTH if entry.is_some() => {
+                        field = Some(EntryField { name, value: String::new(), span });
+                    }
+                    _ => {}
+                }
+            }
+            // self-closing elements are opened and closed in a single event
+            Event::Empty(element) => {
+                let name = element_name(element.name().as_ref());
+                match depth + 1 {
+                    ROOT_DEPTH => {
+                        check_root(&name, root_found, position)?;
+                        root_found = true;
+                        document.root_open = span;
+                    }
+                    ENTRY_DEPTH => {
+                        if let Some(kind) = EntryKind::from_tag(&name) {
+                            document.entries.push(Entry { kind, open_tag: span.clone(), span, ..Entry::default() });
+                        }
+                    }
+                    FIELD_DEPTH => {
+                        if let Some(entry) = entry.as_mut() {
+                            entry.fields.push(EntryField { name, value: String::new(), span });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(_) => {
+                match depth {
+                    ROOT_DEPTH => document.root_close = Some(span.start),
+                    ENTRY_DEPTH => {
+                        if let Some(mut entry) = entry.take() {
+                            entry.span.end = span.end;
+                            entry.close_tag = Some(span.start);
+                            document.entries.push(entry);
+                        }
+                    }
+                    FIELD_DEPTH => {
+                        if let (Some(entry), Some(mut field)) = (entry.as_mut(), field.take()) {
+                            field.span.end = span.end;
+                            entry.fields.push(field);
+                        }
+                    }
+                    _ => {}
+                }
+                depth -= 1;
+            }
+            Event::Text(text) => {
+                if let Some(field) = field.as_mut() {
+                    let value = text.unescape().map_err(|e| malformed(position, e))?;
+                    field.value.push_str(&value);
+                }
+            }
+            Event::CData(cdata) => {
+                if let Some(field) = field.as_mut() {
+                    field.value.push_str(&String::from_utf8_lossy(&cdata.into_inner()));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if depth != 0 {
+        return Err(Error::Malformed {
+            position: xml.len(),
+            message: String::from("unexpected end of file, an element was not closed"),
+        });
+    }
+    if !root_found {
+        return Err(Error::MissingRoot);
+    }
+
+    Ok(document)
+}
+
+/// Creates the contents of a new gamelist.xml file holding the given games and folders.
+pub fn create(games: &[Game], folders: &[Folder]) -> String {
+    let mut xml_string = String::new();
+    xml_string.push_str("<?xml version=\"1.0\"?>\n");
+    xml_string.push_str("<gameList>\n");
+    for item in items(games, folders) {
+        xml_string.push('\t');
+        xml_string.push_str(&entry_element(&item, "\n", "\t"));
+        xml_string.push('\n');
+    }
+    xml_string.push_str("</gameList>\n");
+    xml_string
+}
+
+/// Updates the contents of an existing gamelist.xml file so that it holds the given games and folders.
+///
+/// Games and folders are matched to the file's entries by the entry they were read from, or failing
+/// that by their path. Only fields whose values differ are rewritten, entries without a match are
+/// removed and games or folders without a matching entry are added after the last entry. Everything
+/// else is kept unchanged, including comments, attributes, formatting and elements BGLE does not edit.
+///
+/// ### Arguments
+/// * `xml` - The current contents of the gamelist.xml file.
+/// * `games` - The games the file should hold.
+/// * `folders` - The folders the file should hold.
+/// ### Returns
+/// * `Result<(String, Vec<usize>), Error>` - The updated contents, with the position of each game's entry within them
+///   followed by each folder's, or an error if the current contents are malformed.
+pub fn update(xml: &str, games: &[Game], folders: &[Folder]) -> Result<(String, Vec<usize>), Error> {
+    let document = parse(xml)?;
+    let originals: Vec<Vec<Field>> = document.entries.iter().map(fields_from_entry).collect();
+    let items = items(games, folders);
+    let matched = match_items_to_entries(&document.entries, &originals, &items);
+
+    // entries keep their order, those removed close up and new ones follow the last
+    let mut positions = vec![0; items.len()];
+    let mut position = 0;
+    let mut edits = Vec::<Edit>::new();
+    for (index, entry) in document.entries.iter().enumerate() {
+        match matched[index] {
+            Some(item_index) => {
+                entry_edits(xml, entry, &originals[index], &items[item_index], &mut edits);
+                positions[item_index] = position;
+                position += 1;
+            }
+            None => edits.push(removal(xml, entry.span.clone())),
+        }
+    }
+
+    let new_items: Vec<&Item> = items.iter()
+        .enumerate()
+        .filter(|(item_index, _)| !matched.contains(&Some(*item_index)))
+        .map(|(item_index, item)| {
+            positions[item_index] = position;
+            position += 1;
+            item
+        })
+        .collect();
+    if !new_items.is_empty() {
+        edits.push(new_entries_edit(xml, &document, &new_items));
+    }
+
+    Ok((apply_edits(xml, edits), positions))
+}
+
+// A game or folder to be written, the parts of either which the writer needs.
+struct Item<'a> {
+    kind: EntryKind,
+    gamelist_entry: Option<usize>,
+    fields: &'a [Field],
+}
+
+fn items<'a>(games: &'a [Game], folders: &'a [Folder]) -> Vec<Item<'a>> {
+    let games = games.iter().map(|game| Item {
+        kind: EntryKind::Game,
+        gamelist_entry: game.gamelist_entry(),
+        fields: &game.fields,
+    });
+    let folders = folders.iter().map(|folder| Item {
+        kind: EntryKind::Folder,
+        gamelist_entry: folder.gamelist_entry(),
+        fields: &folder.fields,
+    });
+    games.chain(folders).collect()
+}
+
+// Creates the fields of an entry, applying the same validation used when loading.
+fn fields_from_entry(entry: &Entry) -> Vec<Field> {
+    match entry.kind {
+        EntryKind::Game => {
+            let mut game = Game::new(0);
+            for field in &entry.fields {
+                game.add_field(&field.name, field.value.clone());
+            }
+            game.fields
+        }
+        EntryKind::Folder => {
+            let mut folder = Folder::new(0);
+            for field in &entry.fields {
+                folder.add_field(&field.name, field.value.clone());
+            }
+            folder.fields
+        }
+    }
+}
+
+fn path_value(fields: &[Field]) -> Option<&str> {
+    fields.iter()
+        .find(|field| field.name == "path" && !field.value.is_empty())
+        .map(|field| field.value.as_str())
+}
+
+// Returns, for every entry, the index of the item it should be updated with.
+fn match_items_to_entries(entries: &[Entry], originals: &[Vec<Field>], items: &[Item]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; entries.len()];
+
+    for (item_index, item) in items.iter().enumerate() {
+        if let Some(entry) = item.gamelist_entry {
+            if entry < matched.len() && matched[entry].is_none() && entries[entry].kind == item.kind {
+                matched[entry] = Some(item_index);
+            }
+        }
+    }
+
+    // items added since the list was loaded may already have been saved once
+    for (item_index, item) in items.iter().enumerate() {
+        if matched.contains(&Some(item_index)) {
+            continue;
+        }
+        let path = match path_value(item.fields) {
+            Some(path) => path,
+            None => continue,
+        };
+        let entry = (0..entries.len()).find(|&entry| {
+            matched[entry].is_none() && entries[entry].kind == item.kind && path_value(&originals[entry]) == Some(path)
+        });
+        if let Some(entry) = entry {
+            matched[entry] = Some(item_index);
+        }
+    }
+    matched
+}
+
+// A replacement of a span of the original contents
+struct Edit {
+    range: Range<usize>,
+    text: String,
+}
+
+// Collects the edits needed to bring an entry in line with an item's fields.
+fn entry_edits(xml: &str, entry: &Entry, original: &[Field], item: &Item, edits: &mut Vec<Edit>) {
+
+    // fields sharing a name are matched by the order they appear in
+    for (index, field) in entry.fields.iter().enumerate() {
+        let occurrence = entry.fields[..index].iter().filter(|f| f.name == field.name).count();
+        let original_value = &original[index].value;
+
+        match item.fields.iter().filter(|f| f.name == field.name).nth(occurrence) {
+            Some(current) if &current.value == original_value => {}
+            Some(current) => edits.push(Edit {
+                range: field.span.clone(),
+                text: field_element(&current.name, &current.value),
+            }),
+            None => edits.push(removal(xml, field.span.clone())),
+        }
+    }
+
+    let added: Vec<_> = item.fields.iter()
+        .enumerate()
+        .filter(|(index, field)| {
+            let occurrence = item.fields[..*index].iter().filter(|f| f.name == field.name).count();
+            entry.fields.iter().filter(|f| f.name == field.name).count() <= occurrence
+        })
+        .map(|(_, field)| field_element(&field.name, &field.value))
+        .collect();
+    if added.is_empty() {
+        return;
+    }
+
+    let entry_indent = line_indent(xml, entry.span.start);
+    match (entry.fields.first(), entry.close_tag) {
+        // add the new fields after the last one, using the indentation of the first
+        (Some(first), _) => {
+            let separator = line_indent(xml, first.span.start)
+                .map(|indent| format!("\n{}", indent))
+                .unwrap_or_default();
+            let last = entry.fields.last().unwrap();
+            edits.push(Edit {
+                range: last.span.end..last.span.end,
+                text: added.iter().map(|element| format!("{}{}", separator, element)).collect(),
+            });
+        }
+        (None, Some(close_tag)) => {
+            let (newline, indent) = layout(entry_indent);
+            let mut text: String = added.iter().map(|element| format!("{}{}", child_prefix(newline, indent), element)).collect();
+            text.push_str(&format!("{}{}", newline, indent));
+            edits.push(Edit { range: entry.open_tag.end..close_tag, text });
+        }
+        // a self-closing <game/> or <folder/> element has to be given an end tag
+        (None, None) => {
+            let (newline, indent) = layout(entry_indent);
+            let open_tag = &xml[entry.open_tag.clone()];
+            let mut text = format!("{}>", open_tag.trim_end_matches("/>").trim_end());
+            text.extend(added.iter().map(|element| format!("{}{}", child_prefix(newline, indent), element)));
+            text.push_str(&format!("{}{}</{}>", newline, indent, item.kind.tag()));
+            edits.push(Edit { range: entry.span.clone(), text });
+        }
+    }
+}
+
+// Creates the edit which adds new items after the last entry, or at the end of the root element.
+fn new_entries_edit(xml: &str, document: &Document, items: &[&Item]) -> Edit {
+
+    if let Some(last) = document.entries.last() {
+        let (newline, indent) = layout(line_indent(xml, last.span.start));
+        return Edit {
+            range: last.span.end..last.span.end,
+            text: items.iter().map(|item| format!("{}{}{}", newline, indent, entry_element(item, newline, indent))).collect(),
+        };
+    }
+
+    let mut text: String = items.iter().map(|item| format!("\n\t{}", entry_element(item, "\n", "\t"))).collect();
+    match document.root_close {
+        Some(root_close) => {
+            // insert before any whitespace preceding the </gameList> end tag
+            let position = xml[..root_close].trim_end().len().max(document.root_open.end);
+            if position == root_close {
+                text.push('\n');
+            }
+            Edit { range: position..position, text }
+        }
+        None => {
+            let open_tag = &xml[document.root_open.clone()];
+            text.insert_str(0, &format!("{}>", open_tag.trim_end_matches("/>").trim_end()));
+            text.push_str("\n</gameList>");
+            Edit { range: document.root_open.clone(), text }
+        }
+    }
+}
+
+fn entry_element(item: &Item, newline: &str, indent: &str) -> String {
+    let mut element = format!("<{}>", item.kind.tag());
+    for field in item.fields {
+        element.push_str(&format!("{}{}", child_prefix(newline, indent), field_element(&field.name, &field.value)));
+    }
+    element.push_str(&format!("{}{}</{}>", newline, indent, item.kind.tag()));
+    element
+}
+
+// Creates a field's element, quotes are left as they are since they only need escaping in attributes.
+fn field_element(name: &str, value: &str) -> String {
+    format!("<{}>{}</{}>", name, partial_escape(value), name)
+}
+
+// Creates an edit removing a span along with the indentation and line break preceding it.
+fn removal(xml: &str, span: Range<usize>) -> Edit {
+    let before = &xml[..span.start];
+    let mut start = before.trim_end_matches([' ', '\t']).len();
+    if before[..start].ends_with('\n') {
+        start -= 1;
+        if before[..start].ends_with('\r') {
+            start -= 1;
+        }
+    }
+    Edit { range: start..span.end, text: String::new() }
+}
+
+// Returns the indentation of the line a position is on, if only whitespace precedes it on that line.
+fn line_indent(xml: &str, position: usize) -> Option<&str> {
+    let before = &xml[..position];
+    let line_start = before.rfind('\n')?;
+    let indent = &before[line_start + 1..];
+    if indent.chars().all(|c| c == ' ' || c == '\t') {
+        Some(indent)
+    } else {
+        None
+    }
+}
+
+// Elements written on their own lines follow the indentation, otherwise they are written inline.
+fn layout(indent: Option<&str>) -> (&'static str, &str) {
+    match indent {
+        Some(indent) => ("\n", indent),
+        None => ("", ""),
+    }
+}
+
+// The line break and indentation preceding a child element.
+fn child_prefix(newline: &str, indent: &str) -> String {
+    if newline.is_empty() {
+        String::new()
+    } else {
+        format!("{}{}\t", newline, indent)
+    }
+}
+
+fn apply_edits(xml: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+
+    let mut updated = String::with_capacity(xml.len());
+    let mut position = 0;
+    for edit in edits {
+        updated.push_str(&xml[position..edit.range.start]);
+        updated.push_str(&edit.text);
+        position = edit.range.end;
+    }
+    updated.push_str(&xml[position..]);
+    updated
+}
+
+// Only a single <gameList> root element is accepted
+fn check_root(name: &str, root_found: bool, position: usize) -> Result<(), Error> {
+    if root_found {
+        return Err(Error::Malformed {
+            position,
+            message: format!("unexpected second root element <{}>", name),
+        });
+    }
+    if name != "gameList" {
+        return Err(Error::UnexpectedRoot(name.to_string()));
+    }
+    Ok(())
+}
+
+fn element_name(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).into_owned()
+}
+
+fn malformed(position: usize, error: impl std::fmt::Display) -> Error {
+    Error::Malformed {
+        position,
+        message: error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(entry: &Entry) -> Vec<(&str, &str)> {
+        entry.fields.iter().map(|f| (f.name.as_str(), f.value.as_str())).collect()
+    }
+
+    // loads the games of a document the same way game_listings::new does
+    fn load_games(xml: &str) -> Vec<Game> {
+        parse(xml).unwrap().entries.iter().enumerate()
+            .filter(|(_, entry)| entry.kind == EntryKind::Game)
+            .map(|(index, entry)| {
+                let mut game = Game::new(index);
+                game.set_gamelist_entry(Some(index));
+                for field in &entry.fields {
+                    game.add_field(&field.name, field.value.clone());
+                }
+                game
+            }).collect()
+    }
+
+    fn load_folders(xml: &str) -> Vec<Folder> {
+        parse(xml).unwrap().entries.iter().enumerate()
+            .filter(|(_, entry)| entry.kind == EntryKind::Folder)
+            .map(|(index, entry)| {
+                let mut folder = Folder::new(index);
+                folder.set_gamelist_entry(Some(index));
+                for field in &entry.fields {
+                    folder.add_field(&field.name, field.value.clone());
+                }
+                folder
+            }).collect()
+    }
+
+    const GAMELIST: &str = "<?xml version=\"1.0\"?>\n\
+<gameList>\n\
+\t<provider>\n\t\t<System>snes</System>\n\t</provider>\n\
+\t<!-- scraped entries -->\n\
+\t<game id=\"123\" source=\"ScreenScraper.fr\">\n\
+\t\t<path>./a.smc</path>\n\
+\t\t<name>A</name>\n\
+\t\t<image />\n\
+\t\t<releasedate>1991</releasedate>\n\
+\t</game>\n\
+\t<folder>\n\t\t<path>./Hacks</path>\n\t</folder>\n\
+\t<game>\n\
+\t\t<name>B</name>\n\
+\t\t<path>./b.smc</path>\n\
+\t</game>\n\
+</gameList>\n";
+
+    #[test]
+    fn test_parse_simple_game() {
+        let xml = "<?xml version=\"1.0\"?>\n<gameList>\n\t<game>\n\t\t<path>./a.smc</path>\n\t\t<name>A</name>\n\t</game>\n</gameList>\n";
+        let entries = parse(xml).unwrap().entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(fields(&entries[0]), vec![("path", "./a.smc"), ("name", "A")]);
+    }
+
+    #[test]
+    fn test_parse_game_with_attributes() {
+        let xml = r#"<gameList><game id="123" source="ScreenScraper.fr"><path>./a.smc</path></game></gameList>"#;
+        let entries = parse(xml).unwrap().entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(fields(&entries[0]), vec![("path", "./a.smc")]);
+    }
+
+    #[test]
+    fn test_parse_self_closing_elements() {
+        let xml = r#"<gameList><game><path>./a.smc</path><image/><scrap name="ScreenScraper" /></game><game/></gameList>"#;
+        let entries = parse(xml).unwrap().entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(fields(&entries[0]), vec![("path", "./a.smc"), ("image", ""), ("scrap", "")]);
+        assert_eq!(entries[1].fields.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_cdata_and_nested_elements() {
+        let xml = "<gameList><game><desc><![CDATA[Fight <bosses> & win]]></desc><genre>Action<b>!</b></genre></game></gameList>";
+        let entries = parse(xml).unwrap().entries;
+        assert_eq!(fields(&entries[0]), vec![("desc", "Fight <bosses> & win"), ("genre", "Action!")]);
+    }
+
+    #[test]
+    fn test_parse_multiline_value() {
+        let xml = "<gameList><game><desc>Line one\nLine two</desc></game></gameList>";
+        let entries = parse(xml).unwrap().entries;
+        assert_eq!(fields(&entries[0]), vec![("desc", "Line one\nLine two")]);
+    }
+
+    #[test]
+    fn test_parse_ignores_other_elements() {
+        let entries = parse(GAMELIST).unwrap().entries;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(fields(&entries[2]), vec![("name", "B"), ("path", "./b.smc")]);
+    }
+
+    #[test]
+    fn test_parse_folders() {
+        let entries = parse(GAMELIST).unwrap().entries;
+        let kinds: Vec<EntryKind> = entries.iter().map(|entry| entry.kind).collect();
+        assert_eq!(kinds, vec![EntryKind::Game, EntryKind::Folder, EntryKind::Game]);
+        assert_eq!(fields(&entries[1]), vec![("path", "./Hacks")]);
+    }
+
+    #[test]
+    fn test_update_folder() {
+        let mut folders = load_folders(GAMELIST);
+        folders[0].add_field("name", "ROM Hacks".to_string());
+        let expected = GAMELIST.replace("<path>./Hacks</path>\n", "<path>./Hacks</path>\n\t\t<name>ROM Hacks</name>\n");
+        assert_eq!(update(GAMELIST, &load_games(GAMELIST), &folders).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_new_folder() {
+        let mut folder = Folder::new(0);
+        folder.add_field("path", "./Hacks".to_string());
+        let expected = "<gameList>\n\t<folder>\n\t\t<path>./Hacks</path>\n\t</folder>\n</gameList>";
+        assert_eq!(update("<gameList>\n</gameList>", &[], &[folder]).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_does_not_match_across_kinds() {
+        // a game sharing a folder's path does not replace the folder
+        let mut game = Game::new(0);
+        game.add_field("path", "./Hacks".to_string());
+        let updated = update(GAMELIST, &[game], &load_folders(GAMELIST)).unwrap().0;
+        let kinds: Vec<EntryKind> = parse(&updated).unwrap().entries.iter().map(|entry| entry.kind).collect();
+        assert_eq!(kinds, vec![EntryKind::Folder, EntryKind::Game]);
+    }
+
+    #[test]
+    fn test_parse_empty_game_list() {
+        assert_eq!(parse("<gameList/>").unwrap().entries.len(), 0);
+        assert_eq!(parse("<gameList></gameList>").unwrap().entries.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_mismatched_tags() {
+        let xml = "<gameList><game><name>A</path></game></gameList>";
+        assert!(matches!(parse(xml), Err(Error::Malformed { .. })));
+    }
+
+    #[test]
+    fn test_parse_unclosed_elements() {
+        let xml = "<gameList><game><name>A</name>";
+        assert!(matches!(parse(xml), Err(Error::Malformed { .. })));
+    }
+
+    #[test]
+    fn test_parse_wrong_root() {
+        let xml = "<games><game></game></games>";
+        assert!(matches!(parse(xml), Err(Error::UnexpectedRoot(name)) if name == "games"));
+    }
+
+    #[test]
+    fn test_parse_missing_root() {
+        assert!(matches!(parse("<?xml version=\"1.0\"?>"), Err(Error::MissingRoot)));
+    }
+
+    #[test]
+    fn test_update_unchanged() {
+        let games = load_games(GAMELIST);
+        assert_eq!(update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap().0, GAMELIST);
+    }
+
+    #[test]
+    fn test_update_changed_field() {
+        let mut games = load_games(GAMELIST);
+        games[1].change_field("name", "Bee".to_string());
+        let expected = GAMELIST.replace("<name>B</name>", "<name>Bee</name>");
+        assert_eq!(update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_self_closing_field() {
+        let mut games = load_games(GAMELIST);
+        games[0].change_field("image", "./images/a.png".to_string());
+        let expected = GAMELIST.replace("<image />", "<image>./images/a.png</image>");
+        assert_eq!(update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_added_and_removed_fields() {
+        let mut games = load_games(GAMELIST);
+        games[1].fields.remove(0);
+        games[1].add_field("genre", "Puzzle".to_string());
+        let expected = GAMELIST.replace("\t\t<name>B</name>\n\t\t<path>./b.smc</path>\n", "\t\t<path>./b.smc</path>\n\t\t<genre>Puzzle</genre>\n");
+        assert_eq!(update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_removed_game() {
+        let mut games = load_games(GAMELIST);
+        games.remove(1);
+        let expected = GAMELIST.replace("\n\t<game>\n\t\t<name>B</name>\n\t\t<path>./b.smc</path>\n\t</game>", "");
+        assert_eq!(update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_new_game() {
+        let mut games = load_games(GAMELIST);
+        let mut game = Game::new(2);
+        game.add_field("path", "./c.smc".to_string());
+        games.push(game);
+        let expected = GAMELIST.replace("</game>\n</gameList>", "</game>\n\t<game>\n\t\t<path>./c.smc</path>\n\t</game>\n</gameList>");
+        let updated = update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap().0;
+        assert_eq!(updated, expected);
+
+        // saving the same games again does not add the new game a second time
+        assert_eq!(update(&updated, &games, &load_folders(GAMELIST)).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_positions() {
+        let mut games = load_games(GAMELIST);
+        games.remove(0);
+        let mut game = Game::new(2);
+        game.add_field("path", "./c.smc".to_string());
+        games.push(game);
+        let (updated, positions) = update(GAMELIST, &games, &load_folders(GAMELIST)).unwrap();
+        // the folder now comes first, then game B and the new game C
+        assert_eq!(positions, vec![1, 2, 0]);
+        let entries = parse(&updated).unwrap().entries;
+        assert_eq!(path_value(&fields_from_entry(&entries[2])), Some("./c.smc"));
+    }
+
+    #[test]
+    fn test_update_empty_list() {
+        let mut game = Game::new(0);
+        game.add_field("path", "./a.smc".to_string());
+        let games = vec![game];
+        let expected = "<gameList>\n\t<game>\n\t\t<path>./a.smc</path>\n\t</game>\n</gameList>";
+        assert_eq!(update("<gameList></gameList>", &games, &[]).unwrap().0, expected);
+        assert_eq!(update("<gameList/>", &games, &[]).unwrap().0, expected);
+    }
+
+    #[test]
+    fn test_update_inline_game() {
+        let xml = "<gameList><game id=\"1\"/></gameList>";
+        let mut games = load_games(xml);
+        games[0].add_field("path", "./a.smc".to_string());
+        assert_eq!(update(xml, &games, &[]).unwrap().0, "<gameList><game id=\"1\"><path>./a.smc</path></game></gameList>");
+    }
+
+    // titles which have to be escaped, or could be mistaken for markup, when written
+    const SPECIAL_CHARACTER_TITLES: [&str; 14] = [
+        "Ghosts 'n Goblins",
+        "Tom & Jerry",
+        "<Beta>",
+        "Mario & Luigi: Superstar Saga",
+        "Street Fighter II' Turbo",
+        "\"Weird\" Al Yankovic",
+        "Q*bert's Qubes",
+        "AT&T <Test> & \"more\"",
+        "50% > 10 && 1 < 2",
+        "&amp; is not an entity here",
+        "&#233; and &lt;",
+        "]]> outside of CDATA",
+        "Pokémon Snap",
+        "ドラゴンクエスト",
+    ];
+
+    fn titled_game(title: &str) -> Game {
+        let mut game = Game::new(0);
+        game.add_field("path", "./a.smc".to_string());
+        game.add_field("name", title.to_string());
+        game
+    }
+
+    #[test]
+    fn test_field_element_escaping() {
+        assert_eq!(field_element("name", "Tom & Jerry"), "<name>Tom &amp; Jerry</name>");
+        assert_eq!(field_element("name", "<Beta>"), "<name>&lt;Beta&gt;</name>");
+        assert_eq!(field_element("name", "Ghosts 'n Goblins"), "<name>Ghosts 'n Goblins</name>");
+    }
+
+    #[test]
+    fn test_parse_unescapes_values() {
+        let xml = "<gameList><game><name>Tom &amp; Jerry &lt;Beta&gt; &quot;&apos;&#233;&#x41;</name></game></gameList>";
+        let entries = parse(xml).unwrap().entries;
+        assert_eq!(fields(&entries[0]), vec![("name", "Tom & Jerry <Beta> \"'éA")]);
+    }
+
+    #[test]
+    fn test_create_round_trip_special_characters() {
+        for title in SPECIAL_CHARACTER_TITLES {
+            let xml = create(&[titled_game(title)], &[]);
+            let entries = parse(&xml).unwrap().entries;
+            assert_eq!(fields(&entries[0]), vec![("path", "./a.smc"), ("name", title)], "{}", xml);
+        }
+    }
+
+    #[test]
+    fn test_update_round_trip_special_characters() {
+        let xml = create(&[titled_game("Placeholder")], &[]);
+        for title in SPECIAL_CHARACTER_TITLES {
+            let mut games = load_games(&xml);
+            games[0].change_field("name", title.to_string());
+            let updated = update(&xml, &games, &[]).unwrap().0;
+            let entries = parse(&updated).unwrap().entries;
+            assert_eq!(fields(&entries[0]), vec![("path", "./a.smc"), ("name", title)], "{}", updated);
+
+            // reading back and saving again leaves the file unchanged
+            assert_eq!(update(&updated, &load_games(&updated), &[]).unwrap().0, updated);
+        }
+    }
+
+    #[test]
+    fn test_create() {
+        let mut game = Game::new(0);
+        game.add_field("path", "./a.smc".to_string());
+        game.add_field("name", "A".to_string());
+        let expected = "<?xml version=\"1.0\"?>\n<gameList>\n\t<game>\n\t\t<path>./a.smc</path>\n\t\t<name>A</name>\n\t</game>\n</gameList>\n";
+        assert_eq!(create(&[game], &[]), expected);
+    }
+}
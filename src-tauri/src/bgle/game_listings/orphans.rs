@@ -0,0 +1,303 @@
+//! This module finds gamelist.xml entries whose ROM no longer exists.
+//!
+//! When ROMs are renamed or moved their old entries stay in gamelist.xml, along with their scraped
+//! fields, while the ROM is found again under its new path as a new, empty game. An orphaned entry
+//! can be pruned, or re-linked to the new file so its fields are kept.
+
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::structs::game::Game;
+use super::structs::game_list::GameList;
+use crate::bgle::roms;
+use crate::bgle::roms::checksums::{HashCache, RomHashes};
+use crate::bgle::roms::names;
+
+/// How a renamed or moved ROM was matched to an orphaned entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MatchedBy {
+    /// The ROM's md5 or crc32 is the one recorded by the entry.
+    Hash,
+    /// The ROM's file name is similar to the entry's name or old file name.
+    Name,
+}
+
+/// A game found on disk which is likely to be the ROM of an orphaned entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelinkCandidate {
+    pub game_id: usize,
+    pub path: String,
+    pub matched_by: MatchedBy,
+}
+
+/// An entry whose path does not lead to a ROM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Orphan {
+    pub game_id: usize,
+    pub path: String,
+    pub name: String,
+    pub candidate: Option<RelinkCandidate>,
+}
+
+/// An entry whose path is missing or was cleared when it was read because it is not a path
+/// relative to the emulator directory, such as `/userdata/roms/snes/game.smc` or `game.smc`. Its
+/// ROM may well exist, so it is never taken for an orphan.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvalidPath {
+    pub game_id: usize,
+    pub name: String,
+    /// Why the path is invalid, empty if the entry has no path.
+    pub message: String,
+}
+
+// A game found on disk which is not yet listed in gamelist.xml.
+struct Candidate {
+    game_id: usize,
+    path: String,
+    hashes: Option<RomHashes>,
+}
+
+impl Candidate {
+    fn relink(&self, matched_by: MatchedBy) -> RelinkCandidate {
+        RelinkCandidate { game_id: self.game_id, path: self.path.clone(), matched_by }
+    }
+}
+
+/// Sets the orphan status of each game, a game is orphaned if its path does not lead to a file or
+/// directory within the emulator directory. Games without a valid path are not orphaned, they are
+/// listed by [`find_invalid_paths`] instead.
+pub fn mark_orphans(emulator_dir: &Path, game_list: &mut GameList) {
+    for game in game_list.games.iter_mut() {
+        let orphaned = match game.field_value("path") {
+            Some(path) if !path.is_empty() => !roms::rom_file_path(emulator_dir, path).exists(),
+            _ => false,
+        };
+        game.set_orphaned(orphaned);
+    }
+}
+
+/// Lists the games whose path is missing or invalid, these have to be fixed by hand.
+pub fn find_invalid_paths(game_list: &GameList) -> Vec<InvalidPath> {
+    game_list.games.iter()
+        .filter(|game| game.field_value("path").unwrap_or_default().is_empty())
+        .map(|game| InvalidPath {
+            game_id: game.game_id(),
+            name: game.field_value("name").unwrap_or_default().to_string(),
+            message: game.fields.iter()
+                .find(|field| field.name == "path")
+                .map(|field| field.format_error_message.clone())
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Lists the orphaned games of a game list, along with the game on disk each could be re-linked to.
+///
+/// Only games which are not yet listed in gamelist.xml are offered as candidates. A candidate whose
+/// hash matches the one recorded by the orphan is preferred over one whose name is similar.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its directory must be the emulator directory.
+/// ### Returns
+/// * `Vec<Orphan>` - The orphaned games, in the order they appear in the game list.
+pub fn find_orphans(game_list: &mut GameList) -> Vec<Orphan> {
+    let emulator_dir = game_list.directory.clone();
+    mark_orphans(&emulator_dir, game_list);
+
+    let mut cache = HashCache::load(&emulator_dir);
+    let candidates: Vec<Candidate> = game_list.games.iter()
+        .filter(|game| !game.is_orphaned() && game.gamelist_entry().is_none())
+        .filter_map(|game| {
+            let path = game.field_value("path")?.to_string();
+            let inner_file = game.archive().and_then(|archive| archive.inner_rom()).map(|rom| rom.name.clone());
+            let rom_path = roms::rom_file_path(&emulator_dir, &path);
+            let hashes = if rom_path.is_file() { cache.hashes(&rom_path, &path, inner_file.as_deref()).ok() } else { None };
+            Some(Candidate { game_id: game.game_id(), path, hashes })
+        })
+        .collect();
+    let _ = cache.save();
+
+    let mut orphans = Vec::<Orphan>::new();
+    for game in game_list.games.iter().filter(|game| game.is_orphaned()) {
+        let by_hash = candidates.iter()
+            .find(|candidate| candidate.hashes.as_ref().map(|hashes| hashes_match(game, hashes)).unwrap_or(false))
+            .map(|candidate| candidate.relink(MatchedBy::Hash));
+        let candidate = by_hash.or_else(|| {
+            candidates.iter()
+                .find(|candidate| name_matches(game, &candidate.path))
+                .map(|candidate| candidate.relink(MatchedBy::Name))
+        });
+
+        orphans.push(Orphan {
+            game_id: game.game_id(),
+            path: game.field_value("path").unwrap_or_default().to_string(),
+            name: game.field_value("name").unwrap_or_default().to_string(),
+            candidate,
+        });
+    }
+    orphans
+}
+
+/// Removes the orphaned games from a game list, returning the number removed. Games without a valid
+/// path are never removed.
+pub fn prune_orphans(game_list: &mut GameList) -> usize {
+    let count = game_list.games.len();
+    game_list.games.retain(|game| !game.is_orphaned());
+    count - game_list.games.len()
+}
+
+/// Re-links an orphaned game to the ROM of another game, which is then removed from the list. The
+/// orphan keeps its fields and position within gamelist.xml, only its path is changed.
+///
+/// ### Arguments
+/// * `game_list` - The game list holding both games.
+/// * `orphan_id` - The id of the orphaned game.
+/// * `candidate_id` - The id of the game whose ROM the orphan should point to.
+/// ### Returns
+/// * `bool` - False if either game is not in the list or the first game is not orphaned.
+pub fn relink(game_list: &mut GameList, orphan_id: usize, candidate_id: usize) -> bool {
+    let orphan = game_list.games.iter().position(|game| game.game_id() == orphan_id && game.is_orphaned());
+    let candidate = game_list.games.iter().position(|game| game.game_id() == candidate_id && !game.is_orphaned());
+    let (orphan, candidate) = match (orphan, candidate) {
+        (Some(orphan), Some(candidate)) if orphan != candidate => (orphan, candidate),
+        _ => return false,
+    };
+
+    let path = game_list.games[candidate].field_value("path").unwrap_or_default().to_string();
+    let archive = game_list.games[candidate].archive().cloned();
+    let game = &mut game_list.games[orphan];
+    game.set_field("path", path);
+    game.set_archive(archive);
+    game.set_orphaned(false);
+    game_list.remove(candidate_id);
+    true
+}
+
+// True if the md5 or crc32 recorded by a game is that of the candidate's ROM.
+fn hashes_match(game: &Game, hashes: &RomHashes) -> bool {
+    let recorded_md5 = game.field_value("md5").unwrap_or_default();
+    let recorded_crc32 = game.field_value("crc32").unwrap_or_default();
+    (!recorded_md5.is_empty() && recorded_md5.eq_ignore_ascii_case(&hashes.md5))
+        || (!recorded_crc32.is_empty() && recorded_crc32.eq_ignore_ascii_case(&hashes.crc32))
+}
+
+// True if the candidate's file name is similar to the game's name or to its old file name.
+fn name_matches(game: &Game, candidate_path: &str) -> bool {
+    let candidate = names::normalized_file_name(candidate_path);
+    let old_name = names::normalized_file_name(game.field_value("path").unwrap_or_default());
+
+    (!old_name.is_empty() && names::similarity(&old_name, &candidate) >= names::NAME_MATCH_THRESHOLD)
+        || names::names_match(game.field_value("name").unwrap_or_default(), &candidate)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use std::fs;
+    use crate::bgle::game_listings;
+
+    const GAMELIST: &str = r#"<?xml version="1.0"?>
+<gameList>
+	<game>
+		<path>./Kept.smc</path>
+		<name>Kept</name>
+	</game>
+	<game>
+		<path>./Old Name.smc</path>
+		<name>Hashed</name>
+		<md5>9e107d9d372bb6826bd81d3542a419d6</md5>
+	</game>
+	<game>
+		<path>./Super Mario World.smc</path>
+		<name>Super Mario World</name>
+	</game>
+	<game>
+		<path>./Gone.smc</path>
+		<name>Gone</name>
+	</game>
+	<game>
+		<path>/userdata/roms/snes/Absolute.smc</path>
+		<name>Absolute</name>
+		<desc>Scraped</desc>
+	</game>
+	<game>
+		<name>No Path</name>
+	</game>
+</gameList>
+"#;
+
+    // Creates an emulator directory where three of the four listed games have been renamed or removed.
+    fn emulator_directory(name: &str) -> TempDir {
+        let directory = TempDir::new(&format!("orphans-{}", name));
+        fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc\"\n").unwrap();
+        fs::write(directory.join("gamelist.xml"), GAMELIST).unwrap();
+        fs::write(directory.join("Kept.smc"), "kept").unwrap();
+        fs::write(directory.join("Renamed.smc"), "The quick brown fox jumps over the lazy dog").unwrap();
+        fs::write(directory.join("Super Mario World (USA).smc"), "mario").unwrap();
+        directory
+    }
+
+    fn game_index(game_list: &GameList, id: usize) -> usize {
+        game_list.games.iter().position(|game| game.game_id() == id).unwrap()
+    }
+
+    #[test]
+    fn test_find_orphans() {
+        let directory = emulator_directory("find");
+        let mut game_list = game_listings::new(directory.to_str().unwrap()).unwrap();
+        assert_eq!(game_list.games.iter().filter(|game| game.is_orphaned()).count(), 3);
+
+        let orphans = find_orphans(&mut game_list);
+        assert_eq!(orphans.len(), 3);
+        assert_eq!(orphans[0].path, "./Old Name.smc");
+        let candidate = orphans[0].candidate.as_ref().unwrap();
+        assert_eq!((candidate.path.as_str(), &candidate.matched_by), ("./Renamed.smc", &MatchedBy::Hash));
+        let candidate = orphans[1].candidate.as_ref().unwrap();
+        assert_eq!((candidate.path.as_str(), &candidate.matched_by), ("./Super Mario World (USA).smc", &MatchedBy::Name));
+        assert_eq!(orphans[2].name, "Gone");
+        assert_eq!(orphans[2].candidate, None);
+    }
+
+    #[test]
+    fn test_relink_and_prune() {
+        let directory = emulator_directory("relink");
+        let mut game_list = game_listings::new(directory.to_str().unwrap()).unwrap();
+        let orphans = find_orphans(&mut game_list);
+        let count = game_list.games.len();
+
+        let candidate = orphans[0].candidate.as_ref().unwrap();
+        assert!(relink(&mut game_list, orphans[0].game_id, candidate.game_id));
+        assert!(!relink(&mut game_list, orphans[0].game_id, candidate.game_id));
+        assert_eq!(game_list.games.len(), count - 1);
+
+        let game = &game_list.games[game_index(&game_list, orphans[0].game_id)];
+        assert_eq!(game.field_value("path"), Some("./Renamed.smc"));
+        assert_eq!(game.field_value("name"), Some("Hashed"));
+        assert!(!game.is_orphaned());
+
+        assert_eq!(prune_orphans(&mut game_list), 2);
+        assert!(game_list.games.iter().all(|game| !game.is_orphaned()));
+    }
+
+    #[test]
+    fn test_invalid_paths_are_not_pruned() {
+        let directory = emulator_directory("invalid");
+        let mut game_list = game_listings::new(directory.to_str().unwrap()).unwrap();
+        find_orphans(&mut game_list);
+
+        let invalid = find_invalid_paths(&game_list);
+        let names: Vec<&str> = invalid.iter().map(|game| game.name.as_str()).collect();
+        assert_eq!(names, vec!["Absolute", "No Path"]);
+        assert_eq!(invalid[0].message, "Must be a file path.");
+        assert_eq!(invalid[1].message, "");
+
+        // the absolute path was cleared when it was read, the entry and its fields are kept
+        prune_orphans(&mut game_list);
+        let game = game_list.get(invalid[0].game_id).unwrap();
+        assert!(!game.is_orphaned());
+        assert_eq!(game.field_value("desc"), Some("Scraped"));
+        assert!(game_list.get(invalid[1].game_id).is_some());
+    }
+}
@@ -1,2 +1,3 @@
 pub mod game_list;
-pub mod game;
\ No newline at end of file
+pub mod game;
+pub mod folder;
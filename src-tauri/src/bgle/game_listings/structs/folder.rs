@@ -0,0 +1,131 @@
+//! This module contains the Folder struct, which represents a `<folder>` entry within gamelist.xml.
+//! Batocera uses folder entries to give a subdirectory of an emulator directory its own name,
+//! artwork and description.
+
+use serde::{Serialize, Deserialize};
+use super::game::{Field, FieldType};
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Folder {
+    been_edited: bool,
+    id: usize,
+    // The position of the folder's <folder> element within gamelist.xml, None if it has not been saved.
+    gamelist_entry: Option<usize>,
+    pub fields: Vec<Field>,
+}
+
+impl Folder {
+
+    pub fn new(id: usize) -> Folder {
+        Folder {
+            been_edited: false,
+            id,
+            gamelist_entry: None,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn add_field(&mut self, name: &str, value: String) {
+        let field = Field::new(name.to_string(), value, assign_a_folder_field_type(name), true);
+        self.been_edited = true;
+        self.fields.push(field);
+    }
+
+    pub fn change_field(&mut self, field_name: &str, value: String) {
+        if let Some(index) = self.fields.iter().position(|f| f.name.as_str() == field_name) {
+            let field = Field::new(field_name.to_string(), value, assign_a_folder_field_type(field_name), true);
+            self.been_edited = true;
+            self.fields[index] = field;
+        }
+    }
+
+    /// Changes the value of a field, adding the field if the folder does not have it.
+    pub fn set_field(&mut self, field_name: &str, value: String) {
+        if self.fields.iter().any(|f| f.name.as_str() == field_name) {
+            self.change_field(field_name, value);
+        }
+        else {
+            self.add_field(field_name, value);
+        }
+    }
+
+    /// Returns the value of a field, None if the folder does not have the field.
+    pub fn field_value(&self, field_name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|f| f.name.as_str() == field_name)
+            .map(|f| f.value.as_str())
+    }
+
+    pub fn folder_id(&self) -> usize {
+        self.id
+    }
+
+    /// Changes the folder's id, which must not be the id of another folder of its list.
+    pub fn set_folder_id(&mut self, id: usize) {
+        self.id = id;
+    }
+
+    /// Returns the position of the folder's entry within gamelist.xml, if it was read from the file.
+    pub fn gamelist_entry(&self) -> Option<usize> {
+        self.gamelist_entry
+    }
+
+    pub fn set_gamelist_entry(&mut self, entry: Option<usize>) {
+        self.gamelist_entry = entry;
+    }
+
+    pub fn has_been_edited(&mut self) -> bool {
+        if self.been_edited {
+            self.been_edited = false;
+            true
+        }
+        else {
+            false
+        }
+    }
+}
+
+// Folders only describe a directory, so they have fewer fields than a game and none are read only.
+fn assign_a_folder_field_type(field_name: &str) -> FieldType {
+    match field_name {
+        "path" | "image" | "thumbnail" | "marquee" | "video" | "fanart" => FieldType::File,
+        "desc" => FieldType::MultilineText,
+        "releasedate" => FieldType::Date,
+        "rating" => FieldType::Float,
+        _ => FieldType::LineText,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_assign_a_folder_field_type() {
+        assert_eq!(assign_a_folder_field_type("image"), FieldType::File);
+        assert_eq!(assign_a_folder_field_type("desc"), FieldType::MultilineText);
+        assert_eq!(assign_a_folder_field_type("name"), FieldType::LineText);
+    }
+
+    #[test]
+    fn test_add_field_to_folder() {
+        let mut folder = Folder::new(1);
+        folder.add_field("path", "./Hacks".to_string());
+        folder.add_field("image", "not a path".to_string());
+        assert_eq!(folder.fields[0].value, "./Hacks");
+        assert_eq!(folder.fields[1].value, "");
+        assert!(folder.fields[1].editable);
+    }
+
+    #[test]
+    fn test_change_folder_field() {
+        let mut folder = Folder::new(1);
+        folder.add_field("name", "Hacks".to_string());
+        assert!(folder.has_been_edited());
+        folder.change_field("name", "ROM Hacks".to_string());
+        assert_eq!(folder.fields[0].value, "ROM Hacks");
+        assert!(folder.has_been_edited());
+    }
+}
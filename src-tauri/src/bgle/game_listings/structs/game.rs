@@ -3,7 +3,10 @@ use serde::ser::{SerializeStruct, SerializeSeq};
 use std::path::Path;
 use regex::Regex;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+use crate::bgle::roms::archives::ArchiveInfo;
+use crate::bgle::roms::checksums::RomHashes;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FieldType {
     LineText,
     Integer,
@@ -21,9 +24,10 @@ pub enum Error {
     ParseError,
     NotFound,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Field {
     pub name: String,
+    // the unescaped text of the field, escaping is left to the gamelist.xml writer
     pub value: String,
     pub editable: bool,
     pub field_type: FieldType,
@@ -31,7 +35,7 @@ pub struct Field {
 }
 
 impl Field {
-    fn new(name: String, value: String, field_type: FieldType, editable: bool) -> Field {
+    pub(super) fn new(name: String, value: String, field_type: FieldType, editable: bool) -> Field {
         
         let mut value = value;
         let mut is_valid_value = false;
@@ -138,7 +142,8 @@ impl Field {
             },
             FieldType::File => {
                 if let Ok(_) = value.parse::<String>() {
-                    let file_regex = Regex::new(r"^\.(/[\w _\-()]+)*(\.[A-z0-9]+)?$").unwrap();
+                    // relative paths such as ./Hacks/Super Mario World (USA, Europe) (Rev 1) [!].sfc
+                    let file_regex = Regex::new(r"^\.(/[^/\x00-\x1f]+)*$").unwrap();
                     if file_regex.is_match(&value) {
                         is_valid_value = true;
                     }
@@ -170,10 +175,21 @@ impl Field {
 
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Game {
     been_edited: bool,
     id: usize,
+    // The position of the game's <game> element within gamelist.xml, None if it has not been saved.
+    gamelist_entry: Option<usize>,
+    // The checksums of the game's ROM, None until the ROM has been hashed.
+    #[serde(default)]
+    hashes: Option<RomHashes>,
+    // The ROMs found inside the game's .zip or .7z archive, None if the game is not archived.
+    #[serde(default)]
+    archive: Option<ArchiveInfo>,
+    // True when the game's path does not lead to a file within the emulator directory.
+    #[serde(default)]
+    orphaned: bool,
     pub fields: Vec<Field>,
 }
 
@@ -186,6 +202,10 @@ impl Game {
         Game {
             been_edited: false,
             id,
+            gamelist_entry: None,
+            hashes: None,
+            archive: None,
+            orphaned: false,
             fields: Vec::new()
         }
     }
@@ -226,16 +246,77 @@ impl Game {
            
             let field = Field::new(field_name.to_string(), value, field_type, is_editable);
             self.been_edited = true;
-            self.fields.swap_remove(index);
-            self.fields.push(field);
+            self.fields[index] = field;
+        }
+
+    }
+
+    /// Changes the value of a field, adding the field if the game does not have it yet.
+    pub fn set_field(&mut self, field_name: &str, value: String) {
+        if self.fields.iter().any(|f| f.name.as_str() == field_name) {
+            self.change_field(field_name, value);
+        }
+        else {
+            self.add_field(field_name, value);
         }
+    }
 
+    /// Returns the value of a field, None if the game does not have the field.
+    pub fn field_value(&self, field_name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|f| f.name.as_str() == field_name)
+            .map(|f| f.value.as_str())
     }
 
     pub fn game_id(&self) -> usize {
         self.id
     }
 
+    /// Changes the game's id, which must not be the id of another game of its list.
+    pub fn set_game_id(&mut self, id: usize) {
+        self.id = id;
+    }
+
+    /// Returns the position of the game's entry within gamelist.xml, if it was read from the file.
+    pub fn gamelist_entry(&self) -> Option<usize> {
+        self.gamelist_entry
+    }
+
+    pub fn set_gamelist_entry(&mut self, entry: Option<usize>) {
+        self.gamelist_entry = entry;
+    }
+
+    /// Returns the checksums of the game's ROM, if it has been hashed.
+    pub fn hashes(&self) -> Option<&RomHashes> {
+        self.hashes.as_ref()
+    }
+
+    /// Records the checksums of the game's ROM and fills in its crc32 and md5 fields.
+    pub fn set_hashes(&mut self, hashes: RomHashes) {
+        // gamelist.xml files written by Batocera's scraper hold the crc32 in upper case
+        self.set_field("crc32", hashes.crc32.to_uppercase());
+        self.set_field("md5", hashes.md5.clone());
+        self.hashes = Some(hashes);
+    }
+
+    /// Returns the contents of the game's archive, if its ROM is archived.
+    pub fn archive(&self) -> Option<&ArchiveInfo> {
+        self.archive.as_ref()
+    }
+
+    pub fn set_archive(&mut self, archive: Option<ArchiveInfo>) {
+        self.archive = archive;
+    }
+
+    /// Returns true if the game's ROM could not be found when the game list was last checked.
+    pub fn is_orphaned(&self) -> bool {
+        self.orphaned
+    }
+
+    pub fn set_orphaned(&mut self, orphaned: bool) {
+        self.orphaned = orphaned;
+    }
+
     pub fn has_been_edited(&mut self) -> bool {
         if self.been_edited {
             self.been_edited = false;
@@ -395,6 +476,54 @@ mod test {
         assert_eq!(game.fields[0].value, "test2");
     }
 
+    #[test]
+    fn test_change_field_keeps_order() {
+        let mut game = super::Game::new(1);
+        game.add_field("name", "test".to_string());
+        game.add_field("genre", "test".to_string());
+        game.change_field("name", "test2".to_string());
+        assert_eq!(game.fields[0].name, "name");
+        assert_eq!(game.fields[1].name, "genre");
+    }
+
+    #[test]
+    fn test_file_field_paths() {
+        let mut game = super::Game::new(1);
+        game.add_field("path", "./Hacks/Super Mario World (USA, Europe) (Rev 1) [!].sfc".to_string());
+        game.add_field("image", "./images/Tom & Jerry's Game-image.png".to_string());
+        game.add_field("video", "videos/a-video.mp4".to_string());
+        assert_eq!(game.fields[0].value, "./Hacks/Super Mario World (USA, Europe) (Rev 1) [!].sfc");
+        assert_eq!(game.fields[1].value, "./images/Tom & Jerry's Game-image.png");
+        assert_eq!(game.fields[2].value, "");
+        assert_eq!(game.fields[2].format_error_message, "Must be a file path.");
+    }
+
+    #[test]
+    fn test_set_field() {
+        let mut game = super::Game::new(1);
+        game.set_field("name", "test".to_string());
+        game.set_field("name", "test2".to_string());
+        assert_eq!(game.fields.len(), 1);
+        assert_eq!(game.field_value("name"), Some("test2"));
+        assert_eq!(game.field_value("genre"), None);
+    }
+
+    #[test]
+    fn test_set_hashes() {
+        let mut game = super::Game::new(1);
+        game.add_field("path", "./game.smc".to_string());
+        game.set_hashes(crate::bgle::roms::checksums::RomHashes {
+            crc32: "414fa339".to_string(),
+            md5: "9e107d9d372bb6826bd81d3542a419d6".to_string(),
+            sha1: "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12".to_string(),
+            size: 43,
+        });
+        assert_eq!(game.field_value("crc32"), Some("414FA339"));
+        assert_eq!(game.field_value("md5"), Some("9e107d9d372bb6826bd81d3542a419d6"));
+        assert!(!game.fields[1].editable);
+        assert_eq!(game.hashes().unwrap().size, 43);
+    }
+
     #[test]
     fn test_has_been_edited() {
         let mut game = super::Game::new(1);
@@ -1,7 +1,10 @@
 //! This module contains the GameList struct and its methods
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use super::game::Game;
+use super::folder::Folder;
+use crate::bgle::game_listings::conflicts::LoadedFile;
 use serde::{Serialize, Deserialize};
 use serde::ser::{SerializeStruct, SerializeSeq};
 
@@ -11,15 +14,54 @@ pub fn new() -> GameList {
         directory: PathBuf::new(),
         emulator: String::new(),
         games: Vec::<Game>::new(),
+        folders: Vec::<Folder>::new(),
+        loaded: None,
+        next_game_id: 0,
+        next_folder_id: 0,
     }
 }
 
-/// This struct represent a list of games. It provides the emulator name and root directory.
-#[derive(Debug, Serialize, Deserialize)]
+/// This struct represent a list of games. It provides the emulator name and root directory,
+/// along with any folders described within the gamelist.xml file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameList {
     pub directory: PathBuf,
     pub emulator: String,
     pub games: Vec<Game>,
+    pub folders: Vec<Folder>,
+    /// The gamelist.xml file as it was when the list was loaded or last saved, None if there was no file.
+    /// It is only held by the backend, the frontend is sent its fingerprint instead.
+    #[serde(skip)]
+    pub loaded: Option<LoadedFile>,
+    // The id given to the next game added. Ids are never reused, so a game keeps its id when the
+    // games before it are removed or the games are sorted.
+    #[serde(skip)]
+    next_game_id: usize,
+    // The id given to the next folder added, folder ids are never reused either.
+    #[serde(skip)]
+    next_folder_id: usize,
+}
+
+// Returns the id each item of a list read again takes, the id of the previous item with the same
+// path or else a new one, along with the next id to give.
+fn kept_ids<'a>(previous: impl Iterator<Item = (Option<&'a str>, usize)>, paths: impl Iterator<Item = Option<&'a str>>, mut next_id: usize) -> (Vec<usize>, usize) {
+    // items sharing a path take the previous ids in the order they are listed
+    let mut previous_ids = HashMap::<&str, Vec<usize>>::new();
+    for (path, id) in previous {
+        if let Some(path) = path.filter(|path| !path.is_empty()) {
+            previous_ids.entry(path).or_default().insert(0, id);
+        }
+    }
+
+    let ids = paths.map(|path| {
+        path.and_then(|path| previous_ids.get_mut(path))
+            .and_then(|ids| ids.pop())
+            .unwrap_or_else(|| {
+                next_id += 1;
+                next_id - 1
+            })
+    }).collect();
+    (ids, next_id)
 }
 
 /// This enum is used to specify the type of search to be performed on the GameList struct.
@@ -31,25 +73,82 @@ pub enum SearchGames {
 
 impl GameList {
 
-    /// This method adds a game to the GameList struct.
+    /// This method adds a game to the GameList struct, with an id no other game of the list has had.
     pub fn add_game_entry(&mut self) {
-        let mut game = Game::new(self.games.len() as usize);
+        let game = Game::new(self.new_game_id());
         self.games.push(game);
     }
 
-    /// This method removes a game from the GameList struct.
-    pub fn remove(&mut self, index: usize) {
-        self.games.remove(index);
+    /// This method adds a folder to the GameList struct, with an id no other folder of the list has had.
+    pub fn add_folder_entry(&mut self) {
+        let folder = Folder::new(self.new_folder_id());
+        self.folders.push(folder);
     }
 
-    /// This method returns a reference to a game in the GameList struct.
-    pub fn get(&self, index: usize) -> &Game {
-        &self.games[index]
+    /// Returns an id for a new game, one no other game of the list has had.
+    pub fn new_game_id(&mut self) -> usize {
+        let id = self.next_game_id;
+        self.next_game_id += 1;
+        id
+    }
+
+    /// Returns an id for a new folder, one no other folder of the list has had.
+    pub fn new_folder_id(&mut self) -> usize {
+        let id = self.next_folder_id;
+        self.next_folder_id += 1;
+        id
+    }
+
+    /// This method removes a game from the GameList struct, the other games keep their ids.
+    ///
+    /// ### Arguments
+    /// * `id` - The id of the game.
+    /// ### Returns
+    /// * `Option<Game>` - The removed game, None if the list has no game with the id.
+    pub fn remove(&mut self, id: usize) -> Option<Game> {
+        let index = self.games.iter().position(|game| game.game_id() == id)?;
+        Some(self.games.remove(index))
+    }
+
+    /// This method returns a reference to a game in the GameList struct, None if the list has no
+    /// game with the id.
+    pub fn get(&self, id: usize) -> Option<&Game> {
+        self.games.iter().find(|game| game.game_id() == id)
+    }
+
+    /// This method returns a mutable reference to a game in the GameList struct, None if the list
+    /// has no game with the id.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Game> {
+        self.games.iter_mut().find(|game| game.game_id() == id)
+    }
+
+    /// Gives the games and folders of a list read again from disk the ids they had in the list read
+    /// before, so the frontend's ids still lead to the same games and folders. They are matched by
+    /// their path, those which are new are given ids the previous list never gave.
+    ///
+    /// ### Arguments
+    /// * `previous` - The list read before from the same directory.
+    pub fn keep_ids(&mut self, previous: &GameList) {
+        let (ids, next_game_id) = kept_ids(
+            previous.games.iter().map(|game| (game.field_value("path"), game.game_id())),
+            self.games.iter().map(|game| game.field_value("path")),
+            previous.next_game_id,
+        );
+        self.games.iter_mut().zip(ids).for_each(|(game, id)| game.set_game_id(id));
+        self.next_game_id = next_game_id;
+
+        let (ids, next_folder_id) = kept_ids(
+            previous.folders.iter().map(|folder| (folder.field_value("path"), folder.folder_id())),
+            self.folders.iter().map(|folder| folder.field_value("path")),
+            previous.next_folder_id,
+        );
+        self.folders.iter_mut().zip(ids).for_each(|(folder, id)| folder.set_folder_id(id));
+        self.next_folder_id = next_folder_id;
     }
 
     /// This search method allows the user to search specific fields of a game in the GameList struct.
     /// The search_type parameter specifies the field to be searched and the search_term parameter
-    /// specifies the value to be searched for. 
+    /// specifies the value to be searched for. The id of the first game found is returned.
     pub fn search(&self, search_type: SearchGames, search_term: &str) -> Option<usize>{
         
         
@@ -97,6 +196,19 @@ mod test {
         assert_eq!(game_list.games.len(), 1);
     }
 
+    #[test]
+    fn test_add_folder_entry() {
+        let mut game_list = new();
+        game_list.add_folder_entry();
+        assert_eq!(game_list.folders.len(), 1);
+        assert_eq!(game_list.games.len(), 0);
+
+        // a removed folder's id is not given again
+        game_list.folders.clear();
+        game_list.add_folder_entry();
+        assert_eq!(game_list.folders[0].folder_id(), 1);
+    }
+
     #[test]
     fn test_remove() {
         let mut game_list = new();
@@ -109,10 +221,64 @@ mod test {
     fn test_get() {
         let mut game_list = new();
         game_list.add_game_entry();
-        let game = game_list.get(0);
+        let game = game_list.get(0).unwrap();
         assert_eq!(game.game_id(), 0);
     }
 
+    #[test]
+    fn test_ids_survive_removal_and_sorting() {
+        let mut game_list = new();
+        for name in ["C", "B", "A"] {
+            game_list.add_game_entry();
+            game_list.games.last_mut().unwrap().add_field("name", name.to_string());
+        }
+        assert_eq!(game_list.remove(0).unwrap().field_value("name"), Some("C"));
+        assert!(game_list.remove(0).is_none());
+
+        // the ids found by a search still lead to the same games once the others have moved
+        let id = game_list.search(SearchGames::Name, "A").unwrap();
+        game_list.games.sort_by(|a, b| a.field_value("name").cmp(&b.field_value("name")));
+        assert_eq!(game_list.get(id).unwrap().field_value("name"), Some("A"));
+        assert_eq!(game_list.get(1).unwrap().field_value("name"), Some("B"));
+
+        // a removed game's id is not given again
+        game_list.add_game_entry();
+        assert_eq!(game_list.games.last().unwrap().game_id(), 3);
+    }
+
+    #[test]
+    fn test_keep_ids() {
+        let mut previous = new();
+        for path in ["./a.smc", "./b.smc", "./b.smc"] {
+            previous.add_game_entry();
+            previous.games.last_mut().unwrap().add_field("path", path.to_string());
+        }
+        previous.remove(0);
+
+        let mut reloaded = new();
+        for path in ["./c.smc", "./b.smc", "./a.smc", "./b.smc"] {
+            reloaded.add_game_entry();
+            reloaded.games.last_mut().unwrap().add_field("path", path.to_string());
+        }
+        for path in ["./Hacks", "./Sets"] {
+            previous.add_folder_entry();
+            previous.folders.last_mut().unwrap().add_field("path", path.to_string());
+        }
+        for path in ["./Sets", "./Betas"] {
+            reloaded.add_folder_entry();
+            reloaded.folders.last_mut().unwrap().add_field("path", path.to_string());
+        }
+        reloaded.keep_ids(&previous);
+        let ids: Vec<usize> = reloaded.games.iter().map(|game| game.game_id()).collect();
+        assert_eq!(ids, vec![3, 1, 4, 2]);
+        reloaded.add_game_entry();
+        assert_eq!(reloaded.games.last().unwrap().game_id(), 5);
+
+        // folders keep their ids the same way, a new folder does not take a previous folder's id
+        let ids: Vec<usize> = reloaded.folders.iter().map(|folder| folder.folder_id()).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
     #[test]
     fn test_search() {
         let mut game_list = new();
@@ -0,0 +1,335 @@
+//! This module links games to the artwork and videos stored in an emulator directory.
+//!
+//! Batocera's scrapers store a game's media in the emulator directory's media folders, named after
+//! the ROM with a suffix for the kind of media, for example `images/<rom>-image.png`,
+//! `images/<rom>-thumb.png`, `images/<rom>-marquee.png` and `videos/<rom>-video.mp4`.
+
+pub mod import;
+pub mod thumbnails;
+pub mod validation;
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::roms;
+
+/// The kinds of media a game can link to, each stored in a field of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MediaKind {
+    Image,
+    Thumbnail,
+    Marquee,
+    Video,
+}
+
+impl MediaKind {
+
+    pub const ALL: [MediaKind; 4] = [MediaKind::Image, MediaKind::Thumbnail, MediaKind::Marquee, MediaKind::Video];
+
+    /// Returns the kind of media stored in a game field, None if the field does not hold media.
+    pub fn from_field(field_name: &str) -> Option<MediaKind> {
+        MediaKind::ALL.iter().copied().find(|kind| kind.field() == field_name)
+    }
+
+    /// The name of the game field which links to this kind of media.
+    pub fn field(&self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Thumbnail => "thumbnail",
+            MediaKind::Marquee => "marquee",
+            MediaKind::Video => "video",
+        }
+    }
+
+    /// The media folder, within the emulator directory, which holds this kind of media.
+    pub fn directory(&self) -> &'static str {
+        match self {
+            MediaKind::Video => "videos",
+            _ => "images",
+        }
+    }
+
+    /// The suffix added to the ROM's name, eg `-thumb` in `images/<rom>-thumb.png`.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            MediaKind::Image => "-image",
+            MediaKind::Thumbnail => "-thumb",
+            MediaKind::Marquee => "-marquee",
+            MediaKind::Video => "-video",
+        }
+    }
+
+    /// The file extensions accepted for this kind of media, in lower case.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            MediaKind::Video => &["mp4"],
+            _ => &["png", "jpg", "jpeg"],
+        }
+    }
+
+    /// Returns the extension to save media of this kind with, the accepted extension matching the
+    /// one given or the first accepted extension if it is not accepted. Extensions reported by a
+    /// server are never used as they are, as they end up in file paths.
+    pub fn accepted_extension(&self, extension: &str) -> &'static str {
+        let extensions = self.extensions();
+        extensions.iter()
+            .find(|accepted| accepted.eq_ignore_ascii_case(extension))
+            .unwrap_or(&extensions[0])
+    }
+
+    /// Returns the conventional gamelist.xml path of a ROM's media, eg `./images/Game (USA)-image.png`.
+    pub fn gamelist_path(&self, rom_name: &str, extension: &str) -> String {
+        format!("./{}/{}{}.{}", self.directory(), rom_name, self.suffix(), extension)
+    }
+
+    /// Returns the gamelist.xml path of a ROM's media, within the subdirectory of the media folder
+    /// matching the ROM's, eg `./images/Hacks/Game (USA)-image.png` for `./Hacks/Game (USA).sfc`,
+    /// so that ROMs of the same name in different folders keep their own media.
+    ///
+    /// ### Returns
+    /// * `Option<String>` - The path, None if the ROM path has no file name.
+    pub fn rom_media_path(&self, rom_path: &str, extension: &str) -> Option<String> {
+        let rom_name = rom_name(rom_path)?;
+        let mut folders = String::new();
+        for component in Path::new(rom_path).parent().map(Path::components).into_iter().flatten() {
+            // only the folders are kept, a `..` cannot lead out of the media folder
+            if let Component::Normal(folder) = component {
+                folders.push_str(&folder.to_string_lossy());
+                folders.push('/');
+            }
+        }
+        Some(self.gamelist_path(&format!("{}{}", folders, rom_name), extension))
+    }
+}
+
+/// The folders, within an emulator directory, which hold the media of its games.
+pub const MEDIA_FOLDERS: [&str; 2] = ["images", "videos"];
+
+/// A media file proposed for a game's field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaLink {
+    pub game_id: usize,
+    pub field: String,
+    /// The path of the media file, as it is written in gamelist.xml.
+    pub path: String,
+    /// The value of the field before linking, empty if the field is not set.
+    pub current: String,
+}
+
+/// The links proposed for a game list and the media files which match no game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaReport {
+    pub links: Vec<MediaLink>,
+    pub unmatched: Vec<String>,
+}
+
+/// Returns the name used for a ROM's media, its file name without the extension.
+pub fn rom_name(rom_path: &str) -> Option<String> {
+    Path::new(rom_path).file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.to_string())
+}
+
+/// Scans an emulator directory's media folders and proposes a link for each media file named
+/// after a game's ROM, at the path [`MediaKind::rom_media_path`] gives it. A media file within a
+/// subfolder only matches the ROMs of the same subfolder. Links are only proposed where they would
+/// change a field.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its directory must be the emulator directory.
+/// ### Returns
+/// * `Result<MediaReport, io::Error>` - The proposed links and the media files which match no game.
+pub fn match_media(game_list: &GameList) -> Result<MediaReport, io::Error> {
+    // the paths of each game's media without their extension, eg `./images/hacks/game-image.`
+    let mut games = HashMap::<String, Vec<usize>>::new();
+    for (index, game) in game_list.games.iter().enumerate() {
+        let rom_path = match game.field_value("path") {
+            Some(path) if !path.is_empty() => path,
+            _ => continue,
+        };
+        for kind in MediaKind::ALL {
+            if let Some(expected) = kind.rom_media_path(rom_path, "") {
+                games.entry(expected.to_lowercase()).or_default().push(index);
+            }
+        }
+    }
+
+    let mut report = MediaReport { links: Vec::new(), unmatched: Vec::new() };
+    for file in media_files(&game_list.directory)? {
+        let path = match roms::gamelist_path(&game_list.directory, &file) {
+            Some(path) => path,
+            None => continue,
+        };
+        let matched = media_name(&file).and_then(|(_, kind)| {
+            let extension = file.extension()?.to_str()?;
+            let expected = path.strip_suffix(extension)?.to_lowercase();
+            Some((games.get(&expected)?, kind))
+        });
+        let (indices, kind) = match matched {
+            Some(matched) => matched,
+            None => {
+                report.unmatched.push(path);
+                continue;
+            }
+        };
+
+        for index in indices {
+            let game = &game_list.games[*index];
+            let current = game.field_value(kind.field()).unwrap_or_default();
+            if current != path {
+                report.links.push(MediaLink {
+                    game_id: game.game_id(),
+                    field: kind.field().to_string(),
+                    path: path.clone(),
+                    current: current.to_string(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Sets the fields of the linked games, returning the number of links applied.
+pub fn apply_links(game_list: &mut GameList, links: &[MediaLink]) -> usize {
+    let mut applied = 0;
+    for link in links.iter().filter(|link| MediaKind::from_field(&link.field).is_some()) {
+        if let Some(game) = game_list.games.iter_mut().find(|game| game.game_id() == link.game_id) {
+            game.set_field(&link.field, link.path.clone());
+            applied += 1;
+        }
+    }
+    applied
+}
+
+// Lists the files within the media folders of an emulator directory, missing folders are skipped.
+fn media_files(emulator_dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = Vec::<PathBuf>::new();
+    for directory in MEDIA_FOLDERS {
+        let directory = emulator_dir.join(directory);
+        if directory.is_dir() {
+            collect_files(&directory, &mut files)?;
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        }
+        else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Splits a media file name such as "Game (USA)-thumb.png" into the ROM's name and the kind of media.
+fn media_name(file: &Path) -> Option<(String, MediaKind)> {
+    let stem = file.file_stem()?.to_str()?;
+    let extension = file.extension()?.to_str()?.to_lowercase();
+    let directory = file.parent()?;
+
+    MediaKind::ALL.iter()
+        .filter(|kind| kind.extensions().contains(&extension.as_str()))
+        .filter(|kind| directory.ancestors().any(|ancestor| ancestor.file_name().and_then(|name| name.to_str()) == Some(kind.directory())))
+        .find_map(|kind| stem.strip_suffix(kind.suffix()).map(|name| (name.to_string(), *kind)))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use std::fs;
+    use crate::bgle::game_listings::structs::game_list;
+
+    #[test]
+    fn test_media_name() {
+        assert_eq!(media_name(Path::new("/snes/images/Game (USA)-thumb.png")), Some(("Game (USA)".to_string(), MediaKind::Thumbnail)));
+        assert_eq!(media_name(Path::new("/snes/videos/Game-video.MP4")), Some(("Game".to_string(), MediaKind::Video)));
+        assert_eq!(media_name(Path::new("/snes/images/Game-video.mp4")), None);
+        assert_eq!(media_name(Path::new("/snes/images/Game.png")), None);
+    }
+
+    #[test]
+    fn test_accepted_extension() {
+        assert_eq!(MediaKind::Image.accepted_extension("JPG"), "jpg");
+        assert_eq!(MediaKind::Thumbnail.accepted_extension("../../x"), "png");
+        assert_eq!(MediaKind::Video.accepted_extension("png"), "mp4");
+    }
+
+    #[test]
+    fn test_gamelist_path() {
+        assert_eq!(MediaKind::Marquee.gamelist_path("Game (USA)", "png"), "./images/Game (USA)-marquee.png");
+        assert_eq!(MediaKind::Image.rom_media_path("./Game.sfc", "png").unwrap(), "./images/Game-image.png");
+        assert_eq!(MediaKind::Video.rom_media_path("./Hacks/Game.sfc", "mp4").unwrap(), "./videos/Hacks/Game-video.mp4");
+        assert_eq!(MediaKind::Thumbnail.rom_media_path("./../Game.sfc", "png").unwrap(), "./images/Game-thumb.png");
+        assert_eq!(MediaKind::Image.rom_media_path("", "png"), None);
+        assert_eq!(MediaKind::from_field("video"), Some(MediaKind::Video));
+        assert_eq!(MediaKind::from_field("name"), None);
+    }
+
+    #[test]
+    fn test_match_and_apply_media() {
+        let directory = TempDir::new("media");
+        for file in ["images/Game (USA)-image.png", "images/Game (USA)-thumb.jpg", "images/Other-marquee.png", "videos/Game (USA)-video.mp4", "images/Hacks/Hack-image.png", "images/Hack-image.png"] {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"media").unwrap();
+        }
+
+        let mut game_list = game_list::new();
+        game_list.directory = directory.to_path_buf();
+        game_list.add_game_entry();
+        game_list.games[0].add_field("path", "./Game (USA).sfc".to_string());
+        game_list.games[0].add_field("image", "./images/Game (USA)-image.png".to_string());
+        game_list.add_game_entry();
+        game_list.games[1].add_field("path", "./Hacks/Hack.sfc".to_string());
+
+        let report = match_media(&game_list).unwrap();
+        let links: Vec<(usize, &str, &str)> = report.links.iter()
+            .map(|link| (link.game_id, link.field.as_str(), link.path.as_str()))
+            .collect();
+        assert_eq!(links, vec![
+            (0, "thumbnail", "./images/Game (USA)-thumb.jpg"),
+            (1, "image", "./images/Hacks/Hack-image.png"),
+            (0, "video", "./videos/Game (USA)-video.mp4"),
+        ]);
+        // the ROM is in a subfolder, so media outside the same subfolder is not its own
+        assert_eq!(report.unmatched, vec!["./images/Hack-image.png", "./images/Other-marquee.png"]);
+
+        assert_eq!(apply_links(&mut game_list, &report.links), 3);
+        assert_eq!(game_list.games[0].field_value("thumbnail"), Some("./images/Game (USA)-thumb.jpg"));
+        assert_eq!(game_list.games[1].field_value("image"), Some("./images/Hacks/Hack-image.png"));
+        assert!(match_media(&game_list).unwrap().links.is_empty());
+    }
+
+    #[test]
+    fn test_match_same_named_roms() {
+        let directory = TempDir::new("media-same-name");
+        for file in ["images/Game-image.png", "images/Hacks/Game-image.png"] {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"media").unwrap();
+        }
+
+        let mut game_list = game_list::new();
+        game_list.directory = directory.to_path_buf();
+        for (path, image) in [("./Game.sfc", "./images/Game-image.png"), ("./Hacks/Game.sfc", "./images/Hacks/Game-image.png")] {
+            game_list.add_game_entry();
+            let game = game_list.games.last_mut().unwrap();
+            game.add_field("path", path.to_string());
+            game.add_field("image", image.to_string());
+        }
+
+        // each game already has its own image, neither is offered the other's
+        let report = match_media(&game_list).unwrap();
+        assert!(report.links.is_empty());
+        assert!(report.unmatched.is_empty());
+    }
+}
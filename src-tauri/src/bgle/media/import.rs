@@ -0,0 +1,222 @@
+//! This module copies images from anywhere on the system into an emulator directory's media
+//! folder, named as Batocera's scrapers would name them.
+//!
+//! gamelist.xml can only refer to files within the emulator directory, so artwork chosen from
+//! elsewhere is imported first. The image can be converted to PNG or JPEG and shrunk on the way in.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::{Serialize, Deserialize};
+
+use super::MediaKind;
+
+/// Errors which can occur while importing an image.
+#[derive(Debug)]
+pub enum Error {
+    /// The image could not be read or written.
+    Io(io::Error),
+    /// The image could not be decoded or encoded.
+    Image(String),
+    /// The field does not hold an image, eg a video field.
+    NotAnImageField(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Image(message) => write!(f, "The image could not be converted: {}", message),
+            Error::NotAnImageField(field) => write!(f, "The {} field does not hold an image", field),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Self {
+        Error::Image(error.to_string())
+    }
+}
+
+/// The formats an imported image can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ImageConversion {
+    Png,
+    Jpeg,
+}
+
+impl ImageConversion {
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageConversion::Png => "png",
+            ImageConversion::Jpeg => "jpg",
+        }
+    }
+}
+
+/// How an image is changed while it is imported. By default an image is copied unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// The format to convert the image to, None keeps PNG and JPEG images as they are and converts
+    /// images of any other format to PNG.
+    pub convert_to: Option<ImageConversion>,
+    /// Images wider than this are shrunk, keeping their aspect ratio.
+    pub max_width: Option<u32>,
+    /// Images taller than this are shrunk, keeping their aspect ratio.
+    pub max_height: Option<u32>,
+}
+
+/// Imports an image into the emulator directory's images folder for one of a game's fields.
+///
+/// ### Arguments
+/// * `emulator_dir` - The emulator directory.
+/// * `rom_path` - The game's path as written in gamelist.xml, which names the imported file.
+/// * `field` - The field the image is for, `image`, `thumbnail` or `marquee`.
+/// * `source` - The full path of the image to import.
+/// * `options` - How the image is converted and resized.
+/// ### Returns
+/// * `Result<String, Error>` - The path of the imported image as written in gamelist.xml, eg `./images/Game-image.png`.
+pub fn import_image(emulator_dir: &Path, rom_path: &str, field: &str, source: &Path, options: &ImportOptions) -> Result<String, Error> {
+    let kind = match MediaKind::from_field(field) {
+        Some(kind) if kind != MediaKind::Video => kind,
+        _ => return Err(Error::NotAnImageField(field.to_string())),
+    };
+    if !source.is_file() {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a file", source.display()))));
+    }
+    let source_extension = source.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .unwrap_or_default();
+    let extension = match options.convert_to {
+        Some(conversion) => conversion.extension(),
+        None if kind.extensions().contains(&source_extension.as_str()) => source_extension.as_str(),
+        None => ImageConversion::Png.extension(),
+    };
+    let source_format = ImageFormat::from_extension(&source_extension);
+    let copy_unchanged = source_format.is_some()
+        && ImageFormat::from_extension(extension) == source_format
+        && options.max_width.is_none()
+        && options.max_height.is_none();
+    // the image is named after the ROM's path rather than its name, so it cannot replace the image
+    // of a ROM of the same name in another folder
+    let gamelist_path = kind.rom_media_path(rom_path, extension)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The game does not have a ROM path"))?;
+    let destination = crate::bgle::roms::rom_file_path(emulator_dir, &gamelist_path);
+    if let Some(folder) = destination.parent() {
+        fs::create_dir_all(folder)?;
+    }
+
+    if copy_unchanged {
+        // an image already in place is not copied onto itself, which would truncate it
+        let same_file = match (fs::canonicalize(source), fs::canonicalize(&destination)) {
+            (Ok(source), Ok(destination)) => source == destination,
+            _ => false,
+        };
+        if !same_file {
+            fs::copy(source, &destination)?;
+        }
+        return Ok(gamelist_path);
+    }
+
+    let image = resize(image::open(source)?, options);
+    let format = ImageFormat::from_extension(extension).unwrap_or(ImageFormat::Png);
+    // JPEG has no alpha channel, so transparency is dropped
+    let image = if format == ImageFormat::Jpeg { DynamicImage::ImageRgb8(image.to_rgb8()) } else { image };
+    image.save_with_format(&destination, format)?;
+    Ok(gamelist_path)
+}
+
+// Shrinks an image to fit within the maximum width and height, smaller images are not enlarged.
+fn resize(image: DynamicImage, options: &ImportOptions) -> DynamicImage {
+    let max_width = options.max_width.unwrap_or(u32::MAX).max(1);
+    let max_height = options.max_height.unwrap_or(u32::MAX).max(1);
+    if image.width() <= max_width && image.height() <= max_height {
+        return image;
+    }
+    image.resize(max_width, max_height, FilterType::Lanczos3)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use std::path::PathBuf;
+
+    // Creates an emulator directory and a source directory holding a 40x20 PNG image, both removed with the returned root.
+    fn directories(name: &str) -> (TempDir, PathBuf, PathBuf) {
+        let root = TempDir::new(&format!("import-{}", name));
+        let emulator_dir = root.join("snes");
+        let source_dir = root.join("downloads");
+        fs::create_dir_all(&emulator_dir).unwrap();
+        fs::create_dir_all(&source_dir).unwrap();
+        DynamicImage::new_rgba8(40, 20).save(source_dir.join("box art.png")).unwrap();
+        (root, emulator_dir, source_dir)
+    }
+
+    #[test]
+    fn test_import_copies_image() {
+        let (_root, emulator_dir, source_dir) = directories("copy");
+        let path = import_image(&emulator_dir, "./Hacks/Game (USA).sfc", "thumbnail", &source_dir.join("box art.png"), &ImportOptions::default()).unwrap();
+        assert_eq!(path, "./images/Hacks/Game (USA)-thumb.png");
+        assert_eq!(fs::read(emulator_dir.join("images/Hacks/Game (USA)-thumb.png")).unwrap(), fs::read(source_dir.join("box art.png")).unwrap());
+    }
+
+    #[test]
+    fn test_import_converts_and_resizes() {
+        let (_root, emulator_dir, source_dir) = directories("convert");
+        let options = ImportOptions { convert_to: Some(ImageConversion::Jpeg), max_width: Some(20), max_height: None };
+        let path = import_image(&emulator_dir, "./Game.sfc", "image", &source_dir.join("box art.png"), &options).unwrap();
+        assert_eq!(path, "./images/Game-image.jpg");
+
+        let imported = image::open(emulator_dir.join("images/Game-image.jpg")).unwrap();
+        assert_eq!((imported.width(), imported.height()), (20, 10));
+    }
+
+    #[test]
+    fn test_import_keeps_images_of_same_named_roms() {
+        let (_root, emulator_dir, source_dir) = directories("collision");
+        DynamicImage::new_rgba8(10, 10).save(source_dir.join("other.png")).unwrap();
+        let hack = import_image(&emulator_dir, "./Hacks/Game.sfc", "image", &source_dir.join("box art.png"), &ImportOptions::default()).unwrap();
+        let original = import_image(&emulator_dir, "./Game.sfc", "image", &source_dir.join("other.png"), &ImportOptions::default()).unwrap();
+
+        // importing the second image leaves the hack's image in place
+        assert_ne!(hack, original);
+        assert_eq!(fs::read(emulator_dir.join("images/Hacks/Game-image.png")).unwrap(), fs::read(source_dir.join("box art.png")).unwrap());
+        assert_eq!(fs::read(emulator_dir.join("images/Game-image.png")).unwrap(), fs::read(source_dir.join("other.png")).unwrap());
+    }
+
+    #[test]
+    fn test_import_rejects_video_field() {
+        let (_root, emulator_dir, source_dir) = directories("video");
+        let result = import_image(&emulator_dir, "./Game.sfc", "video", &source_dir.join("box art.png"), &ImportOptions::default());
+        assert!(matches!(result, Err(Error::NotAnImageField(_))));
+    }
+
+    #[test]
+    fn test_import_rejects_missing_source() {
+        let (_root, emulator_dir, source_dir) = directories("missing");
+        let result = import_image(&emulator_dir, "./Game.sfc", "image", &source_dir.join("missing.png"), &ImportOptions::default());
+        assert!(matches!(result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound));
+        assert!(!emulator_dir.join("images").exists());
+    }
+
+    #[test]
+    fn test_import_image_already_in_place() {
+        let (_root, emulator_dir, source_dir) = directories("in-place");
+        let path = import_image(&emulator_dir, "./Game.sfc", "image", &source_dir.join("box art.png"), &ImportOptions::default()).unwrap();
+        let imported = emulator_dir.join("images/Game-image.png");
+        assert_eq!(import_image(&emulator_dir, "./Game.sfc", "image", &imported, &ImportOptions::default()).unwrap(), path);
+        assert_eq!(fs::read(imported).unwrap(), fs::read(source_dir.join("box art.png")).unwrap());
+    }
+}
@@ -0,0 +1,240 @@
+//! This module imports artwork from a local checkout of libretro-thumbnails
+//! (https://github.com/libretro-thumbnails), the artwork used by RetroArch.
+//!
+//! Each system's folder holds a folder per kind of artwork, named after the games of the system's
+//! No-Intro DAT with the characters `&*/:`<>?\|"` replaced by `_`:
+//!
+//! ```text
+//! Nintendo - Super Nintendo Entertainment System/
+//!     Named_Boxarts/Super Mario World (USA).png
+//!     Named_Snaps/Super Mario World (USA).png
+//!     Named_Titles/Super Mario World (USA).png
+//! ```
+//!
+//! Images are matched to games by name, then copied into the emulator directory's images folder
+//! through the same import as images chosen by hand.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use super::MediaKind;
+use super::import::{self, ImportOptions};
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::roms::names;
+
+// The characters libretro-thumbnails replaces with `_` in file names.
+const REPLACED_CHARACTERS: &[char] = &['&', '*', '/', ':', '`', '<', '>', '?', '\\', '|', '"'];
+
+/// A libretro-thumbnails folder and the game field its images are imported for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThumbnailFolder {
+    /// The folder's name, eg `Named_Boxarts`.
+    pub folder: String,
+    /// The field the folder's images are imported for, `image`, `thumbnail` or `marquee`.
+    pub field: String,
+}
+
+/// The folders imported by default, as Batocera's scraper uses artwork: screenshots for the image,
+/// box art for the thumbnail and title screens for the marquee.
+pub fn default_folders() -> Vec<ThumbnailFolder> {
+    [("Named_Snaps", "image"), ("Named_Boxarts", "thumbnail"), ("Named_Titles", "marquee")].iter()
+        .map(|(folder, field)| ThumbnailFolder { folder: folder.to_string(), field: field.to_string() })
+        .collect()
+}
+
+/// An image proposed for one of a game's fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThumbnailMatch {
+    pub game_id: usize,
+    pub field: String,
+    /// The full path of the libretro-thumbnails image.
+    pub source: PathBuf,
+    /// False when the names are only similar, such matches are worth a closer look.
+    pub exact: bool,
+    /// The value of the field before importing, empty if the field is not set.
+    pub current: String,
+}
+
+/// Returns a name as libretro-thumbnails writes it in file names, eg `Tom _ Jerry (USA)` for
+/// `Tom & Jerry (USA)`.
+pub fn thumbnail_name(name: &str) -> String {
+    name.chars().map(|c| if REPLACED_CHARACTERS.contains(&c) { '_' } else { c }).collect()
+}
+
+/// Matches the images of a libretro-thumbnails system folder to the games of a game list. A game
+/// is matched by its ROM's file name, or its name, first exactly and failing that to the most
+/// similar image name.
+///
+/// ### Arguments
+/// * `game_list` - The game list.
+/// * `system_dir` - The libretro-thumbnails folder of the game list's system.
+/// * `folders` - The folders to import from and the field each is imported for.
+/// * `overwrite` - Whether fields which are already set may be changed.
+/// ### Returns
+/// * `Result<Vec<ThumbnailMatch>, io::Error>` - The proposed images, in the order of the games.
+pub fn match_thumbnails(game_list: &GameList, system_dir: &Path, folders: &[ThumbnailFolder], overwrite: bool) -> Result<Vec<ThumbnailMatch>, io::Error> {
+    let mut images = Vec::<(&ThumbnailFolder, Vec<ThumbnailImage>)>::new();
+    for folder in folders.iter().filter(|folder| MediaKind::from_field(&folder.field).is_some_and(|kind| kind != MediaKind::Video)) {
+        let directory = system_dir.join(&folder.folder);
+        if directory.is_dir() {
+            images.push((folder, folder_images(&directory)?));
+        }
+    }
+
+    let mut matches = Vec::<ThumbnailMatch>::new();
+    for game in &game_list.games {
+        let candidates = game_names(game);
+        for (folder, folder_images) in &images {
+            let current = game.field_value(&folder.field).unwrap_or_default();
+            if !overwrite && !current.is_empty() {
+                continue;
+            }
+            if let Some((image, exact)) = find_image(folder_images, &candidates) {
+                matches.push(ThumbnailMatch {
+                    game_id: game.game_id(),
+                    field: folder.field.clone(),
+                    source: image.path.clone(),
+                    exact,
+                    current: current.to_string(),
+                });
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Copies matched images into the emulator directory's images folder and sets the games' fields.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its directory must be the emulator directory.
+/// * `matches` - The images to import.
+/// * `options` - How the images are converted and resized.
+/// ### Returns
+/// * `Vec<(usize, String)>` - The games whose image could not be imported, with the reason.
+pub fn import_thumbnails(game_list: &mut GameList, matches: &[ThumbnailMatch], options: &ImportOptions) -> Vec<(usize, String)> {
+    let emulator_dir = game_list.directory.clone();
+    let mut failed = Vec::<(usize, String)>::new();
+    for found in matches {
+        let game = match game_list.get_mut(found.game_id) {
+            Some(game) => game,
+            None => continue,
+        };
+        let rom_path = game.field_value("path").unwrap_or_default().to_string();
+        match import::import_image(&emulator_dir, &rom_path, &found.field, &found.source, options) {
+            Ok(path) => game.set_field(&found.field, path),
+            Err(e) => failed.push((found.game_id, e.to_string())),
+        }
+    }
+    failed
+}
+
+// An image of a libretro-thumbnails folder.
+struct ThumbnailImage {
+    path: PathBuf,
+    // the file name without its extension, eg `Tom _ Jerry (USA)`
+    stem: String,
+    normalized: String,
+}
+
+// Lists the PNG images of a libretro-thumbnails folder, sorted by name.
+fn folder_images(directory: &Path) -> Result<Vec<ThumbnailImage>, io::Error> {
+    let mut images = Vec::<ThumbnailImage>::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let is_png = path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        let stem = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) if is_png && path.is_file() => stem.to_string(),
+            _ => continue,
+        };
+        // a replaced `&` is the only replaced character normally written between spaces
+        let normalized = names::normalized_name(&stem.replace(" _ ", " & "));
+        images.push(ThumbnailImage { path, stem, normalized });
+    }
+    images.sort_by(|first, second| first.stem.cmp(&second.stem));
+    Ok(images)
+}
+
+// The names a game's images may be named after: its ROM's file name and its name.
+fn game_names(game: &Game) -> Vec<String> {
+    let rom_name = game.field_value("path").and_then(super::rom_name);
+    let name = game.field_value("name").map(String::from);
+    rom_name.into_iter().chain(name).filter(|name| !name.is_empty()).collect()
+}
+
+// Finds the image named exactly after one of the candidate names or, failing that, the image whose
+// normalized name is most similar to one of them.
+fn find_image<'a>(images: &'a [ThumbnailImage], candidates: &[String]) -> Option<(&'a ThumbnailImage, bool)> {
+    for candidate in candidates {
+        let wanted = thumbnail_name(candidate);
+        if let Some(image) = images.iter().find(|image| image.stem == wanted) {
+            return Some((image, true));
+        }
+    }
+
+    let mut best: Option<(&ThumbnailImage, f64)> = None;
+    for candidate in candidates.iter().map(|candidate| names::normalized_name(candidate)).filter(|candidate| !candidate.is_empty()) {
+        for image in images {
+            let similarity = names::similarity(&candidate, &image.normalized);
+            if similarity >= names::NAME_MATCH_THRESHOLD && best.is_none_or(|(_, best)| similarity > best) {
+                best = Some((image, similarity));
+            }
+        }
+    }
+    best.map(|(image, _)| (image, false))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use image::DynamicImage;
+    use crate::bgle::game_listings::structs::game_list;
+
+    #[test]
+    fn test_thumbnail_name() {
+        assert_eq!(thumbnail_name("Tom & Jerry: The Movie (USA)"), "Tom _ Jerry_ The Movie (USA)");
+    }
+
+    #[test]
+    fn test_match_and_import_thumbnails() {
+        let root = TempDir::new("thumbnails");
+        let system_dir = root.join("Nintendo - Super Nintendo Entertainment System");
+        let emulator_dir = root.join("snes");
+        fs::create_dir_all(&emulator_dir).unwrap();
+        for file in ["Named_Boxarts/Super Mario World (USA).png", "Named_Boxarts/Tom _ Jerry (USA).png", "Named_Snaps/Super Mario World (USA).png", "Named_Titles/Zelda.png"] {
+            let path = system_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            DynamicImage::new_rgb8(4, 4).save(path).unwrap();
+        }
+
+        let mut game_list = game_list::new();
+        game_list.directory = emulator_dir.clone();
+        for _ in 0..3 {
+            game_list.add_game_entry();
+        }
+        game_list.games[0].add_field("path", "./Super Mario World (USA).sfc".to_string());
+        game_list.games[0].add_field("image", "./images/existing.png".to_string());
+        game_list.games[1].add_field("path", "./tomjerry.sfc".to_string());
+        game_list.games[1].add_field("name", "Tom and Jerry".to_string());
+        game_list.games[2].add_field("path", "./Unknown.sfc".to_string());
+
+        let matches = match_thumbnails(&game_list, &system_dir, &default_folders(), false).unwrap();
+        let found: Vec<(usize, &str, bool)> = matches.iter()
+            .map(|found| (found.game_id, found.field.as_str(), found.exact))
+            .collect();
+        assert_eq!(found, vec![(0, "thumbnail", true), (1, "thumbnail", false)]);
+        assert!(matches[1].source.ends_with("Named_Boxarts/Tom _ Jerry (USA).png"));
+
+        let folders = vec![ThumbnailFolder { folder: "Named_Snaps".to_string(), field: "marquee".to_string() }];
+        assert_eq!(match_thumbnails(&game_list, &system_dir, &folders, false).unwrap()[0].field, "marquee");
+
+        assert!(import_thumbnails(&mut game_list, &matches, &ImportOptions::default()).is_empty());
+        assert_eq!(game_list.games[0].field_value("thumbnail"), Some("./images/Super Mario World (USA)-thumb.png"));
+        assert_eq!(game_list.games[1].field_value("thumbnail"), Some("./images/tomjerry-thumb.png"));
+        assert!(emulator_dir.join("images/tomjerry-thumb.png").is_file());
+    }
+}
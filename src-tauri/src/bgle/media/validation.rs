@@ -0,0 +1,240 @@
+//! This module checks the media files which games link to.
+//!
+//! `Field::new` only checks that a media field holds a path. This module checks the file at that
+//! path: that it exists and can be read, that it is not empty or too large, and that its contents
+//! are of the expected type, a PNG or JPEG image or an MP4 video, judged by its magic bytes.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::MediaKind;
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::roms;
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// The size above which an image is reported as oversized by default.
+pub const MAX_IMAGE_SIZE: u64 = 10 * MEGABYTE;
+
+/// The size above which a video is reported as oversized by default.
+pub const MAX_VIDEO_SIZE: u64 = 100 * MEGABYTE;
+
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+const JPEG_SIGNATURE: &[u8] = &[0xff, 0xd8, 0xff];
+// An MP4 file begins with the size of its ftyp box, followed by the box type.
+const MP4_BOX_TYPE: &[u8] = b"ftyp";
+
+/// The size limits applied while validating media.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationOptions {
+    pub max_image_size: u64,
+    pub max_video_size: u64,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions { max_image_size: MAX_IMAGE_SIZE, max_video_size: MAX_VIDEO_SIZE }
+    }
+}
+
+/// What is wrong with a media file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MediaProblem {
+    Missing,
+    Unreadable,
+    Empty,
+    WrongType,
+    Oversized { size: u64, limit: u64 },
+}
+
+impl MediaProblem {
+    // The message shown for the problem in the field's format_error_message.
+    fn message(&self, kind: MediaKind) -> String {
+        match self {
+            MediaProblem::Missing => String::from("File not found."),
+            MediaProblem::Unreadable => String::from("File cannot be read."),
+            MediaProblem::Empty => String::from("File is empty."),
+            MediaProblem::WrongType if kind == MediaKind::Video => String::from("Must be an MP4 video."),
+            MediaProblem::WrongType => String::from("Must be a PNG or JPEG image."),
+            MediaProblem::Oversized { limit, .. } => format!("File is larger than {} MB.", limit / MEGABYTE),
+        }
+    }
+}
+
+impl fmt::Display for MediaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaProblem::Missing => write!(f, "missing"),
+            MediaProblem::Unreadable => write!(f, "unreadable"),
+            MediaProblem::Empty => write!(f, "zero bytes"),
+            MediaProblem::WrongType => write!(f, "wrong file type"),
+            MediaProblem::Oversized { size, limit } => write!(f, "{} bytes, over the limit of {} bytes", size, limit),
+        }
+    }
+}
+
+/// A problem found with the media file linked by one of a game's fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaFinding {
+    pub game_id: usize,
+    pub field: String,
+    pub path: String,
+    pub problem: MediaProblem,
+}
+
+/// The problems found with the media of an emulator's games.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaValidationReport {
+    pub emulator: String,
+    /// The number of media files checked.
+    pub checked: usize,
+    pub findings: Vec<MediaFinding>,
+}
+
+/// Checks the media of every game in a game list, setting the `format_error_message` of each field
+/// whose file has a problem.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its directory must be the emulator directory.
+/// * `options` - The size limits to apply.
+/// ### Returns
+/// * `MediaValidationReport` - The problems found, in the order of the games.
+pub fn validate_media(game_list: &mut GameList, options: &ValidationOptions) -> MediaValidationReport {
+    let mut report = MediaValidationReport { emulator: game_list.emulator.clone(), checked: 0, findings: Vec::new() };
+    let emulator_dir = game_list.directory.clone();
+
+    for game in game_list.games.iter_mut() {
+        report.checked += game.fields.iter()
+            .filter(|field| MediaKind::from_field(&field.name).is_some() && !field.value.is_empty())
+            .count();
+        report.findings.extend(validate_game_media(&emulator_dir, game, options));
+    }
+    report
+}
+
+/// Checks the media of a single game, setting the `format_error_message` of each field whose file
+/// has a problem and clearing it from those whose file is now fine.
+pub fn validate_game_media(emulator_dir: &Path, game: &mut Game, options: &ValidationOptions) -> Vec<MediaFinding> {
+    let game_id = game.game_id();
+    let mut findings = Vec::<MediaFinding>::new();
+
+    for field in game.fields.iter_mut() {
+        let kind = match MediaKind::from_field(&field.name) {
+            Some(kind) if !field.value.is_empty() => kind,
+            _ => continue,
+        };
+        // a value with a message from its own validation was cleared, so only the message of an
+        // earlier check can be left on a field with a value
+        field.format_error_message.clear();
+        if let Some(problem) = check_file(&roms::rom_file_path(emulator_dir, &field.value), kind, options) {
+            field.format_error_message = problem.message(kind);
+            findings.push(MediaFinding { game_id, field: field.name.clone(), path: field.value.clone(), problem });
+        }
+    }
+    findings
+}
+
+// Returns the problem with a media file, None if it is fine.
+fn check_file(path: &Path, kind: MediaKind, options: &ValidationOptions) -> Option<MediaProblem> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => return Some(MediaProblem::WrongType),
+        Err(_) => return Some(MediaProblem::Missing),
+    };
+    if metadata.len() == 0 {
+        return Some(MediaProblem::Empty);
+    }
+
+    let mut header = [0u8; 12];
+    let read = match File::open(path).and_then(|mut file| file.read(&mut header)) {
+        Ok(read) => read,
+        Err(_) => return Some(MediaProblem::Unreadable),
+    };
+    if !has_expected_signature(&header[..read], kind) {
+        return Some(MediaProblem::WrongType);
+    }
+
+    let limit = match kind {
+        MediaKind::Video => options.max_video_size,
+        _ => options.max_image_size,
+    };
+    if metadata.len() > limit {
+        return Some(MediaProblem::Oversized { size: metadata.len(), limit });
+    }
+    None
+}
+
+fn has_expected_signature(header: &[u8], kind: MediaKind) -> bool {
+    match kind {
+        MediaKind::Video => header.len() >= 8 && &header[4..8] == MP4_BOX_TYPE,
+        _ => header.starts_with(PNG_SIGNATURE) || header.starts_with(JPEG_SIGNATURE),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use crate::bgle::game_listings::structs::game_list;
+
+    #[test]
+    fn test_has_expected_signature() {
+        assert!(has_expected_signature(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0], MediaKind::Image));
+        assert!(has_expected_signature(&[0xff, 0xd8, 0xff, 0xe0], MediaKind::Thumbnail));
+        assert!(has_expected_signature(b"\0\0\0\x18ftypmp42", MediaKind::Video));
+        assert!(!has_expected_signature(b"GIF89a", MediaKind::Marquee));
+        assert!(!has_expected_signature(&[0xff, 0xd8, 0xff, 0xe0], MediaKind::Video));
+    }
+
+    #[test]
+    fn test_validate_media() {
+        let directory = TempDir::new("media-validation");
+        fs::create_dir_all(directory.join("images")).unwrap();
+        fs::create_dir_all(directory.join("videos")).unwrap();
+        fs::write(directory.join("images/good-image.png"), [PNG_SIGNATURE, b"data"].concat()).unwrap();
+        fs::write(directory.join("images/large-image.jpg"), [JPEG_SIGNATURE, &[0u8; 64]].concat()).unwrap();
+        fs::write(directory.join("images/text-thumb.png"), b"not an image").unwrap();
+        fs::write(directory.join("videos/empty-video.mp4"), b"").unwrap();
+
+        let mut game_list = game_list::new();
+        game_list.directory = directory.to_path_buf();
+        game_list.add_game_entry();
+        let game = &mut game_list.games[0];
+        game.add_field("path", "./good.sfc".to_string());
+        game.add_field("image", "./images/good-image.png".to_string());
+        game.add_field("thumbnail", "./images/text-thumb.png".to_string());
+        game.add_field("marquee", "./images/missing-marquee.png".to_string());
+        game.add_field("video", "./videos/empty-video.mp4".to_string());
+        game_list.add_game_entry();
+        game_list.games[1].add_field("image", "./images/large-image.jpg".to_string());
+
+        let options = ValidationOptions { max_image_size: 32, ..ValidationOptions::default() };
+        let report = validate_media(&mut game_list, &options);
+        let problems: Vec<(usize, &str, &MediaProblem)> = report.findings.iter()
+            .map(|finding| (finding.game_id, finding.field.as_str(), &finding.problem))
+            .collect();
+        assert_eq!(report.checked, 5);
+        assert_eq!(problems, vec![
+            (0, "thumbnail", &MediaProblem::WrongType),
+            (0, "marquee", &MediaProblem::Missing),
+            (0, "video", &MediaProblem::Empty),
+            (1, "image", &MediaProblem::Oversized { size: 67, limit: 32 }),
+        ]);
+        assert_eq!(game_list.games[0].fields[1].format_error_message, "");
+        assert_eq!(game_list.games[0].fields[2].format_error_message, "Must be a PNG or JPEG image.");
+        assert_eq!(game_list.games[0].fields[3].format_error_message, "File not found.");
+
+        // once the file is there the field's message is gone, while an invalid value keeps its own
+        fs::write(directory.join("images/missing-marquee.png"), [PNG_SIGNATURE, b"data"].concat()).unwrap();
+        game_list.games[1].add_field("thumbnail", "/absolute/thumb.png".to_string());
+        let report = validate_media(&mut game_list, &options);
+        assert_eq!(report.findings.len(), 3);
+        assert_eq!(game_list.games[0].fields[3].format_error_message, "");
+        assert_eq!(game_list.games[1].fields[1].format_error_message, "Must be a file path.");
+    }
+}
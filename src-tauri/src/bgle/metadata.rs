@@ -0,0 +1,127 @@
+//! This module fills in game fields from external sources of metadata.
+//!
+//! Each source proposes changes to the fields of the games it recognises. The changes are returned
+//! as a preview so they can be reviewed, and only the changes which are accepted are applied to the
+//! game list, through `Game::change_field`, before it is saved.
+
+pub mod dat;
+pub mod file_names;
+pub mod launchbox;
+pub mod openvgdb;
+pub mod rdb;
+pub mod scraper;
+
+use serde::{Serialize, Deserialize};
+
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+
+/// A proposed change to one of a game's fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub game_id: usize,
+    pub field: String,
+    /// The value of the field before the change, empty if the game does not have the field.
+    pub current: String,
+    pub proposed: String,
+}
+
+/// Proposes changes to a game's fields, leaving out empty values and values the game already has.
+///
+/// ### Arguments
+/// * `game` - The game the values were found for.
+/// * `values` - The field names and values found for the game.
+/// * `overwrite` - Whether fields which already hold a value may be changed.
+pub fn propose_changes(game: &Game, values: &[(&str, String)], overwrite: bool) -> Vec<FieldChange> {
+    values.iter()
+        .filter(|(_, proposed)| !proposed.is_empty())
+        .filter_map(|(field, proposed)| {
+            let current = game.field_value(field).unwrap_or_default();
+            if current == proposed || (!overwrite && !current.is_empty()) {
+                return None;
+            }
+            Some(FieldChange {
+                game_id: game.game_id(),
+                field: field.to_string(),
+                current: current.to_string(),
+                proposed: proposed.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Applies accepted changes to the games of a game list, returning the number applied. Changes to
+/// games which are no longer in the list are skipped.
+pub fn apply_changes(game_list: &mut GameList, changes: &[FieldChange]) -> usize {
+    let mut applied = 0;
+    for change in changes {
+        if let Some(game) = game_list.get_mut(change.game_id) {
+            game.set_field(&change.field, change.proposed.clone());
+            applied += 1;
+        }
+    }
+    applied
+}
+
+/// Formats a release date in gamelist.xml's format, eg `19910813T000000`.
+///
+/// ### Returns
+/// * `Option<String>` - None for a date the `releasedate` field would reject, such as a year which is not four digits or a month over 12.
+pub fn gamelist_date(year: u32, month: u32, day: u32) -> Option<String> {
+    if !(1000..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(format!("{:04}{:02}{:02}T000000", year, month, day))
+}
+
+/// Converts a maximum number of players to gamelist.xml's range, eg `2` to `1-2`.
+///
+/// ### Returns
+/// * `Option<String>` - None for no players or a number the `players` field would reject, it holds at most three digits.
+pub fn players_range(max_players: u32) -> Option<String> {
+    match max_players {
+        0 | 1000.. => None,
+        1 => Some(String::from("1")),
+        players => Some(format!("1-{}", players)),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::game_listings::structs::game_list;
+
+    #[test]
+    fn test_propose_and_apply_changes() {
+        let mut game_list = game_list::new();
+        game_list.add_game_entry();
+        game_list.games[0].add_field("name", "Mario".to_string());
+        game_list.games[0].add_field("region", "us".to_string());
+
+        let values = [("name", "Super Mario World".to_string()), ("region", "us".to_string()), ("lang", "en".to_string()), ("genre", String::new())];
+        let changes = propose_changes(&game_list.games[0], &values, false);
+        assert_eq!(changes, vec![FieldChange { game_id: 0, field: "lang".to_string(), current: String::new(), proposed: "en".to_string() }]);
+        assert_eq!(propose_changes(&game_list.games[0], &values, true).len(), 2);
+
+        assert_eq!(apply_changes(&mut game_list, &changes), 1);
+        assert_eq!(game_list.games[0].field_value("lang"), Some("en"));
+    }
+
+    #[test]
+    fn test_gamelist_date() {
+        assert_eq!(gamelist_date(1991, 8, 13), Some("19910813T000000".to_string()));
+        assert_eq!(gamelist_date(991, 8, 13), None);
+        assert_eq!(gamelist_date(19910, 8, 13), None);
+        assert_eq!(gamelist_date(1991, 13, 1), None);
+        assert_eq!(gamelist_date(1991, 8, 0), None);
+    }
+
+    #[test]
+    fn test_players_range() {
+        assert_eq!(players_range(0), None);
+        assert_eq!(players_range(1), Some("1".to_string()));
+        assert_eq!(players_range(4), Some("1-4".to_string()));
+        assert_eq!(players_range(1000), None);
+    }
+}
@@ -0,0 +1,381 @@
+//! This module reads No-Intro and Redump DAT files, in the clrmamepro/Logiqx XML format, and fills in
+//! the name, region and language of the games whose ROMs they list.
+//!
+//! A DAT lists the known good (and bad) dumps of a system, each game holding one or more ROMs with
+//! their size and checksums:
+//!
+//! ```xml
+//! <datafile>
+//!     <game name="Super Mario World (USA)">
+//!         <rom name="Super Mario World (USA).sfc" size="524288" crc="b19ed489" md5="..." sha1="..."/>
+//!     </game>
+//! </datafile>
+//! ```
+//!
+//! Games are matched by the checksums of their ROMs, falling back to the ROM's file name.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Serialize, Deserialize};
+
+use super::FieldChange;
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::roms::tags::{self, Flag};
+
+/// Errors which can occur while reading a DAT file.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file is not well formed XML.
+    Malformed(String),
+    /// The file is XML but not a Logiqx DAT, it has no `datafile` root element.
+    NotADat,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Malformed(message) => write!(f, "The DAT file is malformed: {}", message),
+            Error::NotADat => write!(f, "The file is not a Logiqx XML DAT file"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// A ROM listed in a DAT. Checksums are in lower case hex, empty if the DAT does not give them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DatRom {
+    pub name: String,
+    pub size: Option<u64>,
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+    /// The dump status, eg `baddump`, `nodump` or `verified`, empty if not given.
+    pub status: String,
+}
+
+/// A game listed in a DAT.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DatGame {
+    pub name: String,
+    pub roms: Vec<DatRom>,
+}
+
+impl DatGame {
+    /// Whether the game is a bad dump, either flagged by the status of one of its ROMs or by a
+    /// GoodTools `[b]` tag in its name.
+    pub fn is_bad_dump(&self) -> bool {
+        tags::parse(&self.name).flags.contains(&Flag::BadDump) || self.roms.iter().any(|rom| rom.status == "baddump" || rom.status == "nodump")
+    }
+}
+
+/// The contents of a DAT file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Dat {
+    /// The name given in the DAT's header, eg `Nintendo - Super Nintendo Entertainment System`.
+    pub name: String,
+    pub games: Vec<DatGame>,
+}
+
+/// How a game was matched to a DAT entry.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MatchedBy {
+    Sha1,
+    Md5,
+    Crc32,
+    FileName,
+}
+
+/// A game matched to a DAT entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatMatch {
+    pub game_id: usize,
+    /// The name of the DAT game, eg `Super Mario World (USA)`.
+    pub dat_game: String,
+    pub matched_by: MatchedBy,
+    pub bad_dump: bool,
+}
+
+/// What matching a game list against a DAT found, and the field changes it proposes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatPreview {
+    pub matches: Vec<DatMatch>,
+    /// The games which match no entry in the DAT.
+    pub unmatched: Vec<usize>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Reads a DAT file.
+pub fn load(path: &Path) -> Result<Dat, Error> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Parses the contents of a Logiqx XML DAT file. Both `game` and `machine` entries are read.
+pub fn parse(xml: &str) -> Result<Dat, Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut dat = Dat::default();
+    let mut game: Option<DatGame> = None;
+    let mut root_found = false;
+    let mut in_header_name = false;
+    let mut depth = 0;
+
+    loop {
+        let event = reader.read_event().map_err(|e| Error::Malformed(e.to_string()))?;
+        match event {
+            Event::Start(element) => {
+                depth += 1;
+                match (depth, element.name().as_ref()) {
+                    (1, b"datafile") => root_found = true,
+                    (2, b"game") | (2, b"machine") => {
+                        game = Some(DatGame { name: attribute(&element, "name")?, roms: Vec::new() });
+                    }
+                    (3, b"name") => in_header_name = game.is_none(),
+                    (3, b"rom") => add_rom(&mut game, &element)?,
+                    _ => {}
+                }
+            }
+            Event::Empty(element) => {
+                match (depth + 1, element.name().as_ref()) {
+                    (1, b"datafile") => root_found = true,
+                    (3, b"rom") => add_rom(&mut game, &element)?,
+                    _ => {}
+                }
+            }
+            Event::End(_) => {
+                if depth == 2 {
+                    if let Some(game) = game.take() {
+                        dat.games.push(game);
+                    }
+                }
+                in_header_name = false;
+                depth -= 1;
+            }
+            Event::Text(text) if in_header_name => {
+                dat.name.push_str(&text.unescape().map_err(|e| Error::Malformed(e.to_string()))?);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if !root_found {
+        return Err(Error::NotADat);
+    }
+    Ok(dat)
+}
+
+/// Matches the games of a game list against a DAT and proposes the name, region and language of
+/// each matched game. Games are matched by their ROM's SHA1, MD5 or CRC32, in that order, and
+/// failing those by the file name of their ROM.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its games' hashes should already have been computed.
+/// * `dat` - The DAT to match against.
+/// * `overwrite` - Whether fields which already hold a value may be changed.
+/// ### Returns
+/// * `DatPreview` - The matches, the unmatched games and the proposed changes, nothing is changed.
+pub fn preview(game_list: &GameList, dat: &Dat, overwrite: bool) -> DatPreview {
+    let index = DatIndex::new(dat);
+    let mut preview = DatPreview { matches: Vec::new(), unmatched: Vec::new(), changes: Vec::new() };
+
+    for game in &game_list.games {
+        let (dat_game, matched_by) = match index.find(game) {
+            Some(found) => found,
+            None => {
+                preview.unmatched.push(game.game_id());
+                continue;
+            }
+        };
+        let dat_game = &dat.games[dat_game];
+        preview.matches.push(DatMatch {
+            game_id: game.game_id(),
+            dat_game: dat_game.name.clone(),
+            matched_by,
+            bad_dump: dat_game.is_bad_dump(),
+        });
+
+        let values = tags::parse(&dat_game.name).field_values();
+        preview.changes.extend(super::propose_changes(game, &values, overwrite));
+    }
+    preview
+}
+
+// Lookups from a checksum or lower case file name to the index of the DAT game listing it.
+struct DatIndex {
+    sha1: HashMap<String, usize>,
+    md5: HashMap<String, usize>,
+    crc32: HashMap<String, usize>,
+    file_names: HashMap<String, usize>,
+}
+
+impl DatIndex {
+    fn new(dat: &Dat) -> DatIndex {
+        let mut index = DatIndex { sha1: HashMap::new(), md5: HashMap::new(), crc32: HashMap::new(), file_names: HashMap::new() };
+        for (position, game) in dat.games.iter().enumerate() {
+            for rom in &game.roms {
+                for (lookup, key) in [(&mut index.sha1, &rom.sha1), (&mut index.md5, &rom.md5), (&mut index.crc32, &rom.crc32)] {
+                    if !key.is_empty() {
+                        lookup.entry(key.clone()).or_insert(position);
+                    }
+                }
+                index.file_names.entry(rom.name.to_lowercase()).or_insert(position);
+            }
+            index.file_names.entry(game.name.to_lowercase()).or_insert(position);
+        }
+        index
+    }
+
+    fn find(&self, game: &Game) -> Option<(usize, MatchedBy)> {
+        let field = |name: &str| game.field_value(name).map(|value| value.to_lowercase()).unwrap_or_default();
+        let (sha1, md5, crc32) = match game.hashes() {
+            Some(hashes) => (hashes.sha1.clone(), hashes.md5.clone(), hashes.crc32.clone()),
+            None => (String::new(), field("md5"), field("crc32")),
+        };
+        let by_hash = [(&self.sha1, sha1, MatchedBy::Sha1), (&self.md5, md5, MatchedBy::Md5), (&self.crc32, crc32, MatchedBy::Crc32)];
+        for (lookup, key, matched_by) in by_hash {
+            if let Some(position) = lookup.get(&key) {
+                return Some((*position, matched_by));
+            }
+        }
+
+        self.file_name_candidates(game).iter()
+            .find_map(|name| self.file_names.get(name))
+            .map(|position| (*position, MatchedBy::FileName))
+    }
+
+    // The names a game's ROM may be listed under: the file inside its archive, its file name and its
+    // file name without the extension, which DATs use as the game's name.
+    fn file_name_candidates(&self, game: &Game) -> Vec<String> {
+        let mut names = Vec::<String>::new();
+        if let Some(rom) = game.archive().and_then(|archive| archive.inner_rom()) {
+            names.push(rom.name.rsplit('/').next().unwrap_or(&rom.name).to_lowercase());
+        }
+        if let Some(path) = game.field_value("path").map(Path::new) {
+            names.extend(path.file_name().and_then(|name| name.to_str()).map(|name| name.to_lowercase()));
+            names.extend(path.file_stem().and_then(|name| name.to_str()).map(|name| name.to_lowercase()));
+        }
+        names
+    }
+}
+
+// Adds a ROM element to the game being read, ROMs outside of a game are ignored.
+fn add_rom(game: &mut Option<DatGame>, element: &BytesStart) -> Result<(), Error> {
+    if let Some(game) = game.as_mut() {
+        game.roms.push(DatRom {
+            name: attribute(element, "name")?,
+            size: attribute(element, "size")?.parse().ok(),
+            crc32: attribute(element, "crc")?.to_lowercase(),
+            md5: attribute(element, "md5")?.to_lowercase(),
+            sha1: attribute(element, "sha1")?.to_lowercase(),
+            status: attribute(element, "status")?,
+        });
+    }
+    Ok(())
+}
+
+// Returns the unescaped value of an element's attribute, empty if the element does not have it.
+fn attribute(element: &BytesStart, name: &str) -> Result<String, Error> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| Error::Malformed(e.to_string()))?;
+        if attribute.key.as_ref() == name.as_bytes() {
+            let value = attribute.unescape_value().map_err(|e| Error::Malformed(e.to_string()))?;
+            return Ok(value.to_string());
+        }
+    }
+    Ok(String::new())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::game_listings::structs::game_list;
+    use crate::bgle::roms::checksums::RomHashes;
+
+    const DAT: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/dtds/logiqx.dtd">
+<datafile>
+    <header>
+        <name>Nintendo - Super Nintendo Entertainment System</name>
+    </header>
+    <game name="Super Mario World (USA)">
+        <description>Super Mario World (USA)</description>
+        <rom name="Super Mario World (USA).sfc" size="524288" crc="B19ED489" md5="cdd3c8c37322978ca8669b34bc89c804" sha1="6b47bb75d16514b6a476aa0c73a683a2a4c18765"/>
+    </game>
+    <game name="Donkey Kong Country (Europe) (En,Fr,De)">
+        <rom name="Donkey Kong Country (Europe) (En,Fr,De).sfc" size="4194304" crc="6e2c8c7f" md5="" sha1=""/>
+    </game>
+    <game name="Tetris &amp; Dr. Mario (Japan)">
+        <rom name="Tetris &amp; Dr. Mario (Japan).sfc" size="1048576" crc="0f6f3c5a" status="baddump"/>
+    </game>
+</datafile>"#;
+
+    #[test]
+    fn test_parse() {
+        let dat = parse(DAT).unwrap();
+        assert_eq!(dat.name, "Nintendo - Super Nintendo Entertainment System");
+        assert_eq!(dat.games.len(), 3);
+        assert_eq!(dat.games[0].roms[0].crc32, "b19ed489");
+        assert_eq!(dat.games[0].roms[0].size, Some(524288));
+        assert_eq!(dat.games[2].name, "Tetris & Dr. Mario (Japan)");
+        assert!(dat.games[2].is_bad_dump());
+        assert!(!dat.games[0].is_bad_dump());
+        assert!(matches!(parse("<gameList></gameList>"), Err(Error::NotADat)));
+    }
+
+    #[test]
+    fn test_preview() {
+        let dat = parse(DAT).unwrap();
+        let mut game_list = game_list::new();
+        for _ in 0..4 {
+            game_list.add_game_entry();
+        }
+        game_list.games[0].add_field("path", "./smw.sfc".to_string());
+        game_list.games[0].add_field("name", "smw".to_string());
+        game_list.games[0].set_hashes(RomHashes {
+            crc32: "b19ed489".to_string(),
+            md5: "cdd3c8c37322978ca8669b34bc89c804".to_string(),
+            sha1: "6b47bb75d16514b6a476aa0c73a683a2a4c18765".to_string(),
+            size: 524288,
+        });
+        game_list.games[1].add_field("path", "./dkc.sfc".to_string());
+        game_list.games[1].add_field("crc32", "6E2C8C7F".to_string());
+        game_list.games[2].add_field("path", "./Tetris & Dr. Mario (Japan).sfc".to_string());
+        game_list.games[3].add_field("path", "./Unknown.sfc".to_string());
+
+        let preview = preview(&game_list, &dat, true);
+        let matches: Vec<(usize, MatchedBy, bool)> = preview.matches.iter()
+            .map(|found| (found.game_id, found.matched_by, found.bad_dump))
+            .collect();
+        assert_eq!(matches, vec![(0, MatchedBy::Sha1, false), (1, MatchedBy::Crc32, false), (2, MatchedBy::FileName, true)]);
+        assert_eq!(preview.unmatched, vec![3]);
+
+        let changes: Vec<(usize, &str, &str)> = preview.changes.iter()
+            .map(|change| (change.game_id, change.field.as_str(), change.proposed.as_str()))
+            .collect();
+        assert_eq!(changes, vec![
+            (0, "name", "Super Mario World"),
+            (0, "region", "us"),
+            (0, "lang", "en"),
+            (1, "name", "Donkey Kong Country"),
+            (1, "region", "eu"),
+            (1, "lang", "en,fr,de"),
+            (2, "name", "Tetris & Dr. Mario"),
+            (2, "region", "jp"),
+            (2, "lang", "ja"),
+        ]);
+        assert_eq!(preview.changes[0].current, "smw");
+    }
+}
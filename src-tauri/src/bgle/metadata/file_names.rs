@@ -0,0 +1,70 @@
+//! This module fills in the name, region and language of games from the tags in their ROM's file
+//! name, eg `Super Mario World (USA, Europe) (Rev 1) [!].sfc`.
+//!
+//! New games found in an emulator directory are named this way as they are listed. This module
+//! does the same for games already in a game list, as a preview of changes to review.
+
+use serde::{Serialize, Deserialize};
+
+use super::FieldChange;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::roms::tags::{self, NameTags};
+
+/// The tags read from a game's file name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParsedFileName {
+    pub game_id: usize,
+    pub tags: NameTags,
+}
+
+/// The tags read from the file names of a game list and the field changes they propose.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileNamePreview {
+    pub parsed: Vec<ParsedFileName>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Reads the tags of each game's file name and proposes its name, region and language.
+///
+/// ### Arguments
+/// * `game_list` - The game list.
+/// * `overwrite` - Whether fields which already hold a value may be changed.
+/// ### Returns
+/// * `FileNamePreview` - The tags of each game with a path and the proposed changes, nothing is changed.
+pub fn preview(game_list: &GameList, overwrite: bool) -> FileNamePreview {
+    let mut preview = FileNamePreview { parsed: Vec::new(), changes: Vec::new() };
+    for game in &game_list.games {
+        let path = match game.field_value("path") {
+            Some(path) if !path.is_empty() => path,
+            _ => continue,
+        };
+        let tags = tags::parse_file_name(path);
+        preview.changes.extend(super::propose_changes(game, &tags.field_values(), overwrite));
+        preview.parsed.push(ParsedFileName { game_id: game.game_id(), tags });
+    }
+    preview
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::game_listings::structs::game_list;
+
+    #[test]
+    fn test_preview() {
+        let mut game_list = game_list::new();
+        game_list.add_game_entry();
+        game_list.games[0].add_field("path", "./Super Mario World (USA, Europe) (Rev 1) [!].sfc".to_string());
+        game_list.games[0].add_field("name", "Super Mario World".to_string());
+        game_list.add_game_entry();
+
+        let preview = preview(&game_list, false);
+        assert_eq!(preview.parsed.len(), 1);
+        assert_eq!(preview.parsed[0].tags.revision.as_deref(), Some("Rev 1"));
+        let changes: Vec<(&str, &str)> = preview.changes.iter()
+            .map(|change| (change.field.as_str(), change.proposed.as_str()))
+            .collect();
+        assert_eq!(changes, vec![("region", "us,eu"), ("lang", "en")]);
+    }
+}
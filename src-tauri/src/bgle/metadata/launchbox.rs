@@ -0,0 +1,439 @@
+//! This module fills in game fields from LaunchBox's offline database, `Metadata.xml`.
+//!
+//! LaunchBox publishes its games database as a single large XML file, listing every game of every
+//! platform along with its alternate names:
+//!
+//! ```xml
+//! <LaunchBox>
+//!     <Game>
+//!         <Name>Super Mario World</Name>
+//!         <DatabaseID>140</DatabaseID>
+//!         <Platform>Super Nintendo Entertainment System</Platform>
+//!         <Developer>Nintendo EAD</Developer>
+//!         ...
+//!     </Game>
+//!     <GameAlternateName>
+//!         <AlternateName>Super Mario Bros. 4</AlternateName>
+//!         <DatabaseID>140</DatabaseID>
+//!     </GameAlternateName>
+//! </LaunchBox>
+//! ```
+//!
+//! The file is read as a stream and only the games of one platform are kept. Games carry no
+//! checksums, so they are matched to ROMs by name.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Serialize, Deserialize};
+
+use super::FieldChange;
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::metadata;
+use crate::bgle::roms::names;
+use crate::bgle::roms::tags;
+
+// Batocera system names with their LaunchBox platform name.
+const PLATFORMS: [(&str, &str); 40] = [
+    ("3do", "3DO Interactive Multiplayer"),
+    ("3ds", "Nintendo 3DS"),
+    ("amiga500", "Commodore Amiga"),
+    ("amstradcpc", "Amstrad CPC"),
+    ("atari2600", "Atari 2600"),
+    ("atari5200", "Atari 5200"),
+    ("atari7800", "Atari 7800"),
+    ("c64", "Commodore 64"),
+    ("colecovision", "ColecoVision"),
+    ("dos", "MS-DOS"),
+    ("dreamcast", "Sega Dreamcast"),
+    ("fbneo", "Arcade"),
+    ("gamecube", "Nintendo GameCube"),
+    ("gamegear", "Sega Game Gear"),
+    ("gb", "Nintendo Game Boy"),
+    ("gba", "Nintendo Game Boy Advance"),
+    ("gbc", "Nintendo Game Boy Color"),
+    ("intellivision", "Mattel Intellivision"),
+    ("jaguar", "Atari Jaguar"),
+    ("lynx", "Atari Lynx"),
+    ("mame", "Arcade"),
+    ("mastersystem", "Sega Master System"),
+    ("megadrive", "Sega Genesis"),
+    ("msx1", "Microsoft MSX"),
+    ("n64", "Nintendo 64"),
+    ("nds", "Nintendo DS"),
+    ("neogeo", "SNK Neo Geo AES"),
+    ("nes", "Nintendo Entertainment System"),
+    ("ngpc", "SNK Neo Geo Pocket Color"),
+    ("pcengine", "NEC TurboGrafx-16"),
+    ("ps2", "Sony Playstation 2"),
+    ("psp", "Sony PSP"),
+    ("psx", "Sony Playstation"),
+    ("saturn", "Sega Saturn"),
+    ("sega32x", "Sega 32X"),
+    ("segacd", "Sega CD"),
+    ("snes", "Super Nintendo Entertainment System"),
+    ("vectrex", "GCE Vectrex"),
+    ("virtualboy", "Nintendo Virtual Boy"),
+    ("wii", "Nintendo Wii"),
+];
+
+/// Errors which can occur while reading Metadata.xml.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file is not well formed XML.
+    Malformed(String),
+    /// The emulator directory has no LaunchBox platform.
+    UnknownPlatform(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Malformed(message) => write!(f, "Metadata.xml is malformed: {}", message),
+            Error::UnknownPlatform(system) => write!(f, "No LaunchBox platform is known for {}", system),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// A game listed in Metadata.xml. Values are as LaunchBox writes them, empty if not given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LaunchBoxGame {
+    pub database_id: String,
+    pub name: String,
+    pub alternate_names: Vec<String>,
+    pub overview: String,
+    pub developer: String,
+    pub publisher: String,
+    /// eg `1990-11-21T00:00:00-05:00`.
+    pub release_date: String,
+    pub release_year: String,
+    /// Genres separated by semicolons, eg `Action; Platform`.
+    pub genres: String,
+    pub max_players: String,
+}
+
+impl LaunchBoxGame {
+    /// The game fields given by the game, as field name and value pairs in gamelist.xml's formats.
+    pub fn field_values(&self) -> Vec<(&'static str, String)> {
+        let genres: Vec<&str> = self.genres.split(';').map(|genre| genre.trim()).filter(|genre| !genre.is_empty()).collect();
+        vec![
+            ("desc", self.overview.trim().to_string()),
+            ("developer", self.developer.clone()),
+            ("publisher", self.publisher.clone()),
+            ("releasedate", gamelist_date(&self.release_date, &self.release_year).unwrap_or_default()),
+            ("genre", genres.join(", ")),
+            ("players", self.max_players.trim().parse::<u32>().ok().and_then(metadata::players_range).unwrap_or_default()),
+        ]
+    }
+}
+
+/// The games of one platform read from Metadata.xml, indexed by their normalized names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LaunchBoxIndex {
+    pub platform: String,
+    pub games: Vec<LaunchBoxGame>,
+    names: HashMap<String, usize>,
+}
+
+impl LaunchBoxIndex {
+    // Returns the position of the game with a name, or an alternate name, which normalizes to the
+    // given normalized name.
+    fn find_exact(&self, normalized: &str) -> Option<usize> {
+        self.names.get(normalized).copied()
+    }
+
+    // Returns the position of the game whose name is most similar to the given normalized name, if
+    // it is similar enough.
+    fn find_similar(&self, normalized: &str) -> Option<usize> {
+        self.names.iter()
+            .map(|(name, position)| (names::similarity(normalized, name), *position))
+            .filter(|(similarity, _)| *similarity >= names::NAME_MATCH_THRESHOLD)
+            .max_by(|first, second| first.0.total_cmp(&second.0).then(second.1.cmp(&first.1)))
+            .map(|(_, position)| position)
+    }
+}
+
+/// A game matched to a LaunchBox game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LaunchBoxMatch {
+    pub game_id: usize,
+    pub database_id: String,
+    pub launchbox_name: String,
+    /// False when the names are only similar, such matches are worth a closer look.
+    pub exact: bool,
+}
+
+/// What matching a game list against Metadata.xml found, and the field changes it proposes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LaunchBoxPreview {
+    pub matches: Vec<LaunchBoxMatch>,
+    pub unmatched: Vec<usize>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Returns the LaunchBox platform of an emulator directory, eg `Super Nintendo Entertainment System`
+/// for `snes`. A platform given in `overrides` is preferred to the built in one.
+pub fn platform_for(system: &str, overrides: &HashMap<String, String>) -> Option<String> {
+    overrides.get(system).cloned()
+        .or_else(|| PLATFORMS.iter().find(|(name, _)| *name == system).map(|(_, platform)| platform.to_string()))
+}
+
+/// Reads the games of a platform from Metadata.xml.
+pub fn index(path: &Path, platform: &str) -> Result<LaunchBoxIndex, Error> {
+    index_reader(BufReader::new(File::open(path)?), platform)
+}
+
+/// Reads the games of a platform from the contents of a Metadata.xml file.
+pub fn index_reader<R: BufRead>(source: R, platform: &str) -> Result<LaunchBoxIndex, Error> {
+    let mut reader = Reader::from_reader(source);
+    let mut buffer = Vec::new();
+    let mut index = LaunchBoxIndex { platform: platform.to_string(), ..LaunchBoxIndex::default() };
+    let mut ids = HashMap::<String, usize>::new();
+
+    // the entry being read, its elements and the element whose text is being read
+    let mut entry: Option<String> = None;
+    let mut values = HashMap::<String, String>::new();
+    let mut element: Option<String> = None;
+    let mut depth = 0;
+
+    loop {
+        let event = reader.read_event_into(&mut buffer).map_err(|e| Error::Malformed(e.to_string()))?;
+        match event {
+            Event::Start(start) => {
+                depth += 1;
+                let name = String::from_utf8_lossy(start.name().as_ref()).to_string();
+                match depth {
+                    2 => entry = Some(name),
+                    3 => element = Some(name),
+                    _ => {}
+                }
+            }
+            Event::Text(text) => {
+                if let Some(element) = &element {
+                    let text = text.unescape().map_err(|e| Error::Malformed(e.to_string()))?;
+                    values.entry(element.clone()).or_default().push_str(&text);
+                }
+            }
+            Event::CData(cdata) => {
+                if let Some(element) = &element {
+                    values.entry(element.clone()).or_default().push_str(&String::from_utf8_lossy(&cdata.into_inner()));
+                }
+            }
+            Event::End(_) => {
+                match depth {
+                    2 => {
+                        let mut take = |name: &str| values.remove(name).unwrap_or_default();
+                        match entry.take().as_deref() {
+                            Some("Game") if take("Platform") == platform => {
+                                let game = LaunchBoxGame {
+                                    database_id: take("DatabaseID"),
+                                    name: take("Name"),
+                                    alternate_names: Vec::new(),
+                                    overview: take("Overview"),
+                                    developer: take("Developer"),
+                                    publisher: take("Publisher"),
+                                    release_date: take("ReleaseDate"),
+                                    release_year: take("ReleaseYear"),
+                                    genres: take("Genres"),
+                                    max_players: take("MaxPlayers"),
+                                };
+                                ids.insert(game.database_id.clone(), index.games.len());
+                                index.games.push(game);
+                            }
+                            // alternate names are listed after the games
+                            Some("GameAlternateName") => {
+                                if let Some(position) = ids.get(&take("DatabaseID")) {
+                                    index.games[*position].alternate_names.push(take("AlternateName"));
+                                }
+                            }
+                            _ => {}
+                        }
+                        values.clear();
+                    }
+                    3 => element = None,
+                    _ => {}
+                }
+                depth -= 1;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    for (position, game) in index.games.iter().enumerate() {
+        for name in std::iter::once(&game.name).chain(game.alternate_names.iter()) {
+            index.names.entry(normalized(name)).or_insert(position);
+        }
+    }
+    index.names.remove("");
+    Ok(index)
+}
+
+/// Matches the games of a game list to LaunchBox games by name and proposes their `desc`,
+/// `developer`, `publisher`, `releasedate`, `genre` and `players`.
+/// A game is matched by its name, or the title of its file name, first exactly once normalized and
+/// failing that to the most similar name.
+///
+/// ### Arguments
+/// * `game_list` - The game list.
+/// * `index` - The LaunchBox games of the game list's platform.
+/// * `overwrite` - Whether fields which already hold a value may be changed.
+/// ### Returns
+/// * `LaunchBoxPreview` - The matches, the unmatched games and the proposed changes, nothing is changed.
+pub fn preview(game_list: &GameList, index: &LaunchBoxIndex, overwrite: bool) -> LaunchBoxPreview {
+    let mut preview = LaunchBoxPreview::default();
+    for game in &game_list.games {
+        let candidates = game_names(game);
+        let found = candidates.iter().find_map(|name| index.find_exact(name)).map(|position| (position, true))
+            .or_else(|| candidates.iter().find_map(|name| index.find_similar(name)).map(|position| (position, false)));
+        let (position, exact) = match found {
+            Some(found) => found,
+            None => {
+                preview.unmatched.push(game.game_id());
+                continue;
+            }
+        };
+
+        let launchbox_game = &index.games[position];
+        preview.matches.push(LaunchBoxMatch {
+            game_id: game.game_id(),
+            database_id: launchbox_game.database_id.clone(),
+            launchbox_name: launchbox_game.name.clone(),
+            exact,
+        });
+        preview.changes.extend(super::propose_changes(game, &launchbox_game.field_values(), overwrite));
+    }
+    preview
+}
+
+// The normalized names a game may be listed under: its name and the title of its file name.
+fn game_names(game: &Game) -> Vec<String> {
+    let mut names = Vec::<String>::new();
+    for name in [game.field_value("name").map(String::from), game.field_value("path").map(|path| tags::parse_file_name(path).title)].into_iter().flatten() {
+        let name = normalized(&name);
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+// Normalizes a name, moving a trailing article to the front first so that
+// `Legend of Zelda, The` and `The Legend of Zelda` are the same.
+fn normalized(name: &str) -> String {
+    names::normalized_name(&tags::parse(name).title)
+}
+
+// Converts a LaunchBox release date, eg `1990-11-21T00:00:00-05:00`, or else the release year, to
+// gamelist.xml's format.
+fn gamelist_date(release_date: &str, release_year: &str) -> Option<String> {
+    let parts: Vec<u32> = release_date.get(..10).unwrap_or_default()
+        .split('-')
+        .filter_map(|part| part.parse::<u32>().ok())
+        .collect();
+    if let [year, month, day] = parts[..] {
+        return metadata::gamelist_date(year, month, day);
+    }
+    metadata::gamelist_date(release_year.trim().parse::<u32>().ok()?, 1, 1)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::game_listings::structs::game_list;
+
+    const METADATA: &str = r#"<?xml version="1.0" standalone="yes"?>
+<LaunchBox>
+  <Game>
+    <Name>Super Mario World</Name>
+    <ReleaseYear>1990</ReleaseYear>
+    <Overview>Mario &amp; Luigi visit Dinosaur Land.</Overview>
+    <MaxPlayers>2</MaxPlayers>
+    <DatabaseID>140</DatabaseID>
+    <Platform>Super Nintendo Entertainment System</Platform>
+    <Genres>Platform</Genres>
+    <Developer>Nintendo EAD</Developer>
+    <Publisher>Nintendo</Publisher>
+    <ReleaseDate>1990-11-21T00:00:00-05:00</ReleaseDate>
+  </Game>
+  <Game>
+    <Name>The Legend of Zelda: A Link to the Past</Name>
+    <ReleaseYear>1991</ReleaseYear>
+    <MaxPlayers>1</MaxPlayers>
+    <DatabaseID>141</DatabaseID>
+    <Platform>Super Nintendo Entertainment System</Platform>
+    <Genres>Action; Adventure</Genres>
+  </Game>
+  <Game>
+    <Name>Super Mario Bros.</Name>
+    <DatabaseID>10</DatabaseID>
+    <Platform>Nintendo Entertainment System</Platform>
+  </Game>
+  <GameAlternateName>
+    <AlternateName>Super Mario Bros. 4</AlternateName>
+    <DatabaseID>140</DatabaseID>
+    <Region>Japan</Region>
+  </GameAlternateName>
+</LaunchBox>"#;
+
+    #[test]
+    fn test_index() {
+        let index = index_reader(METADATA.as_bytes(), "Super Nintendo Entertainment System").unwrap();
+        assert_eq!(index.games.len(), 2);
+        assert_eq!(index.games[0].overview, "Mario & Luigi visit Dinosaur Land.");
+        assert_eq!(index.games[0].alternate_names, vec!["Super Mario Bros. 4"]);
+        assert_eq!(index.find_exact("super mario bros 4"), Some(0));
+        assert_eq!(platform_for("snes", &HashMap::new()).as_deref(), Some("Super Nintendo Entertainment System"));
+        let overrides = HashMap::from([("snes".to_string(), "Super Famicom".to_string())]);
+        assert_eq!(platform_for("snes", &overrides).as_deref(), Some("Super Famicom"));
+    }
+
+    #[test]
+    fn test_preview() {
+        let index = index_reader(METADATA.as_bytes(), "Super Nintendo Entertainment System").unwrap();
+        let mut game_list = game_list::new();
+        for _ in 0..3 {
+            game_list.add_game_entry();
+        }
+        game_list.games[0].add_field("path", "./Super Mario World (USA).sfc".to_string());
+        game_list.games[0].add_field("developer", "Nintendo".to_string());
+        game_list.games[1].add_field("path", "./Legend of Zelda, The - A Link to the Past (USA).sfc".to_string());
+        game_list.games[2].add_field("path", "./Unknown Game (USA).sfc".to_string());
+
+        let preview = preview(&game_list, &index, false);
+        let matches: Vec<(usize, &str, bool)> = preview.matches.iter()
+            .map(|found| (found.game_id, found.database_id.as_str(), found.exact))
+            .collect();
+        assert_eq!(matches, vec![(0, "140", true), (1, "141", true)]);
+        assert_eq!(preview.unmatched, vec![2]);
+
+        let changes: Vec<(usize, &str, &str)> = preview.changes.iter()
+            .map(|change| (change.game_id, change.field.as_str(), change.proposed.as_str()))
+            .collect();
+        assert_eq!(changes, vec![
+            (0, "desc", "Mario & Luigi visit Dinosaur Land."),
+            (0, "publisher", "Nintendo"),
+            (0, "releasedate", "19901121T000000"),
+            (0, "genre", "Platform"),
+            (0, "players", "1-2"),
+            (1, "releasedate", "19910101T000000"),
+            (1, "genre", "Action, Adventure"),
+            (1, "players", "1"),
+        ]);
+    }
+}
@@ -0,0 +1,285 @@
+//! This module fills in game fields from OpenVGDB (https://github.com/OpenVGDB/OpenVGDB), an
+//! SQLite database of ROM checksums and the releases they belong to, read entirely offline.
+//!
+//! The `ROMS` table lists each ROM with its checksums and file name, and the `RELEASES` table the
+//! releases of each ROM, with their title, description, developer, publisher, genre, release date
+//! and box art URLs. Region names are held in the `REGIONS` table.
+
+use std::fmt;
+use std::path::Path;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Serialize, Deserialize};
+
+use super::FieldChange;
+use super::dat::MatchedBy;
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::metadata;
+use crate::bgle::roms::tags;
+
+// The release of a ROM, the first release is used when a ROM has several.
+const RELEASE_QUERY: &str = "
+    SELECT RELEASES.releaseTitleName, RELEASES.releaseDescription, RELEASES.releaseDeveloper,
+           RELEASES.releasePublisher, RELEASES.releaseGenre, RELEASES.releaseDate,
+           RELEASES.releaseCoverFront, REGIONS.regionName
+    FROM ROMS
+    JOIN RELEASES ON RELEASES.romID = ROMS.romID
+    LEFT JOIN REGIONS ON REGIONS.regionID = ROMS.regionID
+    WHERE {} = ?1
+    ORDER BY RELEASES.releaseID
+    LIMIT 1";
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Errors which can occur while reading OpenVGDB.
+#[derive(Debug)]
+pub enum Error {
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Sqlite(e) => write!(f, "OpenVGDB could not be read: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Sqlite(error)
+    }
+}
+
+/// A release listed in OpenVGDB. Values are as OpenVGDB writes them, empty if not given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Release {
+    pub title: String,
+    pub description: String,
+    pub developer: String,
+    pub publisher: String,
+    pub genre: String,
+    /// eg `Aug 13, 1991` or `1991`.
+    pub date: String,
+    /// The URL of the front cover.
+    pub cover_url: String,
+    /// eg `USA`.
+    pub region: String,
+}
+
+impl Release {
+    /// The game fields given by the release, as field name and value pairs in gamelist.xml's formats.
+    pub fn field_values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("name", tags::parse(&self.title).title),
+            ("desc", self.description.trim().to_string()),
+            ("developer", self.developer.clone()),
+            ("publisher", self.publisher.clone()),
+            ("genre", self.genre.clone()),
+            ("releasedate", gamelist_date(&self.date).unwrap_or_default()),
+            ("region", tags::region_code(&self.region).unwrap_or_default().to_string()),
+        ]
+    }
+}
+
+/// An OpenVGDB database file.
+pub struct OpenVgdb {
+    connection: Connection,
+}
+
+impl OpenVgdb {
+    /// Opens an OpenVGDB database file, read only.
+    pub fn open(path: &Path) -> Result<OpenVgdb, Error> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+        Ok(OpenVgdb { connection })
+    }
+
+    /// Finds the release of a game's ROM, by its SHA1, MD5 or CRC32, in that order, and failing
+    /// those by its file name.
+    pub fn find(&self, game: &Game) -> Result<Option<(Release, MatchedBy)>, Error> {
+        let field = |name: &str| game.field_value(name).unwrap_or_default().to_uppercase();
+        let (sha1, md5, crc32) = match game.hashes() {
+            Some(hashes) => (hashes.sha1.to_uppercase(), hashes.md5.to_uppercase(), hashes.crc32.to_uppercase()),
+            None => (String::new(), field("md5"), field("crc32")),
+        };
+        let file_name = game.field_value("path")
+            .and_then(|path| Path::new(path).file_name())
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let lookups = [
+            ("upper(ROMS.romHashSHA1)", sha1, MatchedBy::Sha1),
+            ("upper(ROMS.romHashMD5)", md5, MatchedBy::Md5),
+            ("upper(ROMS.romHashCRC)", crc32, MatchedBy::Crc32),
+            ("ROMS.romFileName COLLATE NOCASE", file_name, MatchedBy::FileName),
+        ];
+        for (column, value, matched_by) in lookups {
+            if value.is_empty() {
+                continue;
+            }
+            if let Some(release) = self.release_where(column, &value)? {
+                return Ok(Some((release, matched_by)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn release_where(&self, column: &str, value: &str) -> Result<Option<Release>, Error> {
+        let mut statement = self.connection.prepare_cached(&RELEASE_QUERY.replace("{}", column))?;
+        let text = |row: &rusqlite::Row, index: usize| -> rusqlite::Result<String> {
+            Ok(row.get::<_, Option<String>>(index)?.unwrap_or_default())
+        };
+        let release = statement.query_row([value], |row| {
+            Ok(Release {
+                title: text(row, 0)?,
+                description: text(row, 1)?,
+                developer: text(row, 2)?,
+                publisher: text(row, 3)?,
+                genre: text(row, 4)?,
+                date: text(row, 5)?,
+                cover_url: text(row, 6)?,
+                region: text(row, 7)?,
+            })
+        }).optional()?;
+        Ok(release)
+    }
+}
+
+/// A game matched to an OpenVGDB release.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenVgdbMatch {
+    pub game_id: usize,
+    pub title: String,
+    pub matched_by: MatchedBy,
+    /// The URL of the release's front cover, empty if it has none.
+    pub cover_url: String,
+}
+
+/// What matching a game list against OpenVGDB found, and the field changes it proposes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OpenVgdbPreview {
+    pub matches: Vec<OpenVgdbMatch>,
+    pub unmatched: Vec<usize>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Matches the games of a game list against OpenVGDB and proposes their name, description,
+/// developer, publisher, genre, release date and region.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its games' hashes should already have been computed.
+/// * `database` - The OpenVGDB database.
+/// * `overwrite` - Whether fields which already hold a value may be changed.
+/// ### Returns
+/// * `Result<OpenVgdbPreview, Error>` - The matches, the unmatched games and the proposed changes, nothing is changed.
+pub fn preview(game_list: &GameList, database: &OpenVgdb, overwrite: bool) -> Result<OpenVgdbPreview, Error> {
+    let mut preview = OpenVgdbPreview::default();
+    for game in &game_list.games {
+        let (release, matched_by) = match database.find(game)? {
+            Some(found) => found,
+            None => {
+                preview.unmatched.push(game.game_id());
+                continue;
+            }
+        };
+        preview.changes.extend(super::propose_changes(game, &release.field_values(), overwrite));
+        preview.matches.push(OpenVgdbMatch {
+            game_id: game.game_id(),
+            title: release.title,
+            matched_by,
+            cover_url: release.cover_url,
+        });
+    }
+    Ok(preview)
+}
+
+// Converts an OpenVGDB release date, eg `Aug 13, 1991`, `Aug 1991` or `1991`, to gamelist.xml's
+// format.
+fn gamelist_date(date: &str) -> Option<String> {
+    let parts: Vec<&str> = date.split([' ', ',']).filter(|part| !part.is_empty()).collect();
+    let year = parts.last()?.parse::<u32>().ok()?;
+    let month = parts.first()
+        .and_then(|month| MONTHS.iter().position(|name| month.starts_with(name)))
+        .map(|position| position as u32 + 1)
+        .unwrap_or(1);
+    let day = match parts.len() {
+        3 => parts[1].parse::<u32>().ok()?,
+        _ => 1,
+    };
+    metadata::gamelist_date(year, month, day)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::game_listings::structs::game_list;
+    use crate::bgle::utils::TempDir;
+
+    // Creates a database with OpenVGDB's tables, holding two releases of one ROM and a second ROM.
+    fn database(name: &str) -> (TempDir, std::path::PathBuf) {
+        let directory = TempDir::new(&format!("openvgdb-{}", name));
+        let path = directory.join("openvgdb.sqlite");
+        let connection = Connection::open(&path).unwrap();
+        connection.execute_batch("
+            CREATE TABLE ROMS (romID INTEGER PRIMARY KEY, systemID INTEGER, regionID INTEGER, romHashCRC TEXT, romHashMD5 TEXT, romHashSHA1 TEXT, romSize INTEGER, romFileName TEXT);
+            CREATE TABLE RELEASES (releaseID INTEGER PRIMARY KEY, romID INTEGER, releaseTitleName TEXT, releaseCoverFront TEXT, releaseDescription TEXT, releaseDeveloper TEXT, releasePublisher TEXT, releaseGenre TEXT, releaseDate TEXT);
+            CREATE TABLE REGIONS (regionID INTEGER PRIMARY KEY, regionName TEXT);
+            INSERT INTO REGIONS VALUES (21, 'USA'), (13, 'Japan');
+            INSERT INTO ROMS VALUES (1, 26, 21, 'B19ED489', 'CDD3C8C37322978CA8669B34BC89C804', '6B47BB75D16514B6A476AA0C73A683A2A4C18765', 524288, 'Super Mario World (USA).sfc');
+            INSERT INTO ROMS VALUES (2, 26, 13, '0F6F3C5A', NULL, NULL, 1048576, 'Tetris & Dr. Mario (Japan).sfc');
+            INSERT INTO RELEASES VALUES (1, 1, 'Super Mario World', 'http://covers/smw.jpg', 'Mario is back.', 'Nintendo EAD', 'Nintendo', 'Platformer', 'Aug 13, 1991');
+            INSERT INTO RELEASES VALUES (2, 1, 'Super Mario World (Player''s Choice)', NULL, NULL, NULL, NULL, NULL, NULL);
+            INSERT INTO RELEASES VALUES (3, 2, 'Tetris & Dr. Mario', NULL, NULL, NULL, 'Nintendo', 'Puzzle', '1994');
+        ").unwrap();
+        (directory, path)
+    }
+
+    #[test]
+    fn test_preview() {
+        let (_directory, path) = database("preview");
+        let database = OpenVgdb::open(&path).unwrap();
+        let mut game_list = game_list::new();
+        for _ in 0..3 {
+            game_list.add_game_entry();
+        }
+        game_list.games[0].add_field("path", "./smw.sfc".to_string());
+        game_list.games[0].add_field("md5", "cdd3c8c37322978ca8669b34bc89c804".to_string());
+        game_list.games[1].add_field("path", "./Hacks/tetris & dr. mario (japan).sfc".to_string());
+        game_list.games[2].add_field("path", "./Unknown.sfc".to_string());
+
+        let preview = preview(&game_list, &database, false).unwrap();
+        let matches: Vec<(usize, &str, MatchedBy)> = preview.matches.iter()
+            .map(|found| (found.game_id, found.title.as_str(), found.matched_by))
+            .collect();
+        assert_eq!(matches, vec![(0, "Super Mario World", MatchedBy::Md5), (1, "Tetris & Dr. Mario", MatchedBy::FileName)]);
+        assert_eq!(preview.matches[0].cover_url, "http://covers/smw.jpg");
+        assert_eq!(preview.unmatched, vec![2]);
+
+        let changes: Vec<(usize, &str, &str)> = preview.changes.iter()
+            .map(|change| (change.game_id, change.field.as_str(), change.proposed.as_str()))
+            .collect();
+        assert_eq!(changes, vec![
+            (0, "name", "Super Mario World"),
+            (0, "desc", "Mario is back."),
+            (0, "developer", "Nintendo EAD"),
+            (0, "publisher", "Nintendo"),
+            (0, "genre", "Platformer"),
+            (0, "releasedate", "19910813T000000"),
+            (0, "region", "us"),
+            (1, "name", "Tetris & Dr. Mario"),
+            (1, "publisher", "Nintendo"),
+            (1, "genre", "Puzzle"),
+            (1, "releasedate", "19940101T000000"),
+            (1, "region", "jp"),
+        ]);
+    }
+
+    #[test]
+    fn test_gamelist_date() {
+        assert_eq!(gamelist_date("Aug 13, 1991"), Some("19910813T000000".to_string()));
+        assert_eq!(gamelist_date("November 1990"), Some("19901101T000000".to_string()));
+        assert_eq!(gamelist_date("unknown"), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! This module fills in game fields from libretro-database `.rdb` files, as installed with RetroArch.
+//!
+//! An rdb file holds the games of one system, eg `Nintendo - Super Nintendo Entertainment System.rdb`.
+//! It begins with the magic `RARCHDB\0` and the offset of its metadata, followed by one MessagePack
+//! map for each game and a nil ending the list:
+//!
+//! ```text
+//! {"name": "Super Mario World (USA)", "developer": "Nintendo", "releaseyear": 1991, "crc": <4 bytes>, ...}
+//! ```
+//!
+//! Games are looked up by the CRC32 of their ROM.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use serde_bytes::ByteBuf;
+
+use super::FieldChange;
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::metadata;
+use crate::bgle::roms::tags;
+
+const MAGIC: &[u8; 8] = b"RARCHDB\0";
+
+// Batocera system names with the name of their libretro-database rdb file, without `.rdb`.
+const DATABASES: [(&str, &str); 40] = [
+    ("3do", "The 3DO Company - 3DO"),
+    ("amiga500", "Commodore - Amiga"),
+    ("atari2600", "Atari - 2600"),
+    ("atari5200", "Atari - 5200"),
+    ("atari7800", "Atari - 7800"),
+    ("c64", "Commodore - 64"),
+    ("colecovision", "Coleco - ColecoVision"),
+    ("dreamcast", "Sega - Dreamcast"),
+    ("gamecube", "Nintendo - GameCube"),
+    ("gamegear", "Sega - Game Gear"),
+    ("gb", "Nintendo - Game Boy"),
+    ("gba", "Nintendo - Game Boy Advance"),
+    ("gbc", "Nintendo - Game Boy Color"),
+    ("intellivision", "Mattel - Intellivision"),
+    ("jaguar", "Atari - Jaguar"),
+    ("lynx", "Atari - Lynx"),
+    ("mastersystem", "Sega - Master System - Mark III"),
+    ("megadrive", "Sega - Mega Drive - Genesis"),
+    ("msx1", "Microsoft - MSX"),
+    ("n64", "Nintendo - Nintendo 64"),
+    ("nds", "Nintendo - Nintendo DS"),
+    ("nes", "Nintendo - Nintendo Entertainment System"),
+    ("ngp", "SNK - Neo Geo Pocket"),
+    ("ngpc", "SNK - Neo Geo Pocket Color"),
+    ("pcengine", "NEC - PC Engine - TurboGrafx 16"),
+    ("pcenginecd", "NEC - PC Engine CD - TurboGrafx-CD"),
+    ("ps2", "Sony - PlayStation 2"),
+    ("psp", "Sony - PlayStation Portable"),
+    ("psx", "Sony - PlayStation"),
+    ("saturn", "Sega - Saturn"),
+    ("sega32x", "Sega - 32X"),
+    ("segacd", "Sega - Mega-CD - Sega CD"),
+    ("sg1000", "Sega - SG-1000"),
+    ("snes", "Nintendo - Super Nintendo Entertainment System"),
+    ("vectrex", "GCE - Vectrex"),
+    ("virtualboy", "Nintendo - Virtual Boy"),
+    ("wii", "Nintendo - Wii"),
+    ("wswan", "Bandai - WonderSwan"),
+    ("wswanc", "Bandai - WonderSwan Color"),
+    ("zxspectrum", "Sinclair - ZX Spectrum +3"),
+];
+
+/// Errors which can occur while reading an rdb file.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file does not begin with the rdb magic.
+    NotAnRdb,
+    /// A game's entry could not be decoded.
+    Malformed(String),
+    /// No rdb file is known for the emulator directory.
+    UnknownSystem(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::NotAnRdb => write!(f, "The file is not a libretro-database rdb file"),
+            Error::Malformed(message) => write!(f, "The rdb file is malformed: {}", message),
+            Error::UnknownSystem(system) => write!(f, "No rdb file is known for {}", system),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// A game listed in an rdb file. Only the keys BGLE uses are read, values are empty if not given.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RdbGame {
+    /// The No-Intro style name, eg `Super Mario World (USA)`.
+    pub name: String,
+    pub developer: String,
+    pub publisher: String,
+    pub genre: String,
+    #[serde(rename = "releaseyear")]
+    pub release_year: Option<u32>,
+    #[serde(rename = "releasemonth")]
+    pub release_month: Option<u32>,
+    /// The number of players.
+    pub users: Option<u32>,
+    pub rom_name: String,
+    // the CRC32 of the ROM, as four big endian bytes
+    #[serde(skip_serializing)]
+    crc: Option<ByteBuf>,
+}
+
+impl RdbGame {
+    /// The CRC32 of the game's ROM, None if the rdb does not give it.
+    pub fn crc32(&self) -> Option<u32> {
+        let bytes: [u8; 4] = self.crc.as_ref()?.as_slice().try_into().ok()?;
+        Some(u32::from_be_bytes(bytes))
+    }
+
+    /// The game fields given by the game, as field name and value pairs in gamelist.xml's formats.
+    pub fn field_values(&self) -> Vec<(&'static str, String)> {
+        // a month of 0 is an unknown month
+        let month = self.release_month.filter(|month| *month != 0).unwrap_or(1);
+        let release_date = self.release_year.and_then(|year| metadata::gamelist_date(year, month, 1));
+        let players = self.users.and_then(metadata::players_range).unwrap_or_default();
+        vec![
+            ("name", tags::parse(&self.name).title),
+            ("developer", self.developer.clone()),
+            ("publisher", self.publisher.clone()),
+            ("releasedate", release_date.unwrap_or_default()),
+            ("genre", self.genre.clone()),
+            ("players", players),
+        ]
+    }
+}
+
+/// The games of an rdb file, indexed by CRC32.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RdbIndex {
+    pub games: Vec<RdbGame>,
+    crcs: HashMap<u32, usize>,
+}
+
+impl RdbIndex {
+    /// Returns the game whose ROM has the given CRC32.
+    pub fn find(&self, crc32: u32) -> Option<&RdbGame> {
+        self.crcs.get(&crc32).map(|position| &self.games[*position])
+    }
+}
+
+/// A game matched to an rdb entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RdbMatch {
+    pub game_id: usize,
+    /// The name of the rdb game, eg `Super Mario World (USA)`.
+    pub rdb_game: String,
+}
+
+/// What matching a game list against an rdb file found, and the field changes it proposes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RdbPreview {
+    pub matches: Vec<RdbMatch>,
+    /// The games which match no entry, or whose ROM has not been hashed.
+    pub unmatched: Vec<usize>,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Returns the name of the rdb file of an emulator directory, eg
+/// `Nintendo - Super Nintendo Entertainment System.rdb` for `snes`. A file name given in `overrides`
+/// is preferred to the built in one.
+pub fn database_for(system: &str, overrides: &HashMap<String, String>) -> Option<String> {
+    overrides.get(system).cloned()
+        .or_else(|| DATABASES.iter().find(|(name, _)| *name == system).map(|(_, database)| format!("{}.rdb", database)))
+}
+
+/// Reads an rdb file.
+pub fn read(path: &Path) -> Result<RdbIndex, Error> {
+    read_from(BufReader::new(File::open(path)?))
+}
+
+/// Reads the contents of an rdb file.
+pub fn read_from<R: Read>(mut reader: R) -> Result<RdbIndex, Error> {
+    // the magic is followed by the offset of the metadata, which is not needed
+    let mut header = [0u8; 16];
+    reader.read_exact(&mut header).map_err(|_| Error::NotAnRdb)?;
+    if &header[..8] != MAGIC {
+        return Err(Error::NotAnRdb);
+    }
+
+    let mut index = RdbIndex::default();
+    let mut decoder = rmp_serde::Deserializer::new(reader);
+    // the games are followed by a nil
+    while let Some(game) = Option::<RdbGame>::deserialize(&mut decoder).map_err(|e| Error::Malformed(e.to_string()))? {
+        if let Some(crc32) = game.crc32() {
+            index.crcs.entry(crc32).or_insert(index.games.len());
+        }
+        index.games.push(game);
+    }
+    Ok(index)
+}
+
+/// Looks up the games of a game list by the CRC32 of their ROM and proposes their `name`,
+/// `developer`, `publisher`, `releasedate`, `genre` and `players`.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its games' hashes should already have been computed.
+/// * `index` - The games of the rdb file of the game list's system.
+/// * `overwrite` - Whether fields which already hold a value may be changed.
+/// ### Returns
+/// * `RdbPreview` - The matches, the unmatched games and the proposed changes, nothing is changed.
+pub fn preview(game_list: &GameList, index: &RdbIndex, overwrite: bool) -> RdbPreview {
+    let mut preview = RdbPreview::default();
+    for game in &game_list.games {
+        let found = game_crc32(game).and_then(|crc32| index.find(crc32));
+        let rdb_game = match found {
+            Some(rdb_game) => rdb_game,
+            None => {
+                preview.unmatched.push(game.game_id());
+                continue;
+            }
+        };
+        preview.matches.push(RdbMatch { game_id: game.game_id(), rdb_game: rdb_game.name.clone() });
+        preview.changes.extend(super::propose_changes(game, &rdb_game.field_values(), overwrite));
+    }
+    preview
+}
+
+// The CRC32 of a game's ROM, from its computed hashes or else its crc32 field.
+fn game_crc32(game: &Game) -> Option<u32> {
+    let crc32 = match game.hashes() {
+        Some(hashes) => hashes.crc32.as_str(),
+        None => game.field_value("crc32")?,
+    };
+    u32::from_str_radix(crc32, 16).ok()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::game_listings::structs::game_list;
+
+    #[derive(Serialize)]
+    struct Entry {
+        name: &'static str,
+        developer: &'static str,
+        releaseyear: u32,
+        releasemonth: u32,
+        users: u32,
+        serial: ByteBuf,
+        crc: ByteBuf,
+    }
+
+    // Encodes an rdb file as libretro-db writes them.
+    fn rdb(entries: &[Entry]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[0u8; 8]);
+        for entry in entries {
+            rmp_serde::encode::write_named(&mut bytes, entry).unwrap();
+        }
+        bytes.push(0xc0);
+        bytes
+    }
+
+    #[test]
+    fn test_read_and_preview() {
+        let bytes = rdb(&[
+            Entry { name: "Super Mario World (USA)", developer: "Nintendo", releaseyear: 1991, releasemonth: 8, users: 2, serial: ByteBuf::from(b"SNS-MW".to_vec()), crc: ByteBuf::from(vec![0xb1, 0x9e, 0xd4, 0x89]) },
+            Entry { name: "Tetris (World)", developer: "", releaseyear: 1989, releasemonth: 0, users: 1, serial: ByteBuf::new(), crc: ByteBuf::from(vec![0x46, 0xdf, 0x91, 0x1e]) },
+        ]);
+        let index = read_from(bytes.as_slice()).unwrap();
+        assert_eq!(index.games.len(), 2);
+        assert_eq!(index.find(0xb19ed489).map(|game| game.name.as_str()), Some("Super Mario World (USA)"));
+
+        let mut game_list = game_list::new();
+        game_list.add_game_entry();
+        game_list.games[0].add_field("path", "./smw.sfc".to_string());
+        game_list.games[0].add_field("crc32", "B19ED489".to_string());
+        game_list.add_game_entry();
+        game_list.games[1].add_field("path", "./unhashed.sfc".to_string());
+
+        let preview = preview(&game_list, &index, false);
+        assert_eq!(preview.matches, vec![RdbMatch { game_id: 0, rdb_game: "Super Mario World (USA)".to_string() }]);
+        assert_eq!(preview.unmatched, vec![1]);
+        let changes: Vec<(&str, &str)> = preview.changes.iter()
+            .map(|change| (change.field.as_str(), change.proposed.as_str()))
+            .collect();
+        assert_eq!(changes, vec![("name", "Super Mario World"), ("developer", "Nintendo"), ("releasedate", "19910801T000000"), ("players", "1-2")]);
+    }
+
+    #[test]
+    fn test_read_rejects_other_files() {
+        assert!(matches!(read_from(&b"<?xml version=\"1.0\"?>"[..]), Err(Error::NotAnRdb)));
+        assert_eq!(database_for("snes", &HashMap::new()).as_deref(), Some("Nintendo - Super Nintendo Entertainment System.rdb"));
+    }
+}
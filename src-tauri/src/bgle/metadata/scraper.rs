@@ -0,0 +1,395 @@
+//! This module looks games up in online metadata databases, such as ScreenScraper, as Batocera's
+//! own scraper does.
+//!
+//! Each database is a provider implementing the `Scraper` trait. A provider is given what is known
+//! about a game, its ROM's checksums, name and system, and returns the fields it found along with
+//! the URLs of the game's media. Providers make their requests through an `HttpClient`, so they can
+//! be pointed at a local server while testing.
+
+pub mod screenscraper;
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::FieldChange;
+use crate::bgle::game_listings::structs::game::Game;
+use crate::bgle::game_listings::structs::game_list::GameList;
+use crate::bgle::media::MediaKind;
+use crate::bgle::media::import::{self, ImportOptions};
+use crate::bgle::roms;
+use crate::bgle::roms::checksums::RomHashes;
+
+/// Errors which can occur while scraping.
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be made, eg the server could not be reached.
+    Http(String),
+    /// The server answered with an error status.
+    Status(u16, String),
+    /// The server's answer could not be understood.
+    Parse(String),
+    /// The system is not known to the provider.
+    UnknownSystem(String),
+    /// The answer is larger than the most a client reads, in bytes.
+    TooLarge(u64),
+    Io(io::Error),
+    Import(import::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(message) => write!(f, "The request failed: {}", message),
+            Error::Status(status, message) => write!(f, "The server answered {}: {}", status, message),
+            Error::Parse(message) => write!(f, "The answer could not be read: {}", message),
+            Error::UnknownSystem(system) => write!(f, "The {} system is not supported by the scraper", system),
+            Error::TooLarge(limit) => write!(f, "The answer is larger than {} bytes", limit),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Import(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<import::Error> for Error {
+    fn from(error: import::Error) -> Self {
+        Error::Import(error)
+    }
+}
+
+/// The answer to an HTTP request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Makes the HTTP requests of the scrapers.
+pub trait HttpClient {
+    /// Sends a GET request, the query parameters are encoded and added to the URL. Error statuses
+    /// are returned as responses, only failures to make the request are errors.
+    fn get(&self, url: &str, query: &[(String, String)]) -> Result<HttpResponse, Error>;
+}
+
+/// The HTTP client used outside of tests.
+pub struct UreqClient {
+    agent: ureq::Agent,
+    max_response_size: u64,
+}
+
+// The largest answer read, larger media files are refused.
+const MAX_RESPONSE_SIZE: u64 = 200 * 1024 * 1024;
+
+impl Default for UreqClient {
+    fn default() -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(std::time::Duration::from_secs(30))
+            .user_agent(concat!("bgle/", env!("CARGO_PKG_VERSION")))
+            .build();
+        UreqClient { agent, max_response_size: MAX_RESPONSE_SIZE }
+    }
+}
+
+impl HttpClient for UreqClient {
+    fn get(&self, url: &str, query: &[(String, String)]) -> Result<HttpResponse, Error> {
+        let mut request = self.agent.get(url);
+        for (name, value) in query {
+            request = request.query(name, value);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(Error::Http(e.to_string())),
+        };
+        let status = response.status();
+        // one byte more than the limit is read, to tell a full answer from a cut one
+        let mut body = Vec::new();
+        response.into_reader().take(self.max_response_size + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > self.max_response_size {
+            return Err(Error::TooLarge(self.max_response_size));
+        }
+        Ok(HttpResponse { status, body })
+    }
+}
+
+/// What is known about a game when looking it up.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrapeQuery {
+    /// The Batocera system, the name of the emulator directory, eg `snes`.
+    pub system: String,
+    /// The file name of the ROM, eg `Super Mario World (USA).sfc`.
+    pub file_name: String,
+    /// The game's name, or the title of its file name if it has none.
+    pub name: String,
+    pub hashes: Option<RomHashes>,
+}
+
+impl ScrapeQuery {
+    /// Builds the query for a game of a game list.
+    pub fn from_game(system: &str, game: &Game) -> ScrapeQuery {
+        let path = game.field_value("path").unwrap_or_default();
+        let file_name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let name = match game.field_value("name") {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => roms::tags::parse_file_name(path).title,
+        };
+        ScrapeQuery { system: system.to_string(), file_name: file_name.to_string(), name, hashes: game.hashes().cloned() }
+    }
+}
+
+/// A media file found for a game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScrapedMedia {
+    pub kind: MediaKind,
+    pub url: String,
+    /// The file extension of the media, eg `png` or `mp4`.
+    pub extension: String,
+}
+
+/// What a scraper found for a game.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrapeResult {
+    /// The game fields found, as field name and value pairs, with values in gamelist.xml's formats.
+    pub fields: Vec<(String, String)>,
+    pub media: Vec<ScrapedMedia>,
+}
+
+/// An online metadata database.
+pub trait Scraper {
+    /// The name of the database, eg `ScreenScraper`.
+    fn name(&self) -> &str;
+
+    /// Looks a game up, returning None if the database does not know it.
+    fn scrape(&self, query: &ScrapeQuery) -> Result<Option<ScrapeResult>, Error>;
+}
+
+/// A media file to download for one of a game's fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaDownload {
+    pub game_id: usize,
+    pub media: ScrapedMedia,
+    /// The value of the field before the download, empty if the field is not set.
+    pub current: String,
+}
+
+/// What scraping a game list found: the proposed field changes and media downloads.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrapePreview {
+    pub changes: Vec<FieldChange>,
+    pub media: Vec<MediaDownload>,
+    /// The games the database does not know.
+    pub unmatched: Vec<usize>,
+    /// The games which could not be looked up, with the reason.
+    pub failed: Vec<(usize, String)>,
+}
+
+/// Looks up the games of a game list and proposes changes to their fields and the media to
+/// download. Nothing is changed or downloaded.
+///
+/// ### Arguments
+/// * `game_list` - The game list, its games' hashes should already have been computed.
+/// * `scraper` - The database to look the games up in.
+/// * `game_ids` - The games to look up, all of them if empty.
+/// * `overwrite` - Whether fields and media which are already set may be changed.
+pub fn preview(game_list: &GameList, scraper: &dyn Scraper, game_ids: &[usize], overwrite: bool) -> ScrapePreview {
+    let system = game_list.directory.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let mut preview = ScrapePreview::default();
+
+    let games = game_list.games.iter().filter(|game| game_ids.is_empty() || game_ids.contains(&game.game_id()));
+    for game in games {
+        let result = match scraper.scrape(&ScrapeQuery::from_game(system, game)) {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                preview.unmatched.push(game.game_id());
+                continue;
+            }
+            Err(e) => {
+                preview.failed.push((game.game_id(), format!("{}: {}", scraper.name(), e)));
+                continue;
+            }
+        };
+
+        let values: Vec<(&str, String)> = result.fields.iter().map(|(field, value)| (field.as_str(), value.clone())).collect();
+        preview.changes.extend(super::propose_changes(game, &values, overwrite));
+        for media in result.media {
+            let current = game.field_value(media.kind.field()).unwrap_or_default();
+            if overwrite || current.is_empty() {
+                preview.media.push(MediaDownload { game_id: game.game_id(), media, current: current.to_string() });
+            }
+        }
+    }
+    preview
+}
+
+/// Downloads a game's media into the emulator directory and sets the game's field to it. Images go
+/// through the same import as images chosen by hand, so they can be converted and resized.
+///
+/// ### Arguments
+/// * `client` - The HTTP client to download with.
+/// * `game_list` - The game list, its directory must be the emulator directory.
+/// * `download` - The media to download.
+/// * `options` - How images are converted and resized.
+/// ### Returns
+/// * `Result<String, Error>` - The path of the media as written in gamelist.xml.
+pub fn download_media(client: &dyn HttpClient, game_list: &mut GameList, download: &MediaDownload, options: &ImportOptions) -> Result<String, Error> {
+    let emulator_dir = game_list.directory.clone();
+    let game = game_list.get_mut(download.game_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The game is no longer in the game list"))?;
+    let rom_path = game.field_value("path").unwrap_or_default().to_string();
+
+    let response = client.get(&download.media.url, &[])?;
+    if response.status != 200 {
+        return Err(Error::Status(response.status, download.media.url.clone()));
+    }
+
+    // the download is sent back by the frontend, so its extension is checked again
+    let kind = download.media.kind;
+    let extension = kind.accepted_extension(&download.media.extension);
+    let path = if kind == MediaKind::Video {
+        let path = kind.rom_media_path(&rom_path, extension)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The game does not have a ROM path"))?;
+        let destination = roms::rom_file_path(&emulator_dir, &path);
+        if let Some(folder) = destination.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(destination, &response.body)?;
+        path
+    }
+    else {
+        // the image is imported from a temporary file, as images chosen by hand are
+        let source = std::env::temp_dir().join(format!("bgle-scraped-{}-{}.{}", std::process::id(), download.game_id, extension));
+        fs::write(&source, &response.body)?;
+        let imported = import::import_image(&emulator_dir, &rom_path, kind.field(), &source, options);
+        let _ = fs::remove_file(&source);
+        imported?
+    };
+
+    game.set_field(kind.field(), path.clone());
+    Ok(path)
+}
+
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use crate::bgle::game_listings::structs::game_list;
+
+    /// Starts a local HTTP server which answers each request with the response of the first route
+    /// whose path the request's path starts with, or 404. Returns the server's base URL and a
+    /// handle which returns the request lines received, once `requests` requests have been served.
+    pub(crate) fn mock_server(routes: Vec<(&'static str, u16, Vec<u8>)>, requests: usize) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut received = Vec::<String>::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // skip the headers
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let target = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let (status, body) = routes.iter()
+                    .find(|(path, _, _)| target.starts_with(path))
+                    .map(|(_, status, body)| (*status, body.clone()))
+                    .unwrap_or((404, b"not found".to_vec()));
+                write!(stream, "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len()).unwrap();
+                stream.write_all(&body).unwrap();
+                received.push(target);
+            }
+            received
+        });
+        (url, handle)
+    }
+
+    struct FixedScraper;
+
+    impl Scraper for FixedScraper {
+        fn name(&self) -> &str {
+            "Fixed"
+        }
+
+        fn scrape(&self, query: &ScrapeQuery) -> Result<Option<ScrapeResult>, Error> {
+            if query.name != "Super Mario World" {
+                return Ok(None);
+            }
+            Ok(Some(ScrapeResult {
+                fields: vec![("name".to_string(), "Super Mario World".to_string()), ("developer".to_string(), "Nintendo".to_string())],
+                media: vec![ScrapedMedia { kind: MediaKind::Video, url: "http://localhost/video".to_string(), extension: "mp4".to_string() }],
+            }))
+        }
+    }
+
+    #[test]
+    fn test_preview() {
+        let mut game_list = game_list::new();
+        game_list.directory = std::path::PathBuf::from("/roms/snes");
+        game_list.add_game_entry();
+        game_list.games[0].add_field("path", "./Super Mario World (USA).sfc".to_string());
+        game_list.add_game_entry();
+        game_list.games[1].add_field("path", "./Unknown.sfc".to_string());
+
+        let preview = preview(&game_list, &FixedScraper, &[], false);
+        let changes: Vec<(usize, &str, &str)> = preview.changes.iter()
+            .map(|change| (change.game_id, change.field.as_str(), change.proposed.as_str()))
+            .collect();
+        assert_eq!(changes, vec![(0, "name", "Super Mario World"), (0, "developer", "Nintendo")]);
+        assert_eq!(preview.media.len(), 1);
+        assert_eq!(preview.unmatched, vec![1]);
+        assert_eq!(ScrapeQuery::from_game("snes", &game_list.games[1]).file_name, "Unknown.sfc");
+    }
+
+    #[test]
+    fn test_download_media() {
+        let directory = TempDir::new("scraper-download");
+        let (url, server) = mock_server(vec![("/video", 200, b"\0\0\0\x18ftypmp42".to_vec())], 2);
+
+        let mut game_list = game_list::new();
+        game_list.directory = directory.to_path_buf();
+        game_list.add_game_entry();
+        game_list.games[0].add_field("path", "./Game (USA).sfc".to_string());
+        let download = MediaDownload {
+            game_id: 0,
+            media: ScrapedMedia { kind: MediaKind::Video, url: format!("{}/video", url), extension: "mp4".to_string() },
+            current: String::new(),
+        };
+
+        let path = download_media(&UreqClient::default(), &mut game_list, &download, &ImportOptions::default()).unwrap();
+        assert_eq!(path, "./videos/Game (USA)-video.mp4");
+        assert_eq!(game_list.games[0].field_value("video"), Some(path.as_str()));
+        assert_eq!(fs::read(directory.join("videos/Game (USA)-video.mp4")).unwrap(), b"\0\0\0\x18ftypmp42");
+
+        // an extension which is not a media extension cannot lead the file out of the videos folder
+        let download = MediaDownload { media: ScrapedMedia { extension: "../../x".to_string(), ..download.media }, ..download };
+        let path = download_media(&UreqClient::default(), &mut game_list, &download, &ImportOptions::default()).unwrap();
+        assert_eq!(path, "./videos/Game (USA)-video.mp4");
+        assert_eq!(server.join().unwrap(), vec!["/video", "/video"]);
+    }
+
+    #[test]
+    fn test_response_size_limit() {
+        let (url, server) = mock_server(vec![("/small", 200, b"1234".to_vec()), ("/large", 200, b"12345".to_vec())], 2);
+        let client = UreqClient { max_response_size: 4, ..UreqClient::default() };
+
+        assert_eq!(client.get(&format!("{}/small", url), &[]).unwrap().body, b"1234");
+        // a larger answer is refused rather than cut short
+        assert!(matches!(client.get(&format!("{}/large", url), &[]), Err(Error::TooLarge(4))));
+        server.join().unwrap();
+    }
+}
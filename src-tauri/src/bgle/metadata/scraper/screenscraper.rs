@@ -0,0 +1,334 @@
+//! This module looks games up in ScreenScraper (https://www.screenscraper.fr), the database used
+//! by Batocera's own scraper.
+//!
+//! Games are looked up with the `jeuInfos.php` call of ScreenScraper's v2 API, which matches a ROM
+//! by its checksums, file name and size within a system. A developer id and password are needed to
+//! use the API, and a user's id and password raise the number of requests allowed each day.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use super::{Error, HttpClient, ScrapeQuery, ScrapeResult, ScrapedMedia, Scraper};
+use crate::bgle::media::MediaKind;
+use crate::bgle::metadata;
+
+/// The address of ScreenScraper's API.
+pub const API_URL: &str = "https://api.screenscraper.fr/api2";
+
+// Batocera system names with their ScreenScraper system id.
+const SYSTEMS: [(&str, u32); 48] = [
+    ("megadrive", 1),
+    ("genesis", 1),
+    ("mastersystem", 2),
+    ("nes", 3),
+    ("snes", 4),
+    ("gb", 9),
+    ("gbc", 10),
+    ("virtualboy", 11),
+    ("gba", 12),
+    ("gamecube", 13),
+    ("n64", 14),
+    ("nds", 15),
+    ("wii", 16),
+    ("3ds", 17),
+    ("sega32x", 19),
+    ("segacd", 20),
+    ("gamegear", 21),
+    ("saturn", 22),
+    ("dreamcast", 23),
+    ("ngp", 25),
+    ("atari2600", 26),
+    ("jaguar", 27),
+    ("lynx", 28),
+    ("3do", 29),
+    ("pcengine", 31),
+    ("atari5200", 40),
+    ("atari7800", 41),
+    ("wswan", 45),
+    ("wswanc", 46),
+    ("colecovision", 48),
+    ("psx", 57),
+    ("ps2", 58),
+    ("psp", 61),
+    ("amiga500", 64),
+    ("amstradcpc", 65),
+    ("c64", 66),
+    ("mame", 75),
+    ("fbneo", 75),
+    ("zxspectrum", 76),
+    ("ngpc", 82),
+    ("vectrex", 102),
+    ("msx1", 113),
+    ("pcenginecd", 114),
+    ("intellivision", 115),
+    ("scummvm", 123),
+    ("dos", 135),
+    ("neogeo", 142),
+    ("sg1000", 109),
+];
+
+// The ScreenScraper media types used for each kind of media, in order of preference, as Batocera
+// uses them: a screenshot for the image, the box for the thumbnail and the logo for the marquee.
+const MEDIA_TYPES: [(MediaKind, &[&str]); 4] = [
+    (MediaKind::Image, &["ss"]),
+    (MediaKind::Thumbnail, &["box-2D"]),
+    (MediaKind::Marquee, &["wheel", "wheel-hd"]),
+    (MediaKind::Video, &["video-normalized", "video"]),
+];
+
+/// The settings of the ScreenScraper provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScreenScraperConfig {
+    /// The address of the API, changed to point at a local server while testing.
+    pub api_url: String,
+    pub dev_id: String,
+    pub dev_password: String,
+    /// The name of the software making the requests.
+    pub soft_name: String,
+    pub user_id: Option<String>,
+    pub user_password: Option<String>,
+    /// ScreenScraper region codes in order of preference, used to choose names, dates and media.
+    pub regions: Vec<String>,
+    /// Language codes in order of preference, used to choose descriptions and genres.
+    pub languages: Vec<String>,
+}
+
+impl Default for ScreenScraperConfig {
+    fn default() -> Self {
+        ScreenScraperConfig {
+            api_url: API_URL.to_string(),
+            dev_id: String::new(),
+            dev_password: String::new(),
+            soft_name: String::from("bgle"),
+            user_id: None,
+            user_password: None,
+            regions: ["us", "wor", "eu", "ss", "jp"].iter().map(|region| region.to_string()).collect(),
+            languages: vec![String::from("en")],
+        }
+    }
+}
+
+/// Looks games up in ScreenScraper.
+pub struct ScreenScraper<C: HttpClient> {
+    config: ScreenScraperConfig,
+    client: C,
+}
+
+impl<C: HttpClient> ScreenScraper<C> {
+    pub fn new(config: ScreenScraperConfig, client: C) -> ScreenScraper<C> {
+        ScreenScraper { config, client }
+    }
+
+    // The query parameters of a jeuInfos.php request.
+    fn parameters(&self, query: &ScrapeQuery, system_id: u32) -> Vec<(String, String)> {
+        let mut parameters = vec![
+            ("devid", self.config.dev_id.clone()),
+            ("devpassword", self.config.dev_password.clone()),
+            ("softname", self.config.soft_name.clone()),
+            ("output", String::from("json")),
+            ("systemeid", system_id.to_string()),
+            ("romtype", String::from("rom")),
+            ("romnom", query.file_name.clone()),
+        ];
+        if let (Some(user_id), Some(user_password)) = (&self.config.user_id, &self.config.user_password) {
+            parameters.push(("ssid", user_id.clone()));
+            parameters.push(("sspassword", user_password.clone()));
+        }
+        if let Some(hashes) = &query.hashes {
+            parameters.push(("romtaille", hashes.size.to_string()));
+            parameters.push(("crc", hashes.crc32.to_uppercase()));
+            parameters.push(("md5", hashes.md5.clone()));
+            parameters.push(("sha1", hashes.sha1.clone()));
+        }
+        parameters.into_iter().map(|(name, value)| (name.to_string(), value)).collect()
+    }
+
+    // Reads the fields and media of a jeuInfos.php answer's game.
+    fn read_game(&self, game: &Value) -> ScrapeResult {
+        let mut result = ScrapeResult::default();
+        let mut add = |field: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                result.fields.push((field.to_string(), value));
+            }
+        };
+
+        add("name", self.preferred(&game["noms"], "region", &self.config.regions));
+        add("desc", self.preferred(&game["synopsis"], "langue", &self.config.languages));
+        add("releasedate", self.preferred(&game["dates"], "region", &self.config.regions).and_then(|date| gamelist_date(&date)));
+        add("developer", text(&game["developpeur"]));
+        add("publisher", text(&game["editeur"]));
+        add("genre", game["genres"].as_array().map(|genres| {
+            genres.iter()
+                .filter_map(|genre| self.preferred(&genre["noms"], "langue", &self.config.languages))
+                .collect::<Vec<String>>()
+                .join(", ")
+        }));
+        add("players", text(&game["joueurs"]));
+        // ratings are out of 20, gamelist.xml's are between 0 and 1
+        add("rating", text(&game["note"]).and_then(|note| note.parse::<f32>().ok()).map(|note| format!("{:.2}", note / 20.0)));
+
+        let medias = game["medias"].as_array().cloned().unwrap_or_default();
+        for (kind, types) in MEDIA_TYPES {
+            let found = types.iter().find_map(|media_type| {
+                let candidates: Vec<&Value> = medias.iter().filter(|media| media["type"] == *media_type).collect();
+                self.config.regions.iter()
+                    .find_map(|region| candidates.iter().find(|media| media["region"] == region.as_str()))
+                    .or_else(|| candidates.first())
+                    .copied()
+            });
+            if let Some(media) = found {
+                let url = media["url"].as_str().unwrap_or_default().to_string();
+                let extension = kind.accepted_extension(media["format"].as_str().unwrap_or_default()).to_string();
+                if !url.is_empty() {
+                    result.media.push(ScrapedMedia { kind, url, extension });
+                }
+            }
+        }
+        result
+    }
+
+    // Chooses the text of a list of localised texts, eg `[{"region": "us", "text": "..."}]`, by the
+    // order of preference, falling back to the first text.
+    fn preferred(&self, texts: &Value, key: &str, preference: &[String]) -> Option<String> {
+        let texts = texts.as_array()?;
+        preference.iter()
+            .find_map(|wanted| texts.iter().find(|text| text[key] == wanted.as_str()))
+            .or_else(|| texts.first())
+            .and_then(text)
+    }
+}
+
+impl<C: HttpClient> Scraper for ScreenScraper<C> {
+    fn name(&self) -> &str {
+        "ScreenScraper"
+    }
+
+    fn scrape(&self, query: &ScrapeQuery) -> Result<Option<ScrapeResult>, Error> {
+        let system_id = SYSTEMS.iter()
+            .find(|(system, _)| *system == query.system)
+            .map(|(_, id)| *id)
+            .ok_or_else(|| Error::UnknownSystem(query.system.clone()))?;
+
+        let url = format!("{}/jeuInfos.php", self.config.api_url.trim_end_matches('/'));
+        let response = self.client.get(&url, &self.parameters(query, system_id))?;
+        match response.status {
+            200 => {}
+            // the game is not in the database
+            404 => return Ok(None),
+            status => return Err(Error::Status(status, String::from_utf8_lossy(&response.body).trim().to_string())),
+        }
+
+        let answer: Value = serde_json::from_slice(&response.body).map_err(|e| Error::Parse(e.to_string()))?;
+        let game = &answer["response"]["jeu"];
+        if !game.is_object() {
+            return Ok(None);
+        }
+        Ok(Some(self.read_game(game)))
+    }
+}
+
+// Returns the text of a ScreenScraper value, either `{"text": "..."}` or a list of them.
+fn text(value: &Value) -> Option<String> {
+    value["text"].as_str().or_else(|| value.as_str()).map(|text| text.trim().to_string())
+}
+
+// Converts a ScreenScraper date, `1991-08-13`, `1991-08` or `1991`, to gamelist.xml's format.
+fn gamelist_date(date: &str) -> Option<String> {
+    let mut parts = date.trim().split('-').map(|part| part.parse::<u32>().ok());
+    let year = parts.next()??;
+    let month = parts.next().unwrap_or(Some(1))?;
+    let day = parts.next().unwrap_or(Some(1))?;
+    metadata::gamelist_date(year, month, day)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::metadata::scraper::UreqClient;
+    use crate::bgle::metadata::scraper::test::mock_server;
+    use crate::bgle::roms::checksums::RomHashes;
+
+    const ANSWER: &str = r#"{"response": {"jeu": {
+        "id": "1234",
+        "noms": [{"region": "ss", "text": "Super Mario World"}, {"region": "jp", "text": "Super Mario World: Super Mario Bros. 4"}],
+        "synopsis": [{"langue": "fr", "text": "Mario est de retour."}, {"langue": "en", "text": "Mario is back."}],
+        "dates": [{"region": "jp", "text": "1990-11-21"}, {"region": "us", "text": "1991-08"}],
+        "developpeur": {"id": "1", "text": "Nintendo EAD"},
+        "editeur": {"id": "2", "text": "Nintendo"},
+        "genres": [{"noms": [{"langue": "en", "text": "Platform"}]}],
+        "joueurs": {"text": "1-2"},
+        "note": {"text": "18"},
+        "medias": [
+            {"type": "ss", "region": "wor", "url": "http://media/ss.png", "format": "png"},
+            {"type": "box-2D", "region": "jp", "url": "http://media/box-jp.png", "format": "png"},
+            {"type": "box-2D", "region": "us", "url": "http://media/box-us.jpg", "format": "jpg"},
+            {"type": "video-normalized", "url": "http://media/video.mp4", "format": "mp4"}
+        ]
+    }}}"#;
+
+    fn query(system: &str) -> ScrapeQuery {
+        ScrapeQuery {
+            system: system.to_string(),
+            file_name: "Super Mario World (USA).sfc".to_string(),
+            name: "Super Mario World".to_string(),
+            hashes: Some(RomHashes { crc32: "b19ed489".to_string(), md5: "cdd3c8c37322978ca8669b34bc89c804".to_string(), sha1: "6b47bb75d16514b6a476aa0c73a683a2a4c18765".to_string(), size: 524288 }),
+        }
+    }
+
+    #[test]
+    fn test_scrape() {
+        let (url, server) = mock_server(vec![("/api2/jeuInfos.php", 200, ANSWER.as_bytes().to_vec())], 1);
+        let config = ScreenScraperConfig { api_url: format!("{}/api2", url), dev_id: "dev".to_string(), dev_password: "secret".to_string(), ..ScreenScraperConfig::default() };
+        let scraper = ScreenScraper::new(config, UreqClient::default());
+
+        let result = scraper.scrape(&query("snes")).unwrap().unwrap();
+        let fields: Vec<(&str, &str)> = result.fields.iter().map(|(field, value)| (field.as_str(), value.as_str())).collect();
+        assert_eq!(fields, vec![
+            ("name", "Super Mario World"),
+            ("desc", "Mario is back."),
+            ("releasedate", "19910801T000000"),
+            ("developer", "Nintendo EAD"),
+            ("publisher", "Nintendo"),
+            ("genre", "Platform"),
+            ("players", "1-2"),
+            ("rating", "0.90"),
+        ]);
+        let media: Vec<(MediaKind, &str)> = result.media.iter().map(|media| (media.kind, media.url.as_str())).collect();
+        assert_eq!(media, vec![
+            (MediaKind::Image, "http://media/ss.png"),
+            (MediaKind::Thumbnail, "http://media/box-us.jpg"),
+            (MediaKind::Video, "http://media/video.mp4"),
+        ]);
+
+        let request = &server.join().unwrap()[0];
+        assert!(request.contains("systemeid=4"));
+        assert!(request.contains("crc=B19ED489"));
+        assert!(request.contains("romnom=Super+Mario+World+%28USA%29.sfc") || request.contains("romnom=Super%20Mario%20World%20%28USA%29.sfc"));
+    }
+
+    #[test]
+    fn test_scrape_not_found_and_errors() {
+        let (url, server) = mock_server(vec![], 1);
+        let config = ScreenScraperConfig { api_url: url, ..ScreenScraperConfig::default() };
+        let scraper = ScreenScraper::new(config, UreqClient::default());
+        assert_eq!(scraper.scrape(&query("snes")).unwrap(), None);
+        server.join().unwrap();
+
+        assert!(matches!(scraper.scrape(&query("unknown")), Err(Error::UnknownSystem(_))));
+
+        let (url, server) = mock_server(vec![("/", 430, b"Quota exceeded".to_vec())], 1);
+        let scraper = ScreenScraper::new(ScreenScraperConfig { api_url: url, ..ScreenScraperConfig::default() }, UreqClient::default());
+        assert!(matches!(scraper.scrape(&query("snes")), Err(Error::Status(430, _))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_gamelist_date() {
+        assert_eq!(gamelist_date("1991-08-13"), Some("19910813T000000".to_string()));
+        assert_eq!(gamelist_date("1991"), Some("19910101T000000".to_string()));
+        assert_eq!(gamelist_date("unknown"), None);
+        assert_eq!(gamelist_date("1991-ab"), None);
+        assert_eq!(gamelist_date("1991-13-01"), None);
+    }
+}
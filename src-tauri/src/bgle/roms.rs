@@ -0,0 +1,197 @@
+//! This module finds the ROM files stored within an emulator directory.
+//!
+//! ROMs may be kept in subdirectories of the emulator directory, for example
+//! `roms/psx/Final Fantasy VII/` or `roms/snes/Hacks/`, so the directory is scanned recursively.
+//! Batocera's media directories are skipped, as are hidden directories.
+
+pub mod archives;
+pub mod checksums;
+pub mod disc_sets;
+pub mod names;
+pub mod tags;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The number of subdirectory levels below the emulator directory searched by default.
+pub const MAX_SCAN_DEPTH: usize = 4;
+
+/// Directories which hold scraped media or manuals rather than ROMs.
+pub const MEDIA_DIRECTORIES: [&str; 9] = [
+    "images",
+    "videos",
+    "manuals",
+    "media",
+    "music",
+    "downloaded_images",
+    "downloaded_videos",
+    "downloaded_manuals",
+    "downloaded_media",
+];
+
+/// Options controlling how an emulator directory is scanned for ROMs.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// The number of subdirectory levels to descend into, 0 only scans the emulator directory itself.
+    pub max_depth: usize,
+    /// Names of subdirectories which are never scanned.
+    pub excluded_directories: Vec<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            max_depth: MAX_SCAN_DEPTH,
+            excluded_directories: MEDIA_DIRECTORIES.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+/// Returns the paths of the ROMs found within an emulator directory and its subdirectories.
+///
+/// A ROM is any file whose extension is one of the accepted extensions. Directories whose name has
+/// an accepted extension (such as `Game.pc`) are also treated as ROMs and are not descended into.
+///
+/// ### Arguments
+/// * `emulator_dir` - The emulator directory to scan.
+/// * `extensions` - The accepted ROM extensions, without a leading dot.
+/// * `options` - The depth and exclusions to apply while scanning.
+/// ### Returns
+/// * `Result<Vec<PathBuf>, io::Error>` - The full paths of the ROMs in sorted order, or an error if the emulator directory cannot be read.
+pub fn rom_paths(emulator_dir: &Path, extensions: &[String], options: &ScanOptions) -> Result<Vec<PathBuf>, io::Error> {
+    let mut paths = Vec::<PathBuf>::new();
+    scan_directory(emulator_dir, 0, extensions, options, &mut paths)?;
+    paths.sort();
+    Ok(paths)
+}
+
+/// Converts the path of a ROM into the relative form used by gamelist.xml, eg `./Hacks/foo.smc`.
+///
+/// Returns None if the ROM is not within the emulator directory.
+pub fn gamelist_path(emulator_dir: &Path, rom_path: &Path) -> Option<String> {
+    let relative = rom_path.strip_prefix(emulator_dir).ok()?;
+    let mut path = String::from(".");
+    for component in relative.components() {
+        path.push('/');
+        path.push_str(component.as_os_str().to_str()?);
+    }
+    Some(path)
+}
+
+/// Converts a path written in gamelist.xml, eg `./Hacks/foo.smc`, into the full path of the ROM.
+pub fn rom_file_path(emulator_dir: &Path, gamelist_path: &str) -> PathBuf {
+    let mut path = emulator_dir.to_path_buf();
+    for component in gamelist_path.split('/').filter(|component| !component.is_empty() && *component != ".") {
+        path.push(component);
+    }
+    path
+}
+
+fn scan_directory(directory: &Path, depth: usize, extensions: &[String], options: &ScanOptions, paths: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+
+        if has_rom_extension(&path, extensions) {
+            paths.push(path);
+        }
+        else if path.is_dir() && depth < options.max_depth && !is_excluded(&path, options) {
+            // subdirectories which cannot be read are skipped rather than failing the whole scan
+            let _ = scan_directory(&path, depth + 1, extensions, options, paths);
+        }
+    }
+    Ok(())
+}
+
+fn has_rom_extension(path: &Path, extensions: &[String]) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extensions.iter().any(|valid| valid == extension),
+        None => false,
+    }
+}
+
+fn is_excluded(directory: &Path, options: &ScanOptions) -> bool {
+    match directory.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.starts_with('.') || options.excluded_directories.iter().any(|excluded| excluded == name),
+        None => true,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use crate::bgle::utils::TempDir;
+
+    // Creates an emulator directory in the system's temp directory holding the given files.
+    fn emulator_directory(name: &str, files: &[&str]) -> TempDir {
+        let directory = TempDir::new(&format!("roms-{}", name));
+        for file in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"rom").unwrap();
+        }
+        directory
+    }
+
+    fn extensions() -> Vec<String> {
+        vec!["smc".to_string(), "pc".to_string()]
+    }
+
+    fn gamelist_paths(directory: &Path, options: &ScanOptions) -> Vec<String> {
+        rom_paths(directory, &extensions(), options).unwrap()
+            .iter()
+            .map(|path| gamelist_path(directory, path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rom_paths_recursive() {
+        let directory = emulator_directory("recursive", &[
+            "a.smc",
+            "Hacks/b.smc",
+            "Hacks/More/c.smc",
+            "Hacks/notes.txt",
+            "images/a-image.smc",
+            "videos/a-video.smc",
+            ".hidden/d.smc",
+        ]);
+        let paths = gamelist_paths(&directory, &ScanOptions::default());
+        assert_eq!(paths, vec!["./Hacks/More/c.smc", "./Hacks/b.smc", "./a.smc"]);
+    }
+
+    #[test]
+    fn test_rom_paths_depth_limit() {
+        let directory = emulator_directory("depth", &["a.smc", "one/b.smc", "one/two/c.smc"]);
+        let options = ScanOptions { max_depth: 1, ..ScanOptions::default() };
+        assert_eq!(gamelist_paths(&directory, &options), vec!["./a.smc", "./one/b.smc"]);
+        let options = ScanOptions { max_depth: 0, ..ScanOptions::default() };
+        assert_eq!(gamelist_paths(&directory, &options), vec!["./a.smc"]);
+    }
+
+    #[test]
+    fn test_rom_paths_directory_roms() {
+        let directory = emulator_directory("directory-roms", &["Doom.pc/doom.exe", "Doom.pc/inner.smc"]);
+        assert_eq!(gamelist_paths(&directory, &ScanOptions::default()), vec!["./Doom.pc"]);
+    }
+
+    #[test]
+    fn test_rom_paths_missing_directory() {
+        let directory = std::env::temp_dir().join("bgle-roms-does-not-exist");
+        assert!(rom_paths(&directory, &extensions(), &ScanOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_gamelist_path() {
+        let directory = PathBuf::from("/roms/snes");
+        assert_eq!(gamelist_path(&directory, &directory.join("Hacks").join("foo.smc")), Some("./Hacks/foo.smc".to_string()));
+        assert_eq!(gamelist_path(&directory, Path::new("/roms/nes/foo.nes")), None);
+    }
+
+    #[test]
+    fn test_rom_file_path() {
+        let directory = PathBuf::from("/roms/snes");
+        assert_eq!(rom_file_path(&directory, "./Hacks/foo.smc"), directory.join("Hacks").join("foo.smc"));
+        assert_eq!(rom_file_path(&directory, "foo.smc"), directory.join("foo.smc"));
+    }
+}
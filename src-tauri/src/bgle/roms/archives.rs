@@ -0,0 +1,273 @@
+//! This module looks inside the `.zip` and `.7z` archives which hold ROMs.
+//!
+//! Cartridge based systems usually keep each ROM zipped. The archive is what gamelist.xml refers
+//! to, but the ROM inside it is what identifies the game, so the member files are listed and
+//! checked against the system's accepted extensions.
+
+use std::io::{self, Read};
+use std::fs::File;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+/// The extensions of the archives which can be looked inside.
+pub const ARCHIVE_EXTENSIONS: [&str; 2] = ["zip", "7z"];
+
+/// A file stored within an archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveMember {
+    /// The path of the file within the archive.
+    pub name: String,
+    /// The uncompressed size of the file in bytes.
+    pub size: u64,
+}
+
+/// Whether an archive holds a single ROM, as expected of a game's archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ArchiveStatus {
+    SingleRom,
+    NoRom,
+    SeveralRoms,
+    Unreadable,
+}
+
+/// The ROMs found within an archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveInfo {
+    pub status: ArchiveStatus,
+    /// The members of the archive with an accepted ROM extension.
+    pub roms: Vec<ArchiveMember>,
+}
+
+impl ArchiveInfo {
+
+    /// Returns the archive's ROM, None if it holds no ROM or several.
+    pub fn inner_rom(&self) -> Option<&ArchiveMember> {
+        match self.status {
+            ArchiveStatus::SingleRom => self.roms.first(),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true if the path has the extension of an archive which can be looked inside.
+pub fn is_archive(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => ARCHIVE_EXTENSIONS.iter().any(|archive| archive.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+/// Lists the files stored within a `.zip` or `.7z` archive, directories are not included.
+pub fn members(path: &Path) -> Result<Vec<ArchiveMember>, io::Error> {
+    if is_seven_zip(path) {
+        let archive = sevenz_rust::Archive::open(path).map_err(seven_zip_error)?;
+        return Ok(archive.files.iter()
+            .filter(|entry| !entry.is_directory)
+            .map(|entry| ArchiveMember { name: entry.name.clone(), size: entry.size })
+            .collect());
+    }
+
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(zip_error)?;
+    let mut members = Vec::<ArchiveMember>::new();
+    for index in 0..archive.len() {
+        let file = archive.by_index(index).map_err(zip_error)?;
+        if file.is_file() {
+            members.push(ArchiveMember { name: file.name().to_string(), size: file.size() });
+        }
+    }
+    Ok(members)
+}
+
+/// Lists the ROMs within an archive and flags archives which hold no ROM or several.
+///
+/// ### Arguments
+/// * `path` - The full path of the archive.
+/// * `extensions` - The system's accepted ROM extensions, archive extensions are ignored.
+/// ### Returns
+/// * `Option<ArchiveInfo>` - None if the archive is itself the ROM: the system accepts its extension and it holds no file with another accepted extension, as with arcade ROM sets.
+pub fn inspect(path: &Path, extensions: &[String]) -> Option<ArchiveInfo> {
+    let members = match members(path) {
+        Ok(members) => members,
+        Err(_) => return Some(ArchiveInfo { status: ArchiveStatus::Unreadable, roms: Vec::new() }),
+    };
+
+    let roms: Vec<ArchiveMember> = members.into_iter()
+        .filter(|member| is_rom(&member.name, extensions))
+        .collect();
+    let status = match roms.len() {
+        0 if is_accepted(path, extensions) => return None,
+        0 => ArchiveStatus::NoRom,
+        1 => ArchiveStatus::SingleRom,
+        _ => ArchiveStatus::SeveralRoms,
+    };
+    Some(ArchiveInfo { status, roms })
+}
+
+/// Opens a file stored within an archive and passes its decompressed contents to `read`.
+pub fn read_member<T>(path: &Path, name: &str, read: impl FnOnce(&mut dyn Read) -> Result<T, io::Error>) -> Result<T, io::Error> {
+    if is_seven_zip(path) {
+        let mut reader = sevenz_rust::SevenZReader::open(path, sevenz_rust::Password::empty()).map_err(seven_zip_error)?;
+        let mut read = Some(read);
+        let mut result = None;
+        reader.for_each_entries(|entry, contents| {
+            // the members of a solid archive share one stream, a skipped member must still be read
+            // through or the next member would be handed its bytes
+            if entry.name != name {
+                io::copy(contents, &mut io::sink())?;
+                return Ok(true);
+            }
+            if let Some(read) = read.take() {
+                result = Some(read(contents));
+            }
+            Ok(false)
+        }).map_err(seven_zip_error)?;
+        return result.unwrap_or_else(|| Err(not_found(name)));
+    }
+
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(zip_error)?;
+    let mut file = archive.by_name(name).map_err(|_| not_found(name))?;
+    read(&mut file)
+}
+
+fn is_seven_zip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.eq_ignore_ascii_case("7z"))
+        .unwrap_or(false)
+}
+
+// A member is a ROM if it has one of the system's extensions, other than an archive extension.
+fn is_rom(name: &str, extensions: &[String]) -> bool {
+    !is_archive(Path::new(name)) && is_accepted(Path::new(name), extensions)
+}
+
+fn is_accepted(path: &Path, extensions: &[String]) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extensions.iter().any(|valid| valid.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", name))
+}
+
+fn zip_error(error: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+fn seven_zip_error(error: sevenz_rust::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use crate::bgle::utils::TempDir;
+
+    // Writes a zip archive holding the given files to a temporary directory, removed with the returned guard.
+    fn zip_archive(name: &str, files: &[(&str, &[u8])]) -> (TempDir, PathBuf) {
+        let directory = TempDir::new(&format!("archives-{}", name));
+
+        let path = directory.join(format!("{}.zip", name));
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (file, contents) in files {
+            writer.start_file(*file, Default::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap();
+        (directory, path)
+    }
+
+    // Writes a solid 7z archive, where the files are compressed together in a single stream.
+    fn seven_zip_archive(name: &str, files: &[(&str, &[u8])]) -> (TempDir, PathBuf) {
+        let directory = TempDir::new(&format!("archives-{}", name));
+
+        let path = directory.join(format!("{}.7z", name));
+        let mut writer = sevenz_rust::SevenZWriter::create(&path).unwrap();
+        let entries = files.iter().map(|(file, contents)| {
+            let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+            entry.name = file.to_string();
+            entry.has_stream = true;
+            entry.size = contents.len() as u64;
+            entry
+        }).collect();
+        let readers = files.iter().map(|(_, contents)| sevenz_rust::SourceReader::new(*contents)).collect();
+        writer.push_archive_entries(entries, sevenz_rust::SeqReader::new(readers)).unwrap();
+        writer.finish().unwrap();
+        (directory, path)
+    }
+
+    fn extensions() -> Vec<String> {
+        ["smc", "sfc", "zip"].iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_archive() {
+        assert!(is_archive(Path::new("Game.zip")));
+        assert!(is_archive(Path::new("Game.7Z")));
+        assert!(!is_archive(Path::new("Game.smc")));
+    }
+
+    #[test]
+    fn test_inspect_single_rom() {
+        let (_directory, path) = zip_archive("single", &[("Game.SFC", b"rom"), ("readme.txt", b"text")]);
+        let info = inspect(&path, &extensions()).unwrap();
+        assert_eq!(info.status, ArchiveStatus::SingleRom);
+        assert_eq!(info.inner_rom(), Some(&ArchiveMember { name: "Game.SFC".to_string(), size: 3 }));
+        assert_eq!(members(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_inspect_flags() {
+        let (_directory, path) = zip_archive("several", &[("a.smc", b"rom"), ("b.smc", b"rom")]);
+        assert_eq!(inspect(&path, &extensions()).unwrap().status, ArchiveStatus::SeveralRoms);
+        assert_eq!(inspect(&path, &extensions()).unwrap().inner_rom(), None);
+
+        // the system does not accept zipped ROMs, so the archive should hold one
+        let without_zip = ["smc".to_string(), "sfc".to_string()];
+        let (_directory, path) = zip_archive("none", &[("readme.txt", b"text"), ("nested.zip", b"zip")]);
+        assert_eq!(inspect(&path, &without_zip).unwrap().status, ArchiveStatus::NoRom);
+
+        let path = std::env::temp_dir().join("bgle-archives-missing.zip");
+        assert_eq!(inspect(&path, &extensions()).unwrap().status, ArchiveStatus::Unreadable);
+    }
+
+    #[test]
+    fn test_inspect_archive_rom() {
+        // an arcade ROM set is a zip of chip dumps, the archive itself is the ROM
+        let arcade = ["zip".to_string(), "7z".to_string()];
+        let (_directory, path) = zip_archive("arcade", &[("sf2-1.bin", b"rom"), ("sf2-2.bin", b"rom")]);
+        assert_eq!(inspect(&path, &arcade), None);
+        assert_eq!(inspect(&path, &extensions()), None);
+    }
+
+    #[test]
+    fn test_read_member() {
+        let (_directory, path) = zip_archive("read", &[("a.smc", b"first"), ("b.smc", b"second")]);
+        let mut contents = String::new();
+        read_member(&path, "b.smc", |reader| reader.read_to_string(&mut contents)).unwrap();
+        assert_eq!(contents, "second");
+        assert!(read_member(&path, "c.smc", |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_read_seven_zip_member() {
+        let (_directory, path) = seven_zip_archive("solid", &[("readme.txt", b"read me first"), ("Game.sfc", b"rom"), ("notes.txt", b"notes")]);
+        let info = inspect(&path, &extensions()).unwrap();
+        assert_eq!(info.inner_rom(), Some(&ArchiveMember { name: "Game.sfc".to_string(), size: 3 }));
+
+        // the ROM is not the first member, the readme's bytes must not be read as the ROM's
+        let read = |name: &str| read_member(&path, name, |contents| {
+            let mut bytes = Vec::new();
+            contents.read_to_end(&mut bytes)?;
+            Ok(bytes)
+        });
+        assert_eq!(read("Game.sfc").unwrap(), b"rom");
+        assert_eq!(read("notes.txt").unwrap(), b"notes");
+        assert_eq!(read("missing.sfc").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,242 @@
+//! This module computes the checksums of ROM files.
+//!
+//! ROMs are streamed rather than read into memory, as disc images can be several gigabytes. When a
+//! ROM is stored within a `.zip` or `.7z` archive the ROM inside the archive can be hashed, rather
+//! than the archive itself, so the checksums match those found in DAT files and scraper databases.
+//!
+//! Computed checksums are cached in a file for each emulator directory, keyed by the ROM's size and
+//! modification time, so only new or changed ROMs are hashed again. The cache files are kept in the
+//! app's data directory rather than among the ROMs, which may be on a read-only or shared drive.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+use md5::{Digest, Md5};
+use serde::{Serialize, Deserialize};
+use sha1::Sha1;
+
+use super::archives;
+
+// The directory holding the cache files, set once the app's data directory is known.
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// The number of bytes read from a ROM at a time.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// The checksums of a ROM, as lowercase hexadecimal strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RomHashes {
+    pub crc32: String,
+    pub md5: String,
+    pub sha1: String,
+    /// The size of the data hashed, for a zipped ROM this is the size of the ROM within the archive.
+    pub size: u64,
+}
+
+/// Computes the checksums of all data read from a reader.
+pub fn hash_reader<R: Read + ?Sized>(reader: &mut R) -> Result<RomHashes, io::Error> {
+    let mut crc32 = crc32fast::Hasher::new();
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    let mut size: u64 = 0;
+
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        crc32.update(&buffer[..read]);
+        md5.update(&buffer[..read]);
+        sha1.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok(RomHashes {
+        crc32: format!("{:08x}", crc32.finalize()),
+        md5: to_hex(&md5.finalize()),
+        sha1: to_hex(&sha1.finalize()),
+        size,
+    })
+}
+
+/// Computes the checksums of a ROM file.
+///
+/// ### Arguments
+/// * `path` - The full path of the ROM file.
+/// * `inner_file` - The name of the ROM within the archive at `path`, None to hash the file itself.
+pub fn hash_rom(path: &Path, inner_file: Option<&str>) -> Result<RomHashes, io::Error> {
+    match inner_file {
+        Some(name) => archives::read_member(path, name, |reader| hash_reader(reader)),
+        None => hash_reader(&mut File::open(path)?),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Sets the directory the cache files are written to, eg `checksums` within the app's data
+/// directory. Until it is set the caches are written to the system's temporary directory.
+pub fn set_cache_dir(directory: PathBuf) {
+    let _ = CACHE_DIR.set(directory);
+}
+
+/// Returns the path of the cache file of an emulator directory, named after the directory and the
+/// hash of its full path so emulators of the same name in different places are kept apart.
+pub fn cache_path(emulator_dir: &Path) -> PathBuf {
+    let cache_dir = CACHE_DIR.get().cloned().unwrap_or_else(|| std::env::temp_dir().join("bgle-checksums"));
+    let name = emulator_dir.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let key = to_hex(&Sha1::digest(emulator_dir.to_string_lossy().as_bytes()));
+    cache_dir.join(format!("{}-{}.json", name, &key[..16]))
+}
+
+// A cached result, only valid while the ROM's size and modification time are unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHashes {
+    file_size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    #[serde(default)]
+    inner_file: Option<String>,
+    hashes: RomHashes,
+}
+
+/// A cache of the checksums of the ROMs within an emulator directory.
+#[derive(Debug)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: HashMap<String, CachedHashes>,
+    changed: bool,
+}
+
+impl HashCache {
+
+    /// Loads the cache of an emulator directory, a missing or unreadable cache is treated as empty.
+    pub fn load(emulator_dir: &Path) -> HashCache {
+        let path = cache_path(emulator_dir);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        HashCache { path, entries, changed: false }
+    }
+
+    /// Returns the checksums of a ROM, hashing it only if it is not cached or has changed since.
+    ///
+    /// ### Arguments
+    /// * `rom_path` - The full path of the ROM file.
+    /// * `key` - The ROM's path as written in gamelist.xml, used as the cache key.
+    /// * `inner_file` - The name of the ROM within the archive at `rom_path`, None to hash the file itself.
+    pub fn hashes(&mut self, rom_path: &Path, key: &str, inner_file: Option<&str>) -> Result<RomHashes, io::Error> {
+        let metadata = fs::metadata(rom_path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        if let Some(cached) = self.entries.get(key) {
+            if cached.file_size == metadata.len()
+            && cached.modified_secs == modified.as_secs()
+            && cached.modified_nanos == modified.subsec_nanos()
+            && cached.inner_file.as_deref() == inner_file {
+                return Ok(cached.hashes.clone());
+            }
+        }
+
+        let hashes = hash_rom(rom_path, inner_file)?;
+        self.entries.insert(key.to_string(), CachedHashes {
+            file_size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            inner_file: inner_file.map(|name| name.to_string()),
+            hashes: hashes.clone(),
+        });
+        self.changed = true;
+        Ok(hashes)
+    }
+
+    /// Writes the cache back to its file if any ROMs were hashed.
+    pub fn save(&mut self) -> Result<(), io::Error> {
+        if self.changed {
+            if let Some(cache_dir) = self.path.parent() {
+                fs::create_dir_all(cache_dir)?;
+            }
+            let contents = serde_json::to_string(&self.entries)?;
+            fs::write(&self.path, contents)?;
+            self.changed = false;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use crate::bgle::utils::TempDir;
+
+    const CONTENTS: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    fn assert_known_hashes(hashes: &RomHashes) {
+        assert_eq!(hashes.crc32, "414fa339");
+        assert_eq!(hashes.md5, "9e107d9d372bb6826bd81d3542a419d6");
+        assert_eq!(hashes.sha1, "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12");
+        assert_eq!(hashes.size, CONTENTS.len() as u64);
+    }
+
+    #[test]
+    fn test_hash_reader() {
+        assert_known_hashes(&hash_reader(&mut &CONTENTS[..]).unwrap());
+    }
+
+    #[test]
+    fn test_hash_rom_in_zip() {
+        let directory = TempDir::new("checksums-zip");
+        let path = directory.join("game.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        writer.add_directory("docs/", Default::default()).unwrap();
+        writer.start_file("game.smc", Default::default()).unwrap();
+        writer.write_all(CONTENTS).unwrap();
+        writer.finish().unwrap();
+
+        assert_known_hashes(&hash_rom(&path, Some("game.smc")).unwrap());
+        assert_ne!(hash_rom(&path, None).unwrap().md5, "9e107d9d372bb6826bd81d3542a419d6");
+    }
+
+    #[test]
+    fn test_cache_path() {
+        // emulator directories of the same name have their own caches
+        let first = cache_path(Path::new("/roms/snes"));
+        assert_ne!(first, cache_path(Path::new("/other/roms/snes")));
+        assert_eq!(first, cache_path(Path::new("/roms/snes")));
+        assert!(first.file_name().unwrap().to_string_lossy().starts_with("snes-"));
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        let directory = TempDir::new("checksums-cache");
+        let path = directory.join("game.smc");
+        fs::write(&path, CONTENTS).unwrap();
+
+        let mut cache = HashCache::load(&directory);
+        assert_known_hashes(&cache.hashes(&path, "./game.smc", None).unwrap());
+        cache.save().unwrap();
+        // the cache is not written among the ROMs
+        assert!(cache_path(&directory).exists());
+        assert_eq!(fs::read_dir(&*directory).unwrap().count(), 1);
+
+        // a cached entry is returned without hashing the file again
+        let mut cache = HashCache::load(&directory);
+        cache.entries.get_mut("./game.smc").unwrap().hashes.md5 = String::from("cached");
+        assert_eq!(cache.hashes(&path, "./game.smc", None).unwrap().md5, "cached");
+
+        // a change in size invalidates the entry
+        fs::write(&path, b"changed").unwrap();
+        assert_ne!(cache.hashes(&path, "./game.smc", None).unwrap().md5, "cached");
+        fs::remove_file(cache_path(&directory)).unwrap();
+    }
+}
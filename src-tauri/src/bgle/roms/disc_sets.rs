@@ -0,0 +1,331 @@
+//! This module groups the files of multi-file and multi-disc games.
+//!
+//! Disc based games are often stored as a sheet file referencing other files: a `.cue` or `.gdi`
+//! lists the tracks of a disc and an `.m3u` playlist lists the discs of a game. When both the sheet
+//! and the files it references are accepted ROM extensions, only the sheet is treated as the game
+//! and the referenced files become its members.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+
+use super::{rom_paths, ScanOptions};
+
+/// A game's ROM along with the files it references, such as the tracks of a `.cue` or the discs
+/// of an `.m3u`. Games made of a single file have no members.
+#[derive(Debug, PartialEq)]
+pub struct RomSet {
+    pub path: PathBuf,
+    pub members: Vec<PathBuf>,
+}
+
+// Sheet files larger than this are not sheets, so are not read.
+const MAX_SHEET_SIZE: u64 = 1024 * 1024;
+
+// Playlists may reference sheets, which reference tracks, nothing nests deeper.
+const MAX_SHEET_DEPTH: usize = 2;
+
+/// Groups ROM paths into sets, so that files referenced by an `.m3u`, `.cue` or `.gdi` file are
+/// listed as members of that file rather than as games of their own.
+pub fn group(paths: Vec<PathBuf>) -> Vec<RomSet> {
+    let sets: Vec<RomSet> = paths.iter()
+        .map(|path| RomSet { path: path.clone(), members: referenced_files(path, 0) })
+        .collect();
+
+    let members: HashSet<&PathBuf> = sets.iter().flat_map(|set| set.members.iter()).collect();
+    let primaries: Vec<bool> = sets.iter()
+        .map(|set| !members.contains(&set.path))
+        .collect();
+
+    sets.into_iter()
+        .zip(primaries)
+        .filter(|(_, primary)| *primary)
+        .map(|(set, _)| set)
+        .collect()
+}
+
+/// Returns the games found in an emulator directory, with multi-file games grouped into sets.
+pub fn rom_sets(emulator_dir: &Path, extensions: &[String], options: &ScanOptions) -> Result<Vec<RomSet>, io::Error> {
+    Ok(group(rom_paths(emulator_dir, extensions, options)?))
+}
+
+/// Writes an `.m3u` playlist for each set of discs named with a `(Disc N)` tag, such as
+/// `Final Fantasy VII (USA) (Disc 1).cue`, which is not already part of a playlist.
+///
+/// The playlist is named after the discs with the tag removed and placed beside them, existing
+/// files are never overwritten.
+///
+/// ### Arguments
+/// * `emulator_dir` - The emulator directory to scan.
+/// * `extensions` - The accepted ROM extensions, without a leading dot.
+/// * `options` - The depth and exclusions to apply while scanning.
+/// ### Returns
+/// * `Result<Vec<PathBuf>, io::Error>` - The paths of the playlists written.
+pub fn write_m3u_playlists(emulator_dir: &Path, extensions: &[String], options: &ScanOptions) -> Result<Vec<PathBuf>, io::Error> {
+
+    // discs sharing a directory, title and extension belong to the same game
+    let mut disc_sets = BTreeMap::<(PathBuf, String, String), Vec<(u32, PathBuf)>>::new();
+    for set in rom_sets(emulator_dir, extensions, options)? {
+        let file_name = match set.path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name.to_string(),
+            None => continue,
+        };
+        if let Some((title, disc)) = disc_title(&file_name) {
+            let directory = set.path.parent().unwrap_or(emulator_dir).to_path_buf();
+            let extension = extension(&set.path);
+            disc_sets.entry((directory, title, extension)).or_default().push((disc, set.path));
+        }
+    }
+
+    let mut playlists = Vec::<PathBuf>::new();
+    for ((directory, title, _), mut discs) in disc_sets {
+        if discs.len() < 2 {
+            continue;
+        }
+        let playlist = directory.join(format!("{}.m3u", title));
+        if playlist.exists() {
+            continue;
+        }
+        discs.sort();
+        let lines: Vec<String> = discs.iter()
+            .filter_map(|(_, path)| path.file_name().and_then(|name| name.to_str()).map(|name| name.to_string()))
+            .collect();
+        fs::write(&playlist, lines.join("\n") + "\n")?;
+        playlists.push(playlist);
+    }
+    Ok(playlists)
+}
+
+// Splits a file name such as "Game (USA) (Disc 2 of 3).cue" into its title "Game (USA)" and disc number.
+fn disc_title(file_name: &str) -> Option<(String, u32)> {
+    let regex = Regex::new(r"(?i)\s*\((?:disc|disk|cd)\s*(\d+)(?:\s*of\s*\d+)?\)").unwrap();
+    let stem = match file_name.rfind('.') {
+        Some(index) => &file_name[..index],
+        None => file_name,
+    };
+    let captures = regex.captures(stem)?;
+    let disc = captures[1].parse::<u32>().ok()?;
+    let title = regex.replace(stem, "").trim().to_string();
+    Some((title, disc))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .unwrap_or_default()
+}
+
+// Returns the files a sheet references, including the files referenced by any sheets it references.
+fn referenced_files(path: &Path, depth: usize) -> Vec<PathBuf> {
+    let kind = extension(path);
+    if depth > MAX_SHEET_DEPTH || !matches!(kind.as_str(), "m3u" | "cue" | "gdi") {
+        return Vec::new();
+    }
+    let contents = match read_sheet(path) {
+        Some(contents) => contents,
+        None => return Vec::new(),
+    };
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let names = match kind.as_str() {
+        "m3u" => playlist_entries(&contents),
+        "cue" => cue_files(&contents),
+        _ => gdi_tracks(&contents),
+    };
+
+    let mut files = Vec::<PathBuf>::new();
+    for name in names {
+        let file = entry_path(directory, &name);
+        if file == path {
+            continue;
+        }
+        let nested = referenced_files(&file, depth + 1);
+        files.push(file);
+        files.extend(nested);
+    }
+    files
+}
+
+// Returns the file an entry of a sheet refers to. Sheets written on Windows may separate folders
+// with backslashes, start entries with `.\` or name files in a different case than they have on disk.
+fn entry_path(directory: &Path, entry: &str) -> PathBuf {
+    let mut path = directory.to_path_buf();
+    for part in entry.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                path.pop();
+            }
+            _ => {
+                let name = matching_name(&path, part);
+                path.push(name);
+            }
+        }
+    }
+    path
+}
+
+// Returns the name of the file within a directory which matches a name whatever its case, or the
+// name itself if it matches exactly or no file does.
+fn matching_name(directory: &Path, name: &str) -> String {
+    if directory.join(name).exists() {
+        return name.to_string();
+    }
+    let lowercase = name.to_lowercase();
+    fs::read_dir(directory).ok()
+        .and_then(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .find(|entry| entry.to_lowercase() == lowercase))
+        .unwrap_or_else(|| name.to_string())
+}
+
+fn read_sheet(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_SHEET_SIZE {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Each line of an .m3u is a file, apart from blank lines and comments.
+fn playlist_entries(contents: &str) -> Vec<String> {
+    contents.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// A .cue references its tracks with lines like: FILE "Game (Track 1).bin" BINARY
+fn cue_files(contents: &str) -> Vec<String> {
+    let regex = Regex::new(r#"(?im)^[ \t]*FILE[ \t]+(?:"([^"]+)"|(\S+))"#).unwrap();
+    regex.captures_iter(contents)
+        .filter_map(|captures| captures.get(1).or(captures.get(2)))
+        .map(|name| name.as_str().to_string())
+        .collect()
+}
+
+// A .gdi lists a track per line after the track count: 1 0 4 2352 track01.bin 0
+fn gdi_tracks(contents: &str) -> Vec<String> {
+    let regex = Regex::new(r#"(?m)^[ \t]*\d+[ \t]+\d+[ \t]+\d+[ \t]+\d+[ \t]+(?:"([^"]+)"|(\S+))"#).unwrap();
+    regex.captures_iter(contents)
+        .filter_map(|captures| captures.get(1).or(captures.get(2)))
+        .map(|name| name.as_str().to_string())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+
+    // Creates an emulator directory in the system's temp directory holding the given files.
+    fn emulator_directory(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let directory = TempDir::new(&format!("disc-sets-{}", name));
+        for (file, contents) in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        directory
+    }
+
+    fn extensions() -> Vec<String> {
+        ["m3u", "cue", "bin", "gdi", "chd"].iter().map(|e| e.to_string()).collect()
+    }
+
+    fn set_names(directory: &Path) -> Vec<String> {
+        rom_sets(directory, &extensions(), &ScanOptions::default()).unwrap()
+            .iter()
+            .map(|set| set.path.strip_prefix(directory).unwrap().to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_cue_files() {
+        let cue = "FILE \"Game (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\nfile track2.bin BINARY\n";
+        assert_eq!(cue_files(cue), vec!["Game (Track 1).bin", "track2.bin"]);
+    }
+
+    #[test]
+    fn test_gdi_tracks() {
+        let gdi = "3\n1 0 4 2352 track01.bin 0\n2 756 0 2352 \"track 02.raw\" 0\n3 45000 4 2352 track03.bin 0\n";
+        assert_eq!(gdi_tracks(gdi), vec!["track01.bin", "track 02.raw", "track03.bin"]);
+    }
+
+    #[test]
+    fn test_playlist_entries() {
+        assert_eq!(playlist_entries("#EXTM3U\nDisc 1.cue\r\n\nDisc 2.cue\n"), vec!["Disc 1.cue", "Disc 2.cue"]);
+    }
+
+    #[test]
+    fn test_disc_title() {
+        assert_eq!(disc_title("Final Fantasy VII (USA) (Disc 2).cue"), Some(("Final Fantasy VII (USA)".to_string(), 2)));
+        assert_eq!(disc_title("Riven (Disc 3 of 5).chd"), Some(("Riven".to_string(), 3)));
+        assert_eq!(disc_title("Super Mario World (USA).sfc"), None);
+    }
+
+    #[test]
+    fn test_group_multi_disc_game() {
+        let directory = emulator_directory("group", &[
+            ("FF7/FF7.m3u", "FF7 (Disc 1).cue\nFF7 (Disc 2).cue\n"),
+            ("FF7/FF7 (Disc 1).cue", "FILE \"FF7 (Disc 1).bin\" BINARY\n"),
+            ("FF7/FF7 (Disc 1).bin", ""),
+            ("FF7/FF7 (Disc 2).cue", "FILE \"FF7 (Disc 2).bin\" BINARY\n"),
+            ("FF7/FF7 (Disc 2).bin", ""),
+            ("Crash.cue", "FILE \"Crash (Track 1).bin\" BINARY\nFILE \"Crash (Track 2).bin\" AUDIO\n"),
+            ("Crash (Track 1).bin", ""),
+            ("Crash (Track 2).bin", ""),
+            ("Sonic.gdi", "2\n1 0 4 2352 track01.bin 0\n2 756 0 2352 track02.bin 0\n"),
+            ("track01.bin", ""),
+            ("track02.bin", ""),
+            ("Loose.bin", ""),
+        ]);
+        assert_eq!(set_names(&directory), vec!["Crash.cue", "FF7/FF7.m3u", "Loose.bin", "Sonic.gdi"]);
+
+        let sets = rom_sets(&directory, &extensions(), &ScanOptions::default()).unwrap();
+        assert_eq!(sets[1].members.len(), 4);
+    }
+
+    #[test]
+    fn test_group_windows_sheets() {
+        let directory = emulator_directory("windows", &[
+            ("Game.m3u", "./game (disc 1).CUE\r\n.\\Discs\\Game (Disc 2).cue\r\n"),
+            ("Game (Disc 1).cue", "FILE \".\\Tracks\\TRACK 1.BIN\" BINARY\n"),
+            ("Tracks/Track 1.bin", ""),
+            ("Discs/Game (Disc 2).cue", "FILE \"..\\Tracks\\Track 2.bin\" BINARY\n"),
+            ("Tracks/Track 2.bin", ""),
+        ]);
+        assert_eq!(set_names(&directory), vec!["Game.m3u"]);
+
+        let sets = rom_sets(&directory, &extensions(), &ScanOptions::default()).unwrap();
+        assert_eq!(sets[0].members, vec![
+            directory.join("Game (Disc 1).cue"),
+            directory.join("Tracks/Track 1.bin"),
+            directory.join("Discs/Game (Disc 2).cue"),
+            directory.join("Tracks/Track 2.bin"),
+        ]);
+    }
+
+    #[test]
+    fn test_write_m3u_playlists() {
+        let directory = emulator_directory("playlists", &[
+            ("Riven (Disc 2).chd", ""),
+            ("Riven (Disc 1).chd", ""),
+            ("Myst (Disc 1).chd", ""),
+        ]);
+        let playlists = write_m3u_playlists(&directory, &extensions(), &ScanOptions::default()).unwrap();
+        assert_eq!(playlists, vec![directory.join("Riven.m3u")]);
+        assert_eq!(fs::read_to_string(&playlists[0]).unwrap(), "Riven (Disc 1).chd\nRiven (Disc 2).chd\n");
+        assert_eq!(set_names(&directory), vec!["Myst (Disc 1).chd", "Riven.m3u"]);
+
+        // the discs are now part of a playlist, so no further playlist is written
+        assert!(write_m3u_playlists(&directory, &extensions(), &ScanOptions::default()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! This module compares game names and ROM file names loosely.
+//!
+//! The same game is often named differently in different places, `Super Mario World (USA).sfc` on
+//! disk, `Super Mario World` in gamelist.xml and `super-mario-world` elsewhere, so names are
+//! normalized before they are compared.
+
+use std::path::Path;
+use std::sync::OnceLock;
+use regex::Regex;
+
+/// The similarity at or above which two normalized names are treated as the same game.
+pub const NAME_MATCH_THRESHOLD: f64 = 0.85;
+
+// Matches bracketed tags, compiled once as names are normalized for every game of a list.
+static TAGS: OnceLock<Regex> = OnceLock::new();
+
+/// Normalizes a name for comparison. Bracketed tags such as `(USA)` and `[!]` are removed, `&` is
+/// read as "and", letters are lowercased and punctuation is dropped, so
+/// `Tom & Jerry (Europe) [!]` becomes `tom and jerry`.
+pub fn normalized_name(name: &str) -> String {
+    let tags = TAGS.get_or_init(|| Regex::new(r"\([^)]*\)|\[[^\]]*\]").unwrap());
+    let name = tags.replace_all(name, " ").replace('&', " and ");
+
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Returns the normalized name of a ROM from its path, ignoring its directory and extension.
+pub fn normalized_file_name(path: &str) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(path);
+    normalized_name(stem)
+}
+
+/// Returns how similar two normalized names are, from 0.0 for nothing in common to 1.0 for equal.
+pub fn similarity(first: &str, second: &str) -> f64 {
+    let first: Vec<char> = first.chars().collect();
+    let second: Vec<char> = second.chars().collect();
+    let longest = first.len().max(second.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(&first, &second) as f64 / longest as f64
+}
+
+/// Returns true if two names, normalized or not, are similar enough to be the same game.
+pub fn names_match(first: &str, second: &str) -> bool {
+    let first = normalized_name(first);
+    let second = normalized_name(second);
+    !first.is_empty() && similarity(&first, &second) >= NAME_MATCH_THRESHOLD
+}
+
+// The Levenshtein distance between two strings.
+fn edit_distance(first: &[char], second: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=second.len()).collect();
+    let mut current = vec![0; second.len() + 1];
+
+    for (i, a) in first.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b) in second.iter().enumerate() {
+            let substitution = previous[j] + if a == b { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[second.len()]
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalized_name() {
+        assert_eq!(normalized_name("Tom & Jerry (Europe) [!]"), "tom and jerry");
+        assert_eq!(normalized_name("Super Mario World (USA, Europe) (Rev 1)"), "super mario world");
+        assert_eq!(normalized_name("Castlevania: Symphony of the Night"), "castlevania symphony of the night");
+    }
+
+    #[test]
+    fn test_normalized_file_name() {
+        assert_eq!(normalized_file_name("./Hacks/Super Mario World (USA).sfc"), "super mario world");
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("kitten", "kitten"), 1.0);
+        assert_eq!(similarity("kitten", "sitting"), 1.0 - 3.0 / 7.0);
+        assert_eq!(similarity("", ""), 1.0);
+    }
+
+    #[test]
+    fn test_names_match() {
+        assert!(names_match("Super Mario World (USA)", "super-mario-world"));
+        assert!(names_match("Street Fighter II Turbo (Europe)", "Street Fighter 2 Turbo"));
+        assert!(!names_match("Super Mario World", "Super Mario Kart"));
+        assert!(!names_match("(USA)", "[!]"));
+    }
+}
@@ -0,0 +1,394 @@
+//! This module reads the tags in ROM file names and DAT game names, such as `(USA, Europe)`,
+//! `(En,Fr,De)`, `(Rev 1)` and `[!]`.
+//!
+//! Three naming conventions are understood:
+//! * No-Intro and Redump, eg `Super Mario World (USA, Europe) (Rev 1).sfc`
+//! * TOSEC, eg `Turrican II (1991)(Rainbow Arts)(DE)(en)[cr Fairlight].adf`
+//! * GoodTools, eg `Super Mario World (U) (V1.1) [!].smc`
+//!
+//! Regions and languages are converted to the codes Batocera uses in gamelist.xml, eg `us`, `eu`
+//! and `jp` for regions and `en`, `fr` and `ja` for languages.
+
+use std::path::Path;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+// No-Intro and Redump region names, with their Batocera region code and main language.
+const REGIONS: [(&str, &str, &str); 22] = [
+    ("World", "wor", "en"),
+    ("USA", "us", "en"),
+    ("Europe", "eu", "en"),
+    ("Japan", "jp", "ja"),
+    ("Asia", "asi", "en"),
+    ("Australia", "au", "en"),
+    ("Brazil", "br", "pt"),
+    ("Canada", "ca", "en"),
+    ("China", "cn", "zh"),
+    ("France", "fr", "fr"),
+    ("Germany", "de", "de"),
+    ("Hong Kong", "hk", "zh"),
+    ("Italy", "it", "it"),
+    ("Korea", "kr", "ko"),
+    ("Netherlands", "nl", "nl"),
+    ("Russia", "ru", "ru"),
+    ("Spain", "sp", "es"),
+    ("Sweden", "se", "sv"),
+    ("Taiwan", "tw", "zh"),
+    ("United Kingdom", "uk", "en"),
+    ("UK", "uk", "en"),
+    ("Portugal", "pt", "pt"),
+];
+
+// GoodTools country codes with their Batocera region code. Combined codes such as `JU` are read a
+// letter at a time.
+const GOODTOOLS_REGIONS: [(&str, &str); 19] = [
+    ("W", "wor"),
+    ("U", "us"),
+    ("E", "eu"),
+    ("J", "jp"),
+    ("A", "au"),
+    ("B", "br"),
+    ("C", "cn"),
+    ("F", "fr"),
+    ("G", "de"),
+    ("I", "it"),
+    ("K", "kr"),
+    ("S", "sp"),
+    ("Ch", "cn"),
+    ("FC", "ca"),
+    ("HK", "hk"),
+    ("NL", "nl"),
+    ("Sw", "se"),
+    ("UK", "uk"),
+    ("R", "ru"),
+];
+
+// TOSEC country codes, ISO 3166, which differ from their Batocera region code.
+const TOSEC_REGIONS: [(&str, &str); 2] = [
+    ("GB", "uk"),
+    ("ES", "sp"),
+];
+
+/// A flag given by a name's tags.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Flag {
+    Verified,
+    Alternate,
+    BadDump,
+    Fixed,
+    Hack,
+    Overdump,
+    Underdump,
+    Pirate,
+    Trainer,
+    Translation,
+    Cracked,
+    Modified,
+    Virus,
+    Beta,
+    Prototype,
+    Demo,
+    Sample,
+    Unlicensed,
+    PublicDomain,
+}
+
+/// What a ROM or DAT game name says about the game.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NameTags {
+    /// The name without its tags, eg `Super Mario World`.
+    pub title: String,
+    /// The Batocera codes of the regions, eg `["us", "eu"]`.
+    pub regions: Vec<String>,
+    /// The language codes, from the language tag or else the main language of the first region.
+    pub languages: Vec<String>,
+    /// The revision or version, as written, eg `Rev 1` or `v1.1`.
+    pub revision: Option<String>,
+    /// The year of release, given by TOSEC names.
+    pub year: Option<String>,
+    /// The publisher, given by TOSEC names.
+    pub publisher: Option<String>,
+    pub flags: Vec<Flag>,
+}
+
+impl NameTags {
+    /// The game fields which can be filled from the tags, as field name and value pairs. Values are
+    /// empty where the tags say nothing.
+    pub fn field_values(&self) -> [(&'static str, String); 3] {
+        [
+            ("name", self.title.clone()),
+            ("region", self.regions.join(",")),
+            ("lang", self.languages.join(",")),
+        ]
+    }
+}
+
+/// Reads the tags of a ROM's file name, the directories and extension of the path are ignored.
+///
+/// ### Arguments
+/// * `path` - The ROM's path, eg `./Super Mario World (USA, Europe) (Rev 1) [!].sfc`.
+pub fn parse_file_name(path: &str) -> NameTags {
+    let stem = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    parse(stem)
+}
+
+/// Reads the tags of a name which has no file extension, such as a DAT game name.
+pub fn parse(name: &str) -> NameTags {
+    let mut tags = NameTags::default();
+    let language = Regex::new(r"^[A-Z][a-z](-[A-Za-z]{2})?$").unwrap();
+    let tosec_language = Regex::new(r"^[a-z]{2}(-[a-z]{2})*$").unwrap();
+    let revision = Regex::new(r"^(Rev [0-9A-Z.]+|[vV]\d+(\.\d+)*[a-z]?|PRG\d+)$").unwrap();
+    let year = Regex::new(r"^(19|20)[0-9x]{2}(-[0-9x]{2}){0,2}$").unwrap();
+
+    // TOSEC puts the version after the title, eg `Title v1.1 (1991)(Publisher)`
+    let version = Regex::new(r"\s+(v\d+(\.\d+)*[a-z]?)$").unwrap();
+    let full_title = title(name);
+    match version.captures(&full_title) {
+        Some(found) => {
+            tags.title = move_article(&full_title[..found.get(0).unwrap().start()]);
+            tags.revision = Some(found[1].to_string());
+        }
+        None => tags.title = move_article(&full_title),
+    }
+
+    let round = bracketed(name, '(');
+    for (position, tag) in round.iter().enumerate() {
+        let tag = tag.trim();
+        let parts: Vec<&str> = tag.split(',').map(|part| part.trim()).collect();
+
+        if let Some(codes) = region_codes(tag) {
+            tags.regions.extend(codes);
+        }
+        else if parts.iter().all(|part| language.is_match(part)) {
+            tags.languages.extend(parts.iter().map(|part| part.split('-').next().unwrap_or(part).to_lowercase()));
+        }
+        else if tosec_language.is_match(tag) && tags.year.is_some() {
+            tags.languages.extend(tag.split('-').map(|part| part.to_string()));
+        }
+        else if revision.is_match(tag) {
+            tags.revision = Some(tag.to_string());
+        }
+        else if let Some(flag) = round_flag(tag) {
+            tags.flags.push(flag);
+        }
+        else if position == 0 && year.is_match(tag) {
+            tags.year = Some(tag.to_string());
+            // the publisher follows the year, `-` when it is unknown
+            tags.publisher = round.get(1).map(|publisher| publisher.trim().to_string()).filter(|publisher| publisher != "-");
+        }
+    }
+
+    for tag in bracketed(name, '[') {
+        if let Some(flag) = square_flag(tag.trim()) {
+            if !tags.flags.contains(&flag) {
+                tags.flags.push(flag);
+            }
+        }
+    }
+
+    tags.regions.dedup();
+    if tags.languages.is_empty() {
+        let first_region = tags.regions.first()
+            .and_then(|code| REGIONS.iter().find(|(_, region, _)| region == code));
+        tags.languages.extend(first_region.map(|(_, _, language)| language.to_string()));
+    }
+    tags
+}
+
+/// Returns the Batocera code of a No-Intro region name, eg `us` for `USA`.
+pub fn region_code(region: &str) -> Option<&'static str> {
+    REGIONS.iter().find(|(name, _, _)| *name == region).map(|(_, code, _)| *code)
+}
+
+/// Returns a name with its bracketed tags removed, eg `Super Mario World` for
+/// `Super Mario World (USA) [!]`.
+pub fn title(name: &str) -> String {
+    let tags = Regex::new(r"\s*(\([^)]*\)|\[[^\]]*\])").unwrap();
+    tags.replace_all(name, "").trim().to_string()
+}
+
+// Returns the region codes of a tag, None if the tag is not a region tag. A tag is only a region
+// tag if every part of it is a region.
+fn region_codes(tag: &str) -> Option<Vec<String>> {
+    let find = |table: &[(&str, &str)], part: &str| table.iter().find(|(name, _)| *name == part).map(|(_, code)| code.to_string());
+    let all = |codes: Vec<Option<String>>| -> Option<Vec<String>> {
+        if codes.is_empty() { None } else { codes.into_iter().collect() }
+    };
+    let no_intro: Vec<(&str, &str)> = REGIONS.iter().map(|(name, code, _)| (*name, *code)).collect();
+
+    // No-Intro, eg `USA, Europe`
+    let parts: Vec<&str> = tag.split(',').map(|part| part.trim()).collect();
+    if let Some(codes) = all(parts.iter().map(|part| find(&no_intro, part)).collect()) {
+        return Some(codes);
+    }
+    // GoodTools, eg `U` or `HK`
+    if let Some(code) = find(&GOODTOOLS_REGIONS, tag) {
+        return Some(vec![code]);
+    }
+    // TOSEC, eg `US` or `DE-GB`
+    let tosec = Regex::new(r"^[A-Z]{2}(-[A-Z]{2})*$").unwrap();
+    if tosec.is_match(tag) {
+        let codes = tag.split('-').map(|part| {
+            find(&TOSEC_REGIONS, part).or_else(|| {
+                let code = part.to_lowercase();
+                no_intro.iter().any(|(_, known)| *known == code).then_some(code)
+            })
+        });
+        if let Some(codes) = all(codes.collect()) {
+            return Some(codes);
+        }
+    }
+    // GoodTools combined codes, eg `JU` or `JUE`
+    if tag.len() > 1 && tag.len() <= 3 && tag.chars().all(|letter| letter.is_ascii_uppercase()) {
+        return all(tag.chars().map(|letter| find(&GOODTOOLS_REGIONS, &letter.to_string())).collect());
+    }
+    None
+}
+
+// Returns the flag given by a tag in round brackets, eg `(Beta 2)` or `(Unl)`.
+fn round_flag(tag: &str) -> Option<Flag> {
+    let word = tag.split_whitespace().next().unwrap_or_default();
+    match word {
+        "Beta" => Some(Flag::Beta),
+        "Proto" | "Prototype" => Some(Flag::Prototype),
+        "Demo" => Some(Flag::Demo),
+        "Sample" => Some(Flag::Sample),
+        "Unl" | "Unlicensed" => Some(Flag::Unlicensed),
+        "Hack" => Some(Flag::Hack),
+        _ if tag == "PD" || tag == "Public Domain" => Some(Flag::PublicDomain),
+        _ => None,
+    }
+}
+
+// Returns the flag given by a tag in square brackets, eg `[!]`, `[b1]`, `[T+Eng]` or `[cr Fairlight]`.
+fn square_flag(tag: &str) -> Option<Flag> {
+    if tag == "!" {
+        return Some(Flag::Verified);
+    }
+    let code: String = tag.chars().take_while(|letter| letter.is_ascii_alphabetic()).collect();
+    let rest = &tag[code.len()..];
+    // the code is followed by a number, a space, a sign or nothing, eg `a1`, `h Group`, `T-Fre`
+    if !(rest.is_empty() || rest.starts_with(|next: char| next.is_ascii_digit() || " +-".contains(next))) {
+        return None;
+    }
+    match code.as_str() {
+        "a" => Some(Flag::Alternate),
+        "b" => Some(Flag::BadDump),
+        "f" => Some(Flag::Fixed),
+        "h" => Some(Flag::Hack),
+        "o" => Some(Flag::Overdump),
+        "u" => Some(Flag::Underdump),
+        "p" => Some(Flag::Pirate),
+        "t" => Some(Flag::Trainer),
+        "T" | "tr" => Some(Flag::Translation),
+        "cr" => Some(Flag::Cracked),
+        "m" => Some(Flag::Modified),
+        "v" => Some(Flag::Virus),
+        _ => None,
+    }
+}
+
+// Moves a trailing article to the front, eg `Legend of Zelda, The - A Link to the Past` becomes
+// `The Legend of Zelda - A Link to the Past`.
+fn move_article(title: &str) -> String {
+    let article = Regex::new(r"^(.+?), (The|A|An)( - .*)?$").unwrap();
+    match article.captures(title) {
+        Some(found) => format!("{} {}{}", &found[2], &found[1], found.get(3).map(|rest| rest.as_str()).unwrap_or_default()),
+        None => title.to_string(),
+    }
+}
+
+// Returns the text inside each pair of brackets, opened by `open`, within a name.
+fn bracketed(name: &str, open: char) -> Vec<String> {
+    let close = if open == '(' { ')' } else { ']' };
+    let mut tags = Vec::<String>::new();
+    let mut rest = name;
+    while let Some(start) = rest.find(open) {
+        let after = &rest[start + 1..];
+        match after.find(close) {
+            Some(end) => {
+                tags.push(after[..end].to_string());
+                rest = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+    tags
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_title() {
+        assert_eq!(title("Super Mario World (USA, Europe) (Rev 1) [!]"), "Super Mario World");
+        assert_eq!(title("Tetris"), "Tetris");
+    }
+
+    #[test]
+    fn test_regions() {
+        assert_eq!(parse("Super Mario World (USA, Europe) (Rev 1)").regions, vec!["us", "eu"]);
+        assert_eq!(parse("Game (Japan) (En,Ja)").regions, vec!["jp"]);
+        assert!(parse("Game (Rev 1)").regions.is_empty());
+    }
+
+    #[test]
+    fn test_languages() {
+        assert_eq!(parse("Game (Europe) (En,Fr,De)").languages, vec!["en", "fr", "de"]);
+        assert_eq!(parse("Game (Japan)").languages, vec!["ja"]);
+        assert_eq!(parse("Game (Brazil) (Pt-BR)").languages, vec!["pt"]);
+        assert!(parse("Game").languages.is_empty());
+    }
+
+    #[test]
+    fn test_region_code() {
+        assert_eq!(region_code("USA"), Some("us"));
+        assert_eq!(region_code("Atlantis"), None);
+    }
+
+    #[test]
+    fn test_parse_no_intro() {
+        let tags = parse_file_name("./Super Mario World (USA, Europe) (Rev 1) [!].sfc");
+        assert_eq!(tags.title, "Super Mario World");
+        assert_eq!(tags.regions, vec!["us", "eu"]);
+        assert_eq!(tags.languages, vec!["en"]);
+        assert_eq!(tags.revision.as_deref(), Some("Rev 1"));
+        assert_eq!(tags.flags, vec![Flag::Verified]);
+
+        let tags = parse_file_name("Legend of Zelda, The - A Link to the Past (Japan) (Beta).sfc");
+        assert_eq!(tags.title, "The Legend of Zelda - A Link to the Past");
+        assert_eq!(tags.flags, vec![Flag::Beta]);
+    }
+
+    #[test]
+    fn test_parse_goodtools() {
+        let tags = parse_file_name("Street Fighter II (JU) (V1.1) [h1C][T+Fre].smc");
+        assert_eq!(tags.title, "Street Fighter II");
+        assert_eq!(tags.regions, vec!["jp", "us"]);
+        assert_eq!(tags.languages, vec!["ja"]);
+        assert_eq!(tags.revision.as_deref(), Some("V1.1"));
+        assert_eq!(tags.flags, vec![Flag::Hack, Flag::Translation]);
+        assert_eq!(parse_file_name("Tetris (W) [b2].gb").flags, vec![Flag::BadDump]);
+    }
+
+    #[test]
+    fn test_parse_tosec() {
+        let tags = parse_file_name("Turrican II v1.1 (1991)(Rainbow Arts)(DE-GB)(en-de)[cr Fairlight][a].adf");
+        assert_eq!(tags.title, "Turrican II");
+        assert_eq!(tags.year.as_deref(), Some("1991"));
+        assert_eq!(tags.publisher.as_deref(), Some("Rainbow Arts"));
+        assert_eq!(tags.regions, vec!["de", "uk"]);
+        assert_eq!(tags.languages, vec!["en", "de"]);
+        assert_eq!(tags.revision.as_deref(), Some("v1.1"));
+        assert_eq!(tags.flags, vec![Flag::Cracked, Flag::Alternate]);
+        assert_eq!(parse_file_name("Game (199x)(-)").publisher, None);
+    }
+
+    #[test]
+    fn test_field_values() {
+        let values = parse_file_name("Game (Europe) (En,Fr).md").field_values();
+        assert_eq!(values, [("name", "Game".to_string()), ("region", "eu".to_string()), ("lang", "en,fr".to_string())]);
+    }
+}
@@ -0,0 +1,533 @@
+//! This module holds the emulator and game lists opened by the frontend.
+//!
+//! The backend's copy of a list is the one that is edited and saved. The frontend is given a
+//! handle for each list it opens and refers to the list by its handle, sending only the edits to
+//! make rather than the whole list, which may hold stale games. Each game list records whether it
+//! has edits which have not been saved, and the fingerprint of the gamelist.xml file it was read
+//! from so changes made to the file by another program are noticed before saving.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use serde::Serialize;
+
+use super::emulator_listings;
+use super::emulator_listings::structs::emulator_list::EmulatorList;
+use super::game_listings;
+use super::game_listings::backups::{self, SaveOptions};
+use super::game_listings::conflicts::{self, FieldConflict, Fingerprint};
+use super::game_listings::structs::folder::Folder;
+use super::game_listings::structs::game::Game;
+use super::game_listings::structs::game_list::GameList;
+
+/// Identifies a list held by the session.
+pub type Handle = usize;
+
+/// Errors which can occur while using the lists held by the session.
+#[derive(Debug)]
+pub enum Error {
+    /// The session does not hold a list with the handle, it was never opened or has been closed.
+    UnknownHandle(Handle),
+    /// The game list does not hold a game with the id.
+    UnknownGame(usize),
+    /// The game list does not hold a folder with the id.
+    UnknownFolder(usize),
+    /// No emulator directories were found within the directory.
+    NoEmulators(String),
+    /// The game list has edits which have not been saved, saving a single game or folder would save
+    /// them along with it and restoring a backup would lose them.
+    UnsavedEdits,
+    /// The game list could not be read or saved.
+    GameList(game_listings::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownHandle(handle) => write!(f, "No list is open with the handle {}", handle),
+            Error::UnknownGame(id) => write!(f, "The game list does not hold a game with the id {}", id),
+            Error::UnknownFolder(id) => write!(f, "The game list does not hold a folder with the id {}", id),
+            Error::NoEmulators(path) => write!(f, "No emulators found in {}", path),
+            Error::UnsavedEdits => write!(f, "The game list has edits which have not been saved, save the whole list first"),
+            Error::GameList(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<game_listings::Error> for Error {
+    fn from(error: game_listings::Error) -> Self {
+        Error::GameList(error)
+    }
+}
+
+/// An emulator list held by the session, as it is sent to the frontend.
+#[derive(Debug, Serialize)]
+pub struct OpenEmulatorList {
+    pub handle: Handle,
+    #[serde(flatten)]
+    pub emulator_list: EmulatorList,
+}
+
+/// A game list held by the session, as it is sent to the frontend.
+#[derive(Debug, Serialize)]
+pub struct OpenGameList {
+    pub handle: Handle,
+    /// True when the list has edits which have not been saved.
+    pub dirty: bool,
+    /// The gamelist.xml file the list was read from or last saved to, None if there was no file.
+    pub fingerprint: Option<Fingerprint>,
+    #[serde(flatten)]
+    pub game_list: GameList,
+}
+
+/// The lists opened by the frontend.
+#[derive(Debug, Default)]
+pub struct Session {
+    lists: Mutex<Lists>,
+}
+
+#[derive(Debug, Default)]
+struct Lists {
+    next_handle: Handle,
+    emulator_lists: HashMap<Handle, EmulatorList>,
+    game_lists: HashMap<Handle, HeldGameList>,
+}
+
+#[derive(Debug)]
+struct HeldGameList {
+    game_list: GameList,
+    dirty: bool,
+}
+
+impl HeldGameList {
+    fn open(&self, handle: Handle) -> OpenGameList {
+        OpenGameList {
+            handle,
+            dirty: self.dirty,
+            fingerprint: self.fingerprint(),
+            game_list: self.game_list.clone(),
+        }
+    }
+
+    fn fingerprint(&self) -> Option<Fingerprint> {
+        self.game_list.loaded.as_ref().map(|loaded| loaded.fingerprint.clone())
+    }
+}
+
+impl Lists {
+    fn new_handle(&mut self) -> Handle {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    fn game_list(&mut self, handle: Handle) -> Result<&mut HeldGameList, Error> {
+        self.game_lists.get_mut(&handle).ok_or(Error::UnknownHandle(handle))
+    }
+
+    // Returns the handle of the game list held for a directory, whether it has unsaved edits and
+    // the fingerprint of the file it was read from or last saved to.
+    fn find_game_list(&self, directory: &Path) -> Option<(Handle, bool, Option<Fingerprint>)> {
+        self.game_lists.iter()
+            .find(|(_, held)| held.game_list.directory == directory)
+            .map(|(handle, held)| (*handle, held.dirty, held.fingerprint()))
+    }
+}
+
+impl Session {
+
+    /// Lists the emulator directories found within a directory and holds the list.
+    ///
+    /// ### Arguments
+    /// * `path` - The directory holding the emulator directories, eg `/userdata/roms`.
+    pub fn open_emulator_list(&self, path: &str) -> Result<OpenEmulatorList, Error> {
+        let emulator_list = emulator_listings::list(path).ok_or_else(|| Error::NoEmulators(path.to_string()))?;
+        let mut lists = self.lock();
+        let handle = lists.new_handle();
+        lists.emulator_lists.insert(handle, emulator_list.clone());
+        Ok(OpenEmulatorList { handle, emulator_list })
+    }
+
+    /// Returns an emulator list held by the session.
+    pub fn emulator_list(&self, handle: Handle) -> Result<OpenEmulatorList, Error> {
+        let lists = self.lock();
+        let emulator_list = lists.emulator_lists.get(&handle).ok_or(Error::UnknownHandle(handle))?;
+        Ok(OpenEmulatorList { handle, emulator_list: emulator_list.clone() })
+    }
+
+    /// Reads the game list of an emulator directory and holds it. A list already open for the
+    /// directory keeps its handle, it is read again unless it has edits which have not been saved.
+    ///
+    /// ### Arguments
+    /// * `valid_dir` - The emulator directory.
+    pub fn open_game_list(&self, valid_dir: &str) -> Result<OpenGameList, Error> {
+        let directory = PathBuf::from(valid_dir);
+        let mut lists = self.lock();
+        loop {
+            let open = lists.find_game_list(&directory);
+            if let Some((handle, true, _)) = open {
+                return Ok(lists.game_list(handle)?.open(handle));
+            }
+
+            // the directory is read without holding the lock, other lists stay usable meanwhile
+            drop(lists);
+            let mut game_list = game_listings::new(valid_dir)?;
+            lists = self.lock();
+            // the list may have been opened, edited or saved by another command meanwhile, it is
+            // then looked at again rather than replaced or held twice
+            if lists.find_game_list(&directory) != open {
+                continue;
+            }
+
+            let handle = match open {
+                Some((handle, ..)) => handle,
+                None => lists.new_handle(),
+            };
+            if let Some(previous) = lists.game_lists.get(&handle) {
+                game_list.keep_ids(&previous.game_list);
+            }
+            let held = HeldGameList { game_list, dirty: false };
+            let open = held.open(handle);
+            lists.game_lists.insert(handle, held);
+            return Ok(open);
+        }
+    }
+
+    /// Returns a game list held by the session.
+    pub fn game_list(&self, handle: Handle) -> Result<OpenGameList, Error> {
+        Ok(self.lock().game_list(handle)?.open(handle))
+    }
+
+    /// Stops holding a list, any edits which have not been saved are lost.
+    pub fn close(&self, handle: Handle) {
+        let mut lists = self.lock();
+        lists.emulator_lists.remove(&handle);
+        lists.game_lists.remove(&handle);
+    }
+
+    /// Reads a game list held by the session.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `read` - Called with the game list, its result is returned.
+    pub fn read<T>(&self, handle: Handle, read: impl FnOnce(&GameList) -> T) -> Result<T, Error> {
+        Ok(read(&self.lock().game_list(handle)?.game_list))
+    }
+
+    /// Edits a game list held by the session, the list is then marked as having unsaved edits.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `edit` - Called with the game list, its result is returned.
+    pub fn update<T>(&self, handle: Handle, edit: impl FnOnce(&mut GameList) -> T) -> Result<T, Error> {
+        let mut lists = self.lock();
+        let held = lists.game_list(handle)?;
+        held.dirty = true;
+        Ok(edit(&mut held.game_list))
+    }
+
+    /// Fills in what is known about a game list held by the session without being saved to its
+    /// gamelist.xml file, such as ROM hashes or orphaned games. The list is not marked as having
+    /// unsaved edits.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `annotate` - Called with the game list, its result is returned.
+    pub fn annotate<T>(&self, handle: Handle, annotate: impl FnOnce(&mut GameList) -> T) -> Result<T, Error> {
+        Ok(annotate(&mut self.lock().game_list(handle)?.game_list))
+    }
+
+    /// Saves a game list held by the session to its gamelist.xml file.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `options` - The number of backups to keep.
+    pub fn save(&self, handle: Handle, options: &SaveOptions) -> Result<OpenGameList, Error> {
+        let mut lists = self.lock();
+        let held = lists.game_list(handle)?;
+        save_game_list(held, options)?;
+        Ok(held.open(handle))
+    }
+
+    /// Changes the fields of one game of a game list held by the session and saves the list.
+    ///
+    /// The list must not have other edits which have not been saved, as the whole list is saved.
+    /// If the save fails, for instance because gamelist.xml was changed by another program, the
+    /// game is left as it was.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `game_id` - The id of the game.
+    /// * `fields` - The names and new values of the fields which changed.
+    /// * `options` - The number of backups to keep.
+    /// ### Returns
+    /// * `Result<Game, Error>` - The saved game, with its values validated.
+    pub fn save_game(&self, handle: Handle, game_id: usize, fields: &HashMap<String, String>, options: &SaveOptions) -> Result<Game, Error> {
+        let mut lists = self.lock();
+        let held = lists.game_list(handle)?;
+        if held.dirty {
+            return Err(Error::UnsavedEdits);
+        }
+        let index = held.game_list.games.iter()
+            .position(|game| game.game_id() == game_id)
+            .ok_or(Error::UnknownGame(game_id))?;
+
+        let previous = held.game_list.games[index].clone();
+        let game = &mut held.game_list.games[index];
+        for name in sorted_names(fields) {
+            game.set_field(name, fields[name].clone());
+        }
+        let game = game.clone();
+
+        if let Err(e) = save_game_list(held, options) {
+            held.game_list.games[index] = previous;
+            return Err(e);
+        }
+        Ok(game)
+    }
+
+    /// Changes the fields of one folder of a game list held by the session and saves the list, as
+    /// [`Session::save_game`] does for a game.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `folder_id` - The id of the folder.
+    /// * `fields` - The names and new values of the fields which changed.
+    /// * `options` - The number of backups to keep.
+    /// ### Returns
+    /// * `Result<Folder, Error>` - The saved folder, with its values validated.
+    pub fn save_folder(&self, handle: Handle, folder_id: usize, fields: &HashMap<String, String>, options: &SaveOptions) -> Result<Folder, Error> {
+        let mut lists = self.lock();
+        let held = lists.game_list(handle)?;
+        if held.dirty {
+            return Err(Error::UnsavedEdits);
+        }
+        let index = held.game_list.folders.iter()
+            .position(|folder| folder.folder_id() == folder_id)
+            .ok_or(Error::UnknownFolder(folder_id))?;
+
+        let previous = held.game_list.folders[index].clone();
+        let folder = &mut held.game_list.folders[index];
+        for name in sorted_names(fields) {
+            folder.set_field(name, fields[name].clone());
+        }
+        let folder = folder.clone();
+
+        if let Err(e) = save_game_list(held, options) {
+            held.game_list.folders[index] = previous;
+            return Err(e);
+        }
+        Ok(folder)
+    }
+
+    /// Reads a game list held by the session again from its emulator directory, any edits which
+    /// have not been saved are lost.
+    pub fn reload(&self, handle: Handle) -> Result<OpenGameList, Error> {
+        let mut lists = self.lock();
+        loop {
+            let held = lists.game_list(handle)?;
+            let (directory, fingerprint) = (held.game_list.directory.clone(), held.fingerprint());
+
+            drop(lists);
+            let mut game_list = game_listings::new(&directory.to_string_lossy())?;
+            lists = self.lock();
+            let held = lists.game_list(handle)?;
+            // a list saved meanwhile was saved to a newer file than the one read, which is read again
+            if held.fingerprint() != fingerprint {
+                continue;
+            }
+
+            game_list.keep_ids(&held.game_list);
+            *held = HeldGameList { game_list, dirty: false };
+            return Ok(held.open(handle));
+        }
+    }
+
+    /// Replaces the gamelist.xml file of a game list held by the session with one of its backups, see
+    /// [`backups::restore`], and reads the list again.
+    ///
+    /// The list must not have edits which have not been saved and its file must not have been
+    /// changed by another program since it was read, as the restore would lose them.
+    ///
+    /// ### Arguments
+    /// * `handle` - The game list's handle.
+    /// * `timestamp` - The timestamp of the backup to restore.
+    /// * `options` - The number of backups to keep.
+    pub fn restore_backup(&self, handle: Handle, timestamp: u64, options: &SaveOptions) -> Result<OpenGameList, Error> {
+        {
+            let mut lists = self.lock();
+            let held = lists.game_list(handle)?;
+            if held.dirty {
+                return Err(Error::UnsavedEdits);
+            }
+            let path = gamelist_path(&held.game_list.directory);
+            let on_disk = conflicts::read(&path).map_err(game_listings::Error::Io)?;
+            conflicts::check(held.game_list.loaded.as_ref(), on_disk.as_ref()).map_err(game_listings::Error::Conflict)?;
+            backups::restore(&path, timestamp, options).map_err(game_listings::Error::Io)?;
+        }
+        self.reload(handle)
+    }
+
+    /// Merges the edits made to a game list held by the session with the changes made to its
+    /// gamelist.xml file by another program, see [`conflicts::merge`].
+    ///
+    /// ### Returns
+    /// * `Result<Vec<FieldConflict>, Error>` - The fields changed on both sides, the merged list keeps the session's values.
+    pub fn merge(&self, handle: Handle) -> Result<Vec<FieldConflict>, Error> {
+        let mut lists = self.lock();
+        let held = lists.game_list(handle)?;
+        let on_disk = conflicts::read(&gamelist_path(&held.game_list.directory)).map_err(game_listings::Error::Io)?;
+        let merge = conflicts::merge(held.game_list.clone(), on_disk)?;
+        held.game_list = merge.game_list;
+        held.dirty = true;
+        Ok(merge.conflicts)
+    }
+
+    // A poisoned lock only means a command panicked part way through, the lists are still usable.
+    fn lock(&self) -> MutexGuard<'_, Lists> {
+        self.lists.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Returns the names of fields sent by the frontend in a fixed order, so fields are added in the
+/// same order whatever order they were sent in.
+pub fn sorted_names(fields: &HashMap<String, String>) -> Vec<&String> {
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort();
+    names
+}
+
+fn gamelist_path(emulator_dir: &Path) -> PathBuf {
+    emulator_dir.join("gamelist.xml")
+}
+
+// Saves a held game list, which is saved from the path of its gamelist.xml file.
+fn save_game_list(held: &mut HeldGameList, options: &SaveOptions) -> Result<(), Error> {
+    let emulator_dir = held.game_list.directory.clone();
+    held.game_list.directory = gamelist_path(&emulator_dir);
+    let saved = game_listings::save(&mut held.game_list, options);
+    held.game_list.directory = emulator_dir;
+    saved?;
+    held.dirty = false;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bgle::utils::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_save_game() {
+        let directory = TempDir::new("session");
+        fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc\"\n").unwrap();
+        fs::write(directory.join("a.smc"), "rom").unwrap();
+        fs::write(directory.join("b.smc"), "rom").unwrap();
+        fs::write(directory.join("gamelist.xml"), "<gameList>\n\t<game>\n\t\t<path>./a.smc</path>\n\t</game>\n\t<game>\n\t\t<path>./b.smc</path>\n\t\t<name>B</name>\n\t</game>\n</gameList>\n").unwrap();
+
+        let session = Session::default();
+        assert!(matches!(session.save_game(0, 0, &HashMap::new(), &SaveOptions::default()), Err(Error::UnknownHandle(0))));
+        let open = session.open_game_list(directory.to_str().unwrap()).unwrap();
+        assert!(!open.dirty);
+        assert_eq!(open.fingerprint, conflicts::read(&directory.join("gamelist.xml")).unwrap().map(|loaded| loaded.fingerprint));
+
+        // the frontend's copy of the other game is never sent, so it cannot overwrite the file
+        let fields = HashMap::from([(String::from("name"), String::from("A")), (String::from("players"), String::from("2"))]);
+        let game = session.save_game(open.handle, 0, &fields, &SaveOptions { backups: 0 }).unwrap();
+        assert_eq!(game.field_value("name"), Some("A"));
+        assert_eq!(fs::read_to_string(directory.join("gamelist.xml")).unwrap(), "<gameList>\n\t<game>\n\t\t<path>./a.smc</path>\n\t\t<name>A</name>\n\t\t<players>2</players>\n\t</game>\n\t<game>\n\t\t<path>./b.smc</path>\n\t\t<name>B</name>\n\t</game>\n</gameList>\n");
+
+        // the saved file is the one held, so the next save is not a conflict
+        session.save_game(open.handle, 1, &HashMap::from([(String::from("name"), String::from("C"))]), &SaveOptions { backups: 0 }).unwrap();
+        assert!(matches!(session.save_game(open.handle, 2, &fields, &SaveOptions::default()), Err(Error::UnknownGame(2))));
+
+        // a save refused as a conflict leaves the game as it was
+        fs::write(directory.join("gamelist.xml"), "<gameList>\n</gameList>\n").unwrap();
+        let fields = HashMap::from([(String::from("name"), String::from("D"))]);
+        assert!(matches!(session.save_game(open.handle, 1, &fields, &SaveOptions { backups: 0 }), Err(Error::GameList(game_listings::Error::Conflict(_)))));
+        let open = session.game_list(open.handle).unwrap();
+        assert_eq!(open.game_list.get(1).unwrap().field_value("name"), Some("C"));
+        assert!(!open.dirty);
+    }
+
+    #[test]
+    fn test_dirty_lists_are_kept() {
+        let directory = TempDir::new("session-dirty");
+        fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc\"\n").unwrap();
+        fs::write(directory.join("a.smc"), "rom").unwrap();
+
+        let session = Session::default();
+        let handle = session.open_game_list(directory.to_str().unwrap()).unwrap().handle;
+        session.update(handle, |game_list| game_list.games[0].set_field("name", String::from("Edited"))).unwrap();
+
+        // opening the directory again gives back the edited list rather than reading it again
+        let open = session.open_game_list(directory.to_str().unwrap()).unwrap();
+        assert_eq!((open.handle, open.dirty), (handle, true));
+        assert_eq!(session.read(handle, |game_list| game_list.games[0].field_value("name").map(String::from)).unwrap(), Some(String::from("Edited")));
+
+        // saving one game would save the other edit with it
+        let fields = HashMap::from([(String::from("players"), String::from("2"))]);
+        assert!(matches!(session.save_game(handle, 0, &fields, &SaveOptions { backups: 0 }), Err(Error::UnsavedEdits)));
+        assert!(!directory.join("gamelist.xml").exists());
+
+        let saved = session.save(handle, &SaveOptions { backups: 0 }).unwrap();
+        assert!(!saved.dirty && saved.fingerprint.is_some());
+        session.close(handle);
+        assert!(matches!(session.game_list(handle), Err(Error::UnknownHandle(_))));
+    }
+
+    #[test]
+    fn test_restore_backup() {
+        let directory = TempDir::new("session-restore");
+        fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc\"\n").unwrap();
+        fs::write(directory.join("a.smc"), "rom").unwrap();
+        fs::write(directory.join("gamelist.xml"), "<gameList>\n\t<game>\n\t\t<path>./a.smc</path>\n\t\t<name>A</name>\n\t</game>\n</gameList>\n").unwrap();
+
+        let session = Session::default();
+        let handle = session.open_game_list(directory.to_str().unwrap()).unwrap().handle;
+        let fields = HashMap::from([(String::from("name"), String::from("B"))]);
+        session.save_game(handle, 0, &fields, &SaveOptions { backups: 2 }).unwrap();
+        let timestamp = backups::list(&directory.join("gamelist.xml")).unwrap()[0].timestamp;
+
+        // an edit which has not been saved would be lost
+        session.update(handle, |game_list| game_list.games[0].set_field("name", String::from("C"))).unwrap();
+        assert!(matches!(session.restore_backup(handle, timestamp, &SaveOptions { backups: 2 }), Err(Error::UnsavedEdits)));
+        session.reload(handle).unwrap();
+
+        // as would a change made to the file by another program
+        let saved = fs::read_to_string(directory.join("gamelist.xml")).unwrap();
+        fs::write(directory.join("gamelist.xml"), saved.replace("<name>B</name>", "<name>D</name>\n\t\t<players>2</players>")).unwrap();
+        assert!(matches!(session.restore_backup(handle, timestamp, &SaveOptions { backups: 2 }), Err(Error::GameList(game_listings::Error::Conflict(_)))));
+
+        session.reload(handle).unwrap();
+        let open = session.restore_backup(handle, timestamp, &SaveOptions { backups: 2 }).unwrap();
+        assert_eq!(open.game_list.games[0].field_value("name"), Some("A"));
+        assert!(!open.dirty);
+    }
+
+    #[test]
+    fn test_reload_keeps_game_ids() {
+        let directory = TempDir::new("session-reload");
+        fs::write(directory.join("_info.txt"), "ROM files extensions accepted: \".smc\"\n").unwrap();
+        fs::write(directory.join("a.smc"), "rom").unwrap();
+        fs::write(directory.join("b.smc"), "rom").unwrap();
+        fs::write(directory.join("gamelist.xml"), "<gameList>\n\t<game>\n\t\t<path>./b.smc</path>\n\t</game>\n</gameList>\n").unwrap();
+
+        let session = Session::default();
+        let open = session.open_game_list(directory.to_str().unwrap()).unwrap();
+        let id_of = |open: &OpenGameList, path: &str| open.game_list.games.iter()
+            .find(|game| game.field_value("path") == Some(path))
+            .map(|game| game.game_id());
+        let (a, b) = (id_of(&open, "./a.smc").unwrap(), id_of(&open, "./b.smc").unwrap());
+
+        // a game listed before the others no longer moves their ids along
+        fs::write(directory.join("c.smc"), "rom").unwrap();
+        fs::write(directory.join("gamelist.xml"), "<gameList>\n\t<game>\n\t\t<path>./c.smc</path>\n\t</game>\n\t<game>\n\t\t<path>./b.smc</path>\n\t</game>\n</gameList>\n").unwrap();
+        let reloaded = session.reload(open.handle).unwrap();
+        assert_eq!((id_of(&reloaded, "./a.smc"), id_of(&reloaded, "./b.smc")), (Some(a), Some(b)));
+        assert!(![a, b].contains(&id_of(&reloaded, "./c.smc").unwrap()));
+    }
+}
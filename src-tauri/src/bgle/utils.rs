@@ -84,6 +84,42 @@ pub fn directory_path_buf(dir_path: &String) -> Result<PathBuf, io::Error> {
     }
 }
 
+/// An empty directory within the system's temp directory for a test to write to. The directory and
+/// its contents are removed when it is dropped, even if the test fails.
+#[cfg(test)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TempDir {
+
+    /// Creates the directory, named after the test so tests running at the same time are kept
+    /// apart. A directory left by an earlier run is removed first.
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("bgle-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -4,12 +4,35 @@
 
 mod bgle;
 use bgle::game_listings;
+use bgle::game_listings::backups::{self, Backup, SaveOptions};
+use bgle::game_listings::conflicts::FieldConflict;
+use bgle::game_listings::gamelist_xml::EntryKind;
+use bgle::game_listings::orphans::{self, InvalidPath, Orphan};
+use bgle::game_listings::duplicates::{self, CrossSystemDuplicate, DuplicateGroup};
+use bgle::game_listings::structs::folder::Folder;
 use bgle::game_listings::structs::game::{Game, self};
 use bgle::game_listings::structs::game_list::GameList;
-use bgle::emulator_listings::list;
-use bgle::emulator_listings::structs::emulator_list::EmulatorList;
+use bgle::media::{self, MediaLink, MediaReport};
+use bgle::metadata::{self, FieldChange};
+use bgle::metadata::dat::{self, DatPreview};
+use bgle::metadata::file_names::{self, FileNamePreview};
+use bgle::metadata::launchbox::{self, LaunchBoxPreview};
+use bgle::metadata::openvgdb::{self, OpenVgdb, OpenVgdbPreview};
+use bgle::metadata::rdb::{self, RdbPreview};
+use bgle::metadata::scraper::{self, MediaDownload, ScrapePreview, UreqClient};
+use bgle::metadata::scraper::screenscraper::{ScreenScraper, ScreenScraperConfig};
+use bgle::media::import::{self, ImportOptions};
+use bgle::media::thumbnails::{self, ThumbnailFolder, ThumbnailMatch};
+use bgle::media::validation::{self, MediaValidationReport, ValidationOptions};
+use bgle::roms::ScanOptions;
+use bgle::roms::disc_sets;
+use bgle::roms::checksums;
+use bgle::session::{self, Handle, OpenEmulatorList, OpenGameList, Session};
+use bgle::utils::get_rom_extensions;
 
+use std::collections::HashMap;
 use std::path::PathBuf;
+use tauri::State;
 
 
 
@@ -22,40 +45,44 @@ fn greet(name: &str) -> String {
 
 
 #[tauri::command(rename_all = "snake_case")]
-fn save_game_list(game_list: GameList) -> Result<String, String> {
-    let mut game_list = game_list;
-    let mut game_directory = PathBuf::from(game_list.directory.to_str().unwrap());
-    game_directory.push("gamelist.xml");
-    game_list.directory = game_directory;
-  
+fn save_game_list(session: State<Session>, handle: Handle, options: Option<SaveOptions>) -> Result<OpenGameList, String> {
+    session.save(handle, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
 
-    if game_list.games.len() > 0 {
-        match game_listings::save(&game_list) {
-            Ok(_) => Ok(String::from("Game list saved")),
-            Err(e) => Err(e.to_string()),
-        }
-       
-    }
-    else {
-        Err(String::from("No games found"))
-    }
+#[tauri::command(rename_all = "snake_case")]
+fn save_game(session: State<Session>, handle: Handle, game_id: usize, fields: HashMap<String, String>, options: Option<SaveOptions>) -> Result<Game, String> {
+    session.save_game(handle, game_id, &fields, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
 
+#[tauri::command(rename_all = "snake_case")]
+fn save_folder(session: State<Session>, handle: Handle, folder_id: usize, fields: HashMap<String, String>, options: Option<SaveOptions>) -> Result<Folder, String> {
+    session.save_folder(handle, folder_id, &fields, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
 
+#[tauri::command(rename_all = "snake_case")]
+fn merge_game_list(session: State<Session>, handle: Handle) -> Result<(OpenGameList, Vec<FieldConflict>), String> {
+    let conflicts = session.merge(handle).map_err(|e| e.to_string())?;
+    Ok((session.game_list(handle).map_err(|e| e.to_string())?, conflicts))
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_gamelist_backups(session: State<Session>, handle: Handle) -> Result<Vec<Backup>, String> {
+    let directory = session.read(handle, |game_list| game_list.directory.clone()).map_err(|e| e.to_string())?;
+    backups::list(&directory.join("gamelist.xml")).map_err(|e| e.to_string())
 }
 
+#[tauri::command(rename_all = "snake_case")]
+fn restore_gamelist_backup(session: State<Session>, handle: Handle, timestamp: u64, options: Option<SaveOptions>) -> Result<OpenGameList, String> {
+    session.restore_backup(handle, timestamp, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
 
 #[tauri::command]
-fn get_emulator_list(path: &str) -> Result<EmulatorList, String> {
+fn get_emulator_list(session: State<Session>, path: &str) -> Result<OpenEmulatorList, String> {
     
     let mut buf_path = PathBuf::new();
     buf_path.push(path);
     if buf_path.exists() { 
-        if let Some(emulators) = list(path) {
-            Ok(emulators)
-        }
-        else {
-            Err(String::from("No emulators found"))
-        }
+        session.open_emulator_list(path).map_err(|e| e.to_string())
     } 
     else {
         Err(String::from(path))
@@ -63,21 +90,303 @@ fn get_emulator_list(path: &str) -> Result<EmulatorList, String> {
 }
 
 #[tauri::command(rename_all = "snake_case")]
-fn get_game_list(valid_dir: &str) ->Result<GameList, String> {
-    let games_list: GameList = game_listings::new(&valid_dir);
+fn get_open_emulator_list(session: State<Session>, handle: Handle) -> Result<OpenEmulatorList, String> {
+    session.emulator_list(handle).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_game_list(session: State<Session>, valid_dir: &str) ->Result<OpenGameList, String> {
+    let open = session.open_game_list(valid_dir).map_err(|e| e.to_string())?;
     
-    if games_list.len() > 0 {
-        Ok(games_list)
+    if open.game_list.len() > 0 {
+        Ok(open)
     }
     else {
+        session.close(open.handle);
         Err(String::from("No games found"))
     }
 }
 
+#[tauri::command(rename_all = "snake_case")]
+fn reload_game_list(session: State<Session>, handle: Handle) -> Result<OpenGameList, String> {
+    session.reload(handle).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn close_list(session: State<Session>, handle: Handle) {
+    session.close(handle);
+}
+
+// The new playlists are listed as games once the list is read again, which only happens here when
+// the list has no unsaved edits to lose
+#[tauri::command(rename_all = "snake_case")]
+fn create_disc_playlists(session: State<Session>, handle: Handle) -> Result<(OpenGameList, Vec<String>), String> {
+    let directory = session.read(handle, |game_list| game_list.directory.clone()).map_err(|e| e.to_string())?;
+    let extensions = get_rom_extensions(&directory).unwrap_or_default();
+
+    // a playlist is only listed as a game when the emulator accepts .m3u files
+    if !extensions.iter().any(|extension| extension == "m3u") {
+        return Err(String::from("This emulator does not accept .m3u playlists"));
+    }
+
+    let playlists = match disc_sets::write_m3u_playlists(&directory, &extensions, &ScanOptions::default()) {
+        Ok(playlists) => playlists.iter().map(|path| path.to_str().unwrap().to_string()).collect(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let open = session.game_list(handle).map_err(|e| e.to_string())?;
+    let open = if open.dirty { open } else { session.reload(handle).map_err(|e| e.to_string())? };
+    Ok((open, playlists))
+}
+
+#[tauri::command(async, rename_all = "snake_case")]
+fn compute_game_hashes(session: State<Session>, handle: Handle) -> Result<OpenGameList, String> {
+    // the ROMs are hashed without holding the session, then the crc32 and md5 fields are filled in
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    game_listings::hash_games(&mut game_list).map_err(|e| e.to_string())?;
+    session.update(handle, |held| {
+        for game in &game_list.games {
+            if let (Some(hashes), Some(held_game)) = (game.hashes(), held.get_mut(game.game_id())) {
+                held_game.set_archive(game.archive().cloned());
+                held_game.set_hashes(hashes.clone());
+            }
+        }
+    }).map_err(|e| e.to_string())?;
+    session.game_list(handle).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_orphaned_games(session: State<Session>, handle: Handle) -> Result<Vec<Orphan>, String> {
+    session.annotate(handle, orphans::find_orphans).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_invalid_game_paths(session: State<Session>, handle: Handle) -> Result<Vec<InvalidPath>, String> {
+    session.read(handle, orphans::find_invalid_paths).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn prune_orphaned_games(session: State<Session>, handle: Handle) -> Result<OpenGameList, String> {
+    session.update(handle, |game_list| {
+        orphans::mark_orphans(&game_list.directory.clone(), game_list);
+        orphans::prune_orphans(game_list);
+    }).map_err(|e| e.to_string())?;
+    session.game_list(handle).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn relink_orphaned_game(session: State<Session>, handle: Handle, orphan_id: usize, candidate_id: usize) -> Result<OpenGameList, String> {
+    let relinked = session.update(handle, |game_list| {
+        orphans::mark_orphans(&game_list.directory.clone(), game_list);
+        orphans::relink(game_list, orphan_id, candidate_id)
+    }).map_err(|e| e.to_string())?;
+    if relinked {
+        session.game_list(handle).map_err(|e| e.to_string())
+    }
+    else {
+        Err(String::from("The game is not orphaned or the file to re-link to was not found"))
+    }
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_duplicate_games(session: State<Session>, handle: Handle) -> Result<Vec<DuplicateGroup>, String> {
+    session.read(handle, duplicates::find_duplicates).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn merge_duplicate_games(session: State<Session>, handle: Handle, game_ids: Vec<usize>) -> Result<OpenGameList, String> {
+    match session.update(handle, |game_list| duplicates::merge_duplicates(game_list, &game_ids)).map_err(|e| e.to_string())? {
+        Some(_) => session.game_list(handle).map_err(|e| e.to_string()),
+        None => Err(String::from("At least two of the games must be in the game list to merge them")),
+    }
+}
+
+#[tauri::command(async, rename_all = "snake_case")]
+fn get_cross_system_duplicates(path: &str) -> Result<Vec<CrossSystemDuplicate>, String> {
+    Ok(duplicates::find_cross_system_duplicates(path))
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn match_media_files(session: State<Session>, handle: Handle) -> Result<MediaReport, String> {
+    session.read(handle, media::match_media).map_err(|e| e.to_string())?.map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn apply_media_links(session: State<Session>, handle: Handle, links: Vec<MediaLink>) -> Result<OpenGameList, String> {
+    session.update(handle, |game_list| media::apply_links(game_list, &links)).map_err(|e| e.to_string())?;
+    session.game_list(handle).map_err(|e| e.to_string())
+}
+
+// The image is named after the path of the game or folder held by the session. Its field is left for
+// the frontend to save with the rest of the game's form, so the list is not marked as edited
+#[tauri::command(rename_all = "snake_case")]
+fn import_media_image(session: State<Session>, handle: Handle, kind: EntryKind, id: usize, field: &str, source: &str, options: Option<ImportOptions>) -> Result<String, String> {
+    let options = options.unwrap_or_default();
+    let found = session.read(handle, |game_list| {
+        let path = match kind {
+            EntryKind::Game => game_list.get(id)?.field_value("path"),
+            EntryKind::Folder => game_list.folders.iter().find(|folder| folder.folder_id() == id)?.field_value("path"),
+        };
+        Some((game_list.directory.clone(), path.unwrap_or_default().to_string()))
+    }).map_err(|e| e.to_string())?;
+    let (directory, rom_path) = found.ok_or_else(|| match kind {
+        EntryKind::Game => session::Error::UnknownGame(id).to_string(),
+        EntryKind::Folder => session::Error::UnknownFolder(id).to_string(),
+    })?;
+
+    import::import_image(&directory, &rom_path, field, &PathBuf::from(source), &options).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn update_fields(session: State<Session>, handle: Handle, kind: EntryKind, id: usize, fields: HashMap<String, String>) -> Result<OpenGameList, String> {
+    if !session.update(handle, |game_list| set_fields(game_list, kind, id, &fields)).map_err(|e| e.to_string())? {
+        return Err(String::from("The game list does not hold the game or folder"));
+    }
+    session.game_list(handle).map_err(|e| e.to_string())
+}
+
+// Sets fields of a game or folder of a game list, returning false if the list does not hold it.
+fn set_fields(game_list: &mut GameList, kind: EntryKind, id: usize, fields: &HashMap<String, String>) -> bool {
+    match kind {
+        EntryKind::Game => match game_list.get_mut(id) {
+            Some(game) => session::sorted_names(fields).into_iter().for_each(|name| game.set_field(name, fields[name].clone())),
+            None => return false,
+        },
+        EntryKind::Folder => match game_list.folders.iter_mut().find(|folder| folder.folder_id() == id) {
+            Some(folder) => session::sorted_names(fields).into_iter().for_each(|name| folder.set_field(name, fields[name].clone())),
+            None => return false,
+        },
+    }
+    true
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn get_media_report(session: State<Session>, handle: Handle) -> Result<MediaValidationReport, String> {
+    session.annotate(handle, |game_list| validation::validate_media(game_list, &ValidationOptions::default())).map_err(|e| e.to_string())
+}
+
+#[tauri::command(async, rename_all = "snake_case")]
+fn preview_dat_metadata(session: State<Session>, handle: Handle, dat_path: &str, overwrite: bool) -> Result<DatPreview, String> {
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    game_listings::hash_games(&mut game_list).map_err(|e| e.to_string())?;
+    let dat = dat::load(&PathBuf::from(dat_path)).map_err(|e| e.to_string())?;
+    Ok(dat::preview(&game_list, &dat, overwrite))
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn preview_file_name_tags(session: State<Session>, handle: Handle, overwrite: bool) -> Result<FileNamePreview, String> {
+    session.read(handle, |game_list| file_names::preview(game_list, overwrite)).map_err(|e| e.to_string())
+}
+
+#[tauri::command(rename_all = "snake_case")]
+fn apply_metadata_changes(session: State<Session>, handle: Handle, changes: Vec<FieldChange>) -> Result<OpenGameList, String> {
+    session.update(handle, |game_list| metadata::apply_changes(game_list, &changes)).map_err(|e| e.to_string())?;
+    session.game_list(handle).map_err(|e| e.to_string())
+}
+
+// Metadata.xml is large, so it is read off the main thread, from a copy of the list so other
+// commands can use the list meanwhile
+#[tauri::command(async, rename_all = "snake_case")]
+fn preview_launchbox_metadata(session: State<Session>, handle: Handle, metadata_path: &str, platforms: Option<HashMap<String, String>>, overwrite: bool) -> Result<LaunchBoxPreview, String> {
+    let game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    let system = game_list.directory.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+    let platform = launchbox::platform_for(&system, &platforms.unwrap_or_default())
+        .ok_or_else(|| launchbox::Error::UnknownPlatform(system).to_string())?;
+    let index = launchbox::index(&PathBuf::from(metadata_path), &platform).map_err(|e| e.to_string())?;
+    Ok(launchbox::preview(&game_list, &index, overwrite))
+}
+
+#[tauri::command(async, rename_all = "snake_case")]
+fn preview_rdb_metadata(session: State<Session>, handle: Handle, database_dir: &str, databases: Option<HashMap<String, String>>, overwrite: bool) -> Result<RdbPreview, String> {
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    let system = game_list.directory.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+    let database = rdb::database_for(&system, &databases.unwrap_or_default())
+        .ok_or_else(|| rdb::Error::UnknownSystem(system).to_string())?;
+    let index = rdb::read(&PathBuf::from(database_dir).join(database)).map_err(|e| e.to_string())?;
+    game_listings::hash_games(&mut game_list).map_err(|e| e.to_string())?;
+    Ok(rdb::preview(&game_list, &index, overwrite))
+}
+
+#[tauri::command(async, rename_all = "snake_case")]
+fn preview_openvgdb_metadata(session: State<Session>, handle: Handle, database_path: &str, overwrite: bool) -> Result<OpenVgdbPreview, String> {
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    let database = OpenVgdb::open(&PathBuf::from(database_path)).map_err(|e| e.to_string())?;
+    game_listings::hash_games(&mut game_list).map_err(|e| e.to_string())?;
+    openvgdb::preview(&game_list, &database, overwrite).map_err(|e| e.to_string())
+}
+
+// Scraping makes network requests, so it runs off the main thread
+#[tauri::command(async, rename_all = "snake_case")]
+fn preview_screenscraper(session: State<Session>, handle: Handle, config: ScreenScraperConfig, game_ids: Vec<usize>, overwrite: bool) -> Result<ScrapePreview, String> {
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    game_listings::hash_games(&mut game_list).map_err(|e| e.to_string())?;
+    let scraper = ScreenScraper::new(config, UreqClient::default());
+    Ok(scraper::preview(&game_list, &scraper, &game_ids, overwrite))
+}
+
+// The media is downloaded for a copy of the list, only the fields of the downloads which succeeded
+// are then set on the session's list
+#[tauri::command(async, rename_all = "snake_case")]
+fn download_scraped_media(session: State<Session>, handle: Handle, downloads: Vec<MediaDownload>, options: Option<ImportOptions>) -> Result<(OpenGameList, Vec<(usize, String)>), String> {
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    let client = UreqClient::default();
+    let options = options.unwrap_or_default();
+    let mut downloaded = Vec::new();
+    let mut failed = Vec::new();
+    for download in &downloads {
+        match scraper::download_media(&client, &mut game_list, download, &options) {
+            Ok(path) => downloaded.push((download.game_id, download.media.kind.field(), path)),
+            Err(e) => failed.push((download.game_id, e.to_string())),
+        }
+    }
+
+    session.update(handle, |game_list| {
+        for (game_id, field, path) in downloaded {
+            if let Some(game) = game_list.get_mut(game_id) {
+                game.set_field(field, path);
+            }
+        }
+    }).map_err(|e| e.to_string())?;
+    Ok((session.game_list(handle).map_err(|e| e.to_string())?, failed))
+}
+
+#[tauri::command(rename_all = "snake_case")]
+async fn match_thumbnails(session: State<'_, Session>, handle: Handle, system_dir: &str, folders: Option<Vec<ThumbnailFolder>>, overwrite: bool) -> Result<Vec<ThumbnailMatch>, String> {
+    let game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    let folders = folders.unwrap_or_else(thumbnails::default_folders);
+    thumbnails::match_thumbnails(&game_list, &PathBuf::from(system_dir), &folders, overwrite).map_err(|e| e.to_string())
+}
+
+#[tauri::command(async, rename_all = "snake_case")]
+fn import_thumbnails(session: State<Session>, handle: Handle, matches: Vec<ThumbnailMatch>, options: Option<ImportOptions>) -> Result<(OpenGameList, Vec<(usize, String)>), String> {
+    let mut game_list = session.read(handle, GameList::clone).map_err(|e| e.to_string())?;
+    let options = options.unwrap_or_default();
+    let failed = thumbnails::import_thumbnails(&mut game_list, &matches, &options);
+
+    // only the fields the images were imported for are copied to the session's list
+    session.update(handle, |held| {
+        for found in &matches {
+            let path = game_list.get(found.game_id).and_then(|game| game.field_value(&found.field));
+            if let (Some(path), Some(game)) = (path, held.get_mut(found.game_id)) {
+                if game.field_value(&found.field) != Some(path) {
+                    game.set_field(&found.field, path.to_string());
+                }
+            }
+        }
+    }).map_err(|e| e.to_string())?;
+    Ok((session.game_list(handle).map_err(|e| e.to_string())?, failed))
+}
+
 fn main() {
     tauri::Builder::default()
-
-        .invoke_handler(tauri::generate_handler![greet, get_emulator_list, get_game_list, save_game_list])
+        .manage(Session::default())
+        .setup(|app| {
+            // ROM checksums are cached in the app's data directory rather than among the ROMs
+            if let Some(directory) = app.path_resolver().app_data_dir() {
+                checksums::set_cache_dir(directory.join("checksums"));
+            }
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![greet, get_emulator_list, get_open_emulator_list, get_game_list, reload_game_list, close_list, save_game_list, save_game, save_folder, merge_game_list, get_gamelist_backups, restore_gamelist_backup, create_disc_playlists, compute_game_hashes, get_orphaned_games, get_invalid_game_paths, prune_orphaned_games, relink_orphaned_game, get_duplicate_games, merge_duplicate_games, get_cross_system_duplicates, match_media_files, apply_media_links, import_media_image, update_fields, get_media_report, preview_dat_metadata, preview_file_name_tags, apply_metadata_changes, preview_screenscraper, download_scraped_media, preview_launchbox_metadata, preview_rdb_metadata, preview_openvgdb_metadata, match_thumbnails, import_thumbnails])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -4,4 +4,4 @@ import { writable, get } from "svelte/store";
 
 
 export const emulatorListStore: Writable<Emulator[]> = writable([]);
-export const gameListStore: Writable<Games> = writable({directory: "", emulator: "", games: []});
+export const gameListStore: Writable<Games> = writable({directory: "", emulator: "", games: [], folders: []});
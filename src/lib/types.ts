@@ -6,18 +6,58 @@ export interface FieldProps {
     format_error_message: string;
 }
 
+export interface RomHashes {
+    crc32: string;
+    md5: string;
+    sha1: string;
+    size: number;
+}
+
+export interface ArchiveMember {
+    name: string;
+    size: number;
+}
+
+export type ArchiveStatus = "SingleRom" | "NoRom" | "SeveralRoms" | "Unreadable";
+
+export interface ArchiveInfo {
+    status: ArchiveStatus;
+    roms: ArchiveMember[];
+}
+
 export interface Game {
-    id: string;
+    id: number;
     directory: string;
+    hashes?: RomHashes | null;
+    archive?: ArchiveInfo | null;
+    orphaned?: boolean;
+    fields: FieldProps[];
+}
+
+export interface Folder {
+    id: number;
     fields: FieldProps[];
 }
 
+export interface Fingerprint {
+    sha1: string;
+    size: number;
+    modified_secs: number;
+    modified_nanos: number;
+}
+
 export interface Games {
+    handle: number;
+    dirty: boolean;
+    fingerprint?: Fingerprint | null;
     emulator: string;
     directory: string;
     games: Game[];
+    folders: Folder[];
 }
 
+export type Collection = "games" | "folders";
+
 export interface Emulator {
     complete_percent: number;
     name: string;
@@ -28,5 +68,6 @@ export interface Emulator {
     rom_extensions: string[];
 }
 export interface Emulators {
+    handle: number;
     emulators: Emulator[];
 }